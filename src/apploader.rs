@@ -0,0 +1,162 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+use crate::bootstage;
+
+pub const HEADER_LENGTH : usize = 0x20;
+pub const DATE_LENGTH : usize = 0x10;
+
+// The disc loader copies everything after the header here and jumps to the entry point
+pub const LOAD_ADDR : u32 = 0x81200000;
+
+pub struct Apploader {
+    // Build date, "YYYY/MM/DD" padded with zeroes
+    pub date: [u8; DATE_LENGTH],
+
+    pub entry_point: u32,
+    pub size: u32,
+    pub trailer_size: u32,
+    pub unk_1c: u32,
+
+    // Code and trailer, size + trailer_size bytes
+    pub data: Vec<u8>,
+}
+
+fn invalid_apploader(message: &str) -> Error {
+    return Error::new(ErrorKind::InvalidData, format!("Invalid apploader: {}", message));
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    return u32::from_be_bytes([buffer[offset], buffer[offset + 1], buffer[offset + 2], buffer[offset + 3]]);
+}
+
+impl Apploader {
+    pub fn date_string(&self) -> String {
+        let end = self.date.iter().position(|&c| c == 0).unwrap_or(DATE_LENGTH);
+        return String::from_utf8_lossy(&self.date[..end]).to_string();
+    }
+
+    // Everything the loader copies to LOAD_ADDR
+    pub fn load_size(&self) -> u32 {
+        return self.size + self.trailer_size;
+    }
+
+    // Replaces the code with a raw image of the whole load size (as turn_elf_to_raw gives it).
+    // The trailer keeps its size and contents and goes at the end of the new image.
+    pub fn replace_code(&mut self, mut data: Vec<u8>, entry_point: u32) -> std::io::Result<()> {
+        let trailer = self.data[self.size as usize..].to_vec();
+        let size = data.len().checked_sub(trailer.len())
+                       .ok_or_else(|| invalid_apploader(&format!("image size {:#X} is smaller than the trailer ({:#X} bytes)", data.len(), trailer.len())))?;
+
+        if data[size..].iter().any(|&b| b != 0) {
+            return Err(invalid_apploader(&format!("code runs into the trailer at {:#010X}", LOAD_ADDR + size as u32)));
+        }
+        data[size..].copy_from_slice(&trailer);
+
+        self.data        = data;
+        self.size        = size as u32;
+        self.entry_point = entry_point;
+
+        return Ok(());
+    }
+}
+
+pub fn parse(file_data: &[u8]) -> std::io::Result<Apploader> {
+    if file_data.len() < HEADER_LENGTH {
+        return Err(invalid_apploader("file is smaller than the header"));
+    }
+
+    let mut date = [0u8; DATE_LENGTH];
+    date.copy_from_slice(&file_data[0..DATE_LENGTH]);
+
+    let size = read_u32(file_data, 0x14);
+    let trailer_size = read_u32(file_data, 0x18);
+    let load_size = size.checked_add(trailer_size).ok_or_else(|| invalid_apploader("size and trailer size overflow"))? as usize;
+
+    let data = file_data.get(HEADER_LENGTH..HEADER_LENGTH + load_size)
+                        .ok_or_else(|| invalid_apploader(&format!("{:#X} bytes of code and trailer exceed the file size {:#X}", load_size, file_data.len())))?;
+
+    return Ok(Apploader {
+        date,
+
+        entry_point:    read_u32(file_data, 0x10),
+        size,
+        trailer_size,
+        unk_1c:         read_u32(file_data, 0x1C),
+
+        data:           data.to_vec(),
+    });
+}
+
+#[allow(dead_code, clippy::ptr_arg)]
+pub fn open_file(file_name: &String) -> std::io::Result<Apploader> {
+    let file_data = bootstage::map_file(file_name)?;
+    return parse(&file_data);
+}
+
+#[allow(dead_code)]
+pub fn create_file(file_name: &String, apploader: &Apploader) -> std::io::Result<()> {
+    if apploader.data.len() != apploader.load_size() as usize {
+        return Err(invalid_apploader(&format!("has {:#X} bytes of code and trailer, the header says {:#X}", apploader.data.len(), apploader.load_size())));
+    }
+
+    let mut file_data = Vec::with_capacity(HEADER_LENGTH + apploader.data.len());
+    file_data.extend_from_slice(&apploader.date);
+    file_data.extend_from_slice(&apploader.entry_point.to_be_bytes());
+    file_data.extend_from_slice(&apploader.size.to_be_bytes());
+    file_data.extend_from_slice(&apploader.trailer_size.to_be_bytes());
+    file_data.extend_from_slice(&apploader.unk_1c.to_be_bytes());
+    file_data.extend_from_slice(&apploader.data);
+
+    return fs::write(file_name, file_data);
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::gcipl::tests::temp_file;
+
+    // Header, 0x40 bytes of code and a 0x20 byte trailer
+    pub fn synthetic_apploader() -> Vec<u8> {
+        let mut file_data = vec![0u8; HEADER_LENGTH + 0x60];
+        file_data[..10].copy_from_slice(b"2008/05/21");
+        file_data[0x10..0x14].copy_from_slice(&(LOAD_ADDR + 0x10).to_be_bytes());
+        file_data[0x14..0x18].copy_from_slice(&0x40u32.to_be_bytes());
+        file_data[0x18..0x1C].copy_from_slice(&0x20u32.to_be_bytes());
+        file_data[HEADER_LENGTH..HEADER_LENGTH + 0x40].fill(0x60);
+        for (i, byte) in file_data[HEADER_LENGTH + 0x40..].iter_mut().enumerate() {
+            *byte = 0x80 | i as u8;
+        }
+        return file_data;
+    }
+
+    #[test]
+    fn replace_code_keeps_trailer() {
+        let file_data = synthetic_apploader();
+        let mut loader = parse(&file_data).unwrap();
+        assert_eq!(loader.date_string(), "2008/05/21");
+
+        // A bigger image, with the code not reaching the trailer
+        let mut data = vec![0u8; 0x80];
+        data[..0x50].fill(0x38);
+        loader.replace_code(data, LOAD_ADDR + 0x20).unwrap();
+
+        let file_name = temp_file("apploader.img");
+        create_file(&file_name, &loader).unwrap();
+        let new_data = fs::read(&file_name).unwrap();
+        fs::remove_file(&file_name).ok();
+
+        let reparsed = parse(&new_data).unwrap();
+        assert_eq!(reparsed.entry_point, LOAD_ADDR + 0x20);
+        assert_eq!((reparsed.size, reparsed.trailer_size), (0x60, 0x20));
+        assert_eq!(reparsed.data[..0x50], [0x38; 0x50]);
+        assert_eq!(reparsed.data[0x60..], file_data[HEADER_LENGTH + 0x40..]);
+    }
+
+    #[test]
+    fn replace_code_into_trailer() {
+        let mut loader = parse(&synthetic_apploader()).unwrap();
+        assert!(loader.replace_code(vec![0x38; 0x60], LOAD_ADDR).is_err());
+        assert!(loader.replace_code(vec![0; 0x10], LOAD_ADDR).is_err());
+    }
+}
@@ -0,0 +1,817 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use crate::bootstage::{BSImageRef, BSS_COUNT, DATA_COUNT, TEXT_COUNT};
+use crate::disasm::{self, CodeBlock, Line};
+use crate::elf::{R_PPC_ADDR16_HA, R_PPC_ADDR16_HI, R_PPC_ADDR16_LO, R_PPC_ADDR32, R_PPC_EMB_SDA21, R_PPC_REL14, R_PPC_REL24};
+use crate::objdiff;
+use crate::ppc::{Instruction, Operand};
+use crate::project;
+use crate::splits::{SplitRange, Splits, Unit};
+use crate::symbols::{SymbolKind, SymbolMap};
+
+// Registers the small data bases live in, and the symbols the linker script defines for them
+const SDA_REGISTERS: [(u32, &str, [&str; 2]); 2] = [(13, "_SDA_BASE_", [".sdata", ".sbss"]), (2, "_SDA2_BASE_", [".sdata2", ".sbss2"])];
+
+// One unit's part of one section
+struct Piece<'a> {
+    unit: usize,
+    section: String,
+    // code, data, rodata or bss
+    kind: &'static str,
+    start: u32,
+    end: u32,
+    // None for BSS
+    data: Option<&'a [u8]>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum RelocKind {
+    // lis rD, sym@ha / sym@h
+    Ha,
+    H,
+    // addi or ori: sym@l, loads and stores: sym@l(rA)
+    Lo,
+    LoOffset(u32),
+    // addi rD, r13, sym@sda21 and lwz rD, sym@sda21(r13)
+    Sda,
+    SdaOffset(u32),
+    Branch,
+}
+
+// An instruction operand that becomes a symbol reference
+struct Reloc {
+    line: usize,
+    operand: usize,
+    kind: RelocKind,
+    target: u32,
+    // Set for the lis/addi pair that loads a small data base
+    base: Option<&'static str>,
+}
+
+pub struct Label {
+    pub name: String,
+    pub global: bool,
+}
+
+// A symbol reference the way a relocatable object records it
+pub struct ObjectReloc {
+    // Address of the relocated field
+    pub addr: u32,
+    // R_PPC_ type
+    pub kind: u32,
+    pub symbol: String,
+    // Address the symbol stands for, None for the small data bases and targets outside BS2
+    pub target: Option<u32>,
+}
+
+// What split works out about the whole link: every unit's pieces (the auto_ ones included), the disassembly
+// of the code pieces, the references found in them and a label for every referenced address
+pub struct Link<'a> {
+    units: Vec<Unit>,
+    split_count: usize,
+    pieces: Vec<Piece<'a>>,
+    code: Vec<Option<Vec<Line>>>,
+    relocs: Vec<Vec<Reloc>>,
+    pointers: Vec<HashMap<u32, u32>>,
+    labels: BTreeMap<u32, Label>,
+    external: BTreeMap<u32, String>,
+    // Symbol functions, call targets and the entry point
+    functions: HashSet<u32>,
+    bases: [Option<u32>; 2],
+    entry: String,
+}
+
+// What split wrote, for the summary
+pub struct SplitResult {
+    pub units: usize,
+    pub auto_units: usize,
+}
+
+// The units of the splits, plus an auto_ unit for every stretch of a section none of them covers
+fn all_units(image: &BSImageRef, splits: &Splits) -> std::io::Result<(Vec<Unit>, usize)> {
+    let sections = project::sections(image);
+    let mut units: Vec<Unit> = Vec::new();
+    for unit in &splits.units {
+        let mut ranges = Vec::new();
+        for range in &unit.ranges {
+            let inside = sections.iter().any(|s| s.name == range.section && range.start >= s.addr && range.end <= s.addr + s.len && range.start <= range.end);
+            if !inside {
+                return Err(Error::new(ErrorKind::InvalidData, format!("{} {} {:#010X}-{:#010X} isn't inside the image's {}",
+                                                                     unit.name, range.section, range.start, range.end, range.section)));
+            }
+            if range.start != range.end {
+                ranges.push(SplitRange { section: range.section.clone(), start: range.start, end: range.end });
+            }
+        }
+        units.push(Unit { name: unit.name.clone(), ranges });
+    }
+
+    let split_count = units.len();
+    for section in &sections {
+        let mut covered: Vec<(u32, u32, &str)> = units.iter()
+            .flat_map(|u| u.ranges.iter().filter(|r| r.section == section.name).map(move |r| (r.start, r.end, u.name.as_str())))
+            .collect();
+        covered.sort();
+        let mut addr = section.addr;
+        let mut gaps = Vec::new();
+        for (start, end, name) in covered {
+            if start < addr {
+                return Err(Error::new(ErrorKind::InvalidData, format!("{} {} {:#010X}-{:#010X} overlaps another unit", name, section.name, start, end)));
+            }
+            if start > addr {
+                gaps.push((addr, start));
+            }
+            addr = end;
+        }
+        if addr < section.addr + section.len {
+            gaps.push((addr, section.addr + section.len));
+        }
+        for (start, end) in gaps {
+            let name = format!("auto_{:08X}_{}", start, section.name.trim_start_matches('.'));
+            units.push(Unit { name, ranges: vec![SplitRange { section: section.name.clone(), start, end }] });
+        }
+    }
+    return Ok((units, split_count));
+}
+
+fn pieces<'a>(image: &BSImageRef<'a>, units: &[Unit]) -> std::io::Result<Vec<Piece<'a>>> {
+    let sections = project::sections(image);
+    let mut pieces = Vec::new();
+    for (index, unit) in units.iter().enumerate() {
+        for range in &unit.ranges {
+            let kind = sections.iter().find(|s| s.name == range.section).map(|s| s.kind).unwrap_or("data");
+            if kind == "code" && ((range.start | range.end) & 3 != 0) {
+                return Err(Error::new(ErrorKind::InvalidData, format!("{} {} {:#010X}-{:#010X} doesn't start and end on an instruction",
+                                                                     unit.name, range.section, range.start, range.end)));
+            }
+            let data = if kind == "bss" { None } else { image.section_data(range.start, range.end - range.start) };
+            if kind != "bss" && data.is_none() {
+                return Err(Error::new(ErrorKind::InvalidData, format!("{} {} {:#010X}-{:#010X} is outside BS2", unit.name, range.section, range.start, range.end)));
+            }
+            pieces.push(Piece { unit: index, section: range.section.clone(), kind, start: range.start, end: range.end, data });
+        }
+    }
+    return Ok(pieces);
+}
+
+// The piece an address can be referenced in. Loaded sections win over the BSS ones (which may
+// overlap each other), and code can only be pointed at on an instruction.
+fn piece_at(pieces: &[Piece], addr: u32) -> Option<usize> {
+    let containing = |p: &&Piece| addr >= p.start && addr < p.end;
+    let index = match pieces.iter().position(|p| p.kind != "bss" && containing(&p)) {
+        Some(index) => index,
+        None        => pieces.iter().enumerate().filter(|(_, p)| p.kind == "bss" && containing(p)).min_by_key(|(_, p)| p.end - p.start)?.0,
+    };
+    if pieces[index].kind == "code" && addr & 3 != 0 {
+        return None;
+    }
+    return Some(index);
+}
+
+// r13 and r2 the way __start sets them up: lis rN, base@ha followed by addi (or ori) rN, rN, base@l
+fn sda_bases(code: &[&Vec<Line>]) -> [Option<u32>; 2] {
+    let mut bases = [None; 2];
+    for lines in code {
+        let mut hi: [Option<u32>; 32] = [None; 32];
+        for line in lines.iter().filter(|l| l.instruction.is_some()) {
+            let (opcode, rd, ra) = (line.word >> 26, (line.word >> 21) & 31, (line.word >> 16) & 31);
+            if opcode == 15 && ra == 0 {
+                hi[rd as usize] = Some(line.word & 0xFFFF);
+                continue;
+            }
+            for (i, (register, _, _)) in SDA_REGISTERS.iter().enumerate() {
+                let Some(high) = hi[*register as usize] else {
+                    continue;
+                };
+                if rd == *register && ra == *register && bases[i].is_none() {
+                    if opcode == 14 {
+                        bases[i] = Some((high << 16).wrapping_add((line.word & 0xFFFF) as i16 as u32));
+                    }
+                    else if opcode == 24 {
+                        bases[i] = Some(high << 16 | (line.word & 0xFFFF));
+                    }
+                }
+            }
+            if let Some(register) = written_gpr(line.instruction.as_ref().unwrap()) {
+                hi[register as usize] = None;
+            }
+        }
+    }
+    return bases;
+}
+
+// The GPR an instruction overwrites, for the ones that name it first
+fn written_gpr(instruction: &Instruction) -> Option<u32> {
+    const READ_ONLY: [&str; 8] = ["st", "cmp", "tw", "dcb", "icbi", "mt", "tlbie", "ecowx"];
+    if READ_ONLY.iter().any(|prefix| instruction.mnemonic.starts_with(prefix)) {
+        return None;
+    }
+    return match instruction.operands.first() {
+        Some(Operand::Gpr(register)) => Some(*register),
+        _                            => None,
+    };
+}
+
+// Base register an update form writes back to
+fn updated_gpr(instruction: &Instruction) -> Option<u32> {
+    if instruction.mnemonic.ends_with('u') {
+        if let Some(Operand::Offset(_, register)) = instruction.operands.get(1) {
+            return Some(*register);
+        }
+    }
+    if instruction.mnemonic.ends_with("ux") {
+        if let Some(Operand::Gpr(register)) = instruction.operands.get(1) {
+            return Some(*register);
+        }
+    }
+    return None;
+}
+
+// Relocations of one code piece. lis is paired with the addi, ori, load or store that uses the
+// register next, reading straight down and forgetting everything at returns and jumps.
+fn code_relocations(lines: &[Line], pieces: &[Piece], bases: &[Option<u32>; 2], function_starts: &HashSet<u32>) -> Vec<Reloc> {
+    let mut relocs = Vec::new();
+    let mut hi: [Option<usize>; 32] = [None; 32];
+    // lis line -> first partner's target, whether it's an ori, and the base it loads
+    let mut partners: HashMap<usize, (u32, bool, Option<&'static str>)> = HashMap::new();
+    let mut lows: Vec<(usize, usize, RelocKind, u32, usize)> = Vec::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        if function_starts.contains(&line.addr) {
+            hi = [None; 32];
+        }
+        let Some(instruction) = &line.instruction else {
+            continue;
+        };
+        let word = line.word;
+        let (opcode, rd, ra) = (word >> 26, (word >> 21) & 31, (word >> 16) & 31);
+        let simm = (word & 0xFFFF) as i16 as u32;
+
+        if let Some((operand, Operand::Target(target))) = instruction.operands.iter().enumerate().find(|(_, o)| matches!(o, Operand::Target(_))) {
+            if word & 2 == 0 {
+                relocs.push(Reloc { line: i, operand, kind: RelocKind::Branch, target: *target, base: None });
+            }
+        }
+        if opcode == 15 && ra == 0 {
+            hi[rd as usize] = Some(i);
+            continue;
+        }
+
+        let memory = (32..=55).contains(&opcode) && ra != 0;
+        let (register, lo) = match opcode {
+            14 if ra != 0 => (ra, Some((2, RelocKind::Lo, false))),
+            24            => (rd, Some((2, RelocKind::Lo, true))),
+            _ if memory   => (ra, Some((1, RelocKind::LoOffset(ra), false))),
+            _             => (0, None),
+        };
+        if let Some((operand, kind, ori)) = lo {
+            if let Some(lis) = hi[register as usize] {
+                let high = (lines[lis].word & 0xFFFF) << 16;
+                let target = if ori { high | (word & 0xFFFF) } else { high.wrapping_add(simm) };
+                // The pair that sets up r13 or r2 loads the base itself
+                let base = SDA_REGISTERS.iter().enumerate()
+                                        .find(|(n, (r, _, _))| rd == *r && ra == *r && bases[*n] == Some(target))
+                                        .map(|(_, (_, name, _))| *name);
+                partners.entry(lis).or_insert((target, ori, base));
+                lows.push((i, operand, kind, target, lis));
+            }
+            else if opcode != 24 {
+                for (n, (sda_register, _, sections)) in SDA_REGISTERS.iter().enumerate() {
+                    let Some(sda_base) = bases[n] else {
+                        continue;
+                    };
+                    if ra != *sda_register {
+                        continue;
+                    }
+                    let target = sda_base.wrapping_add(simm);
+                    if piece_at(pieces, target).is_some_and(|p| sections.contains(&pieces[p].section.as_str())) {
+                        let kind = if memory { RelocKind::SdaOffset(ra) } else { RelocKind::Sda };
+                        relocs.push(Reloc { line: i, operand, kind, target, base: None });
+                    }
+                }
+            }
+        }
+
+        if let Some(register) = written_gpr(instruction) {
+            hi[register as usize] = None;
+        }
+        if let Some(register) = updated_gpr(instruction) {
+            hi[register as usize] = None;
+        }
+        if matches!(instruction.mnemonic.as_str(), "blr" | "rfi" | "b" | "bctr") {
+            hi = [None; 32];
+        }
+    }
+
+    // A pair only becomes symbolic if the lis' first partner points into the link
+    let linked = |target: u32, base: Option<&'static str>| base.is_some() || piece_at(pieces, target).is_some();
+    for (lis, (target, ori, base)) in &partners {
+        if linked(*target, *base) {
+            let kind = if *ori { RelocKind::H } else { RelocKind::Ha };
+            relocs.push(Reloc { line: *lis, operand: 1, kind, target: *target, base: *base });
+        }
+    }
+    for (line, operand, kind, target, lis) in lows {
+        let (lis_target, _, lis_base) = partners[&lis];
+        let base = if lis_base.is_some() && target == lis_target { lis_base } else { None };
+        if linked(lis_target, lis_base) && linked(target, base) {
+            relocs.push(Reloc { line, operand, kind, target, base });
+        }
+    }
+    relocs.sort_by_key(|r| (r.line, r.operand));
+    return relocs;
+}
+
+// Words in data that point into the link
+fn data_pointers(piece: &Piece, pieces: &[Piece]) -> Vec<(u32, u32)> {
+    let Some(data) = piece.data else {
+        return Vec::new();
+    };
+    let mut pointers = Vec::new();
+    let mut addr = (piece.start + 3) & !3;
+    while addr + 4 <= piece.end {
+        let offset = (addr - piece.start) as usize;
+        let value = u32::from_be_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]);
+        if piece_at(pieces, value).is_some() {
+            pointers.push((addr, value));
+        }
+        addr += 4;
+    }
+    return pointers;
+}
+
+// Names the assembler and linker take as they are, anything else in quotes
+fn symbol_text(name: &str) -> String {
+    let plain = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$')
+                && !name.starts_with(|c: char| c.is_ascii_digit());
+    return if plain { name.to_string() } else { format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\"")) };
+}
+
+fn section_directive(piece: &Piece) -> String {
+    let flags = match piece.kind {
+        "code"   => "\"ax\"",
+        "rodata" => "\"a\"",
+        "bss"    => "\"wa\", @nobits",
+        _        => "\"wa\"",
+    };
+    return format!(".section {}, {}\n", piece.section, flags);
+}
+
+fn label_lines(label: &Label) -> String {
+    let mut text = String::from("\n");
+    if label.global {
+        text += &format!(".global {}\n", symbol_text(&label.name));
+    }
+    return text + &format!("{}:\n", symbol_text(&label.name));
+}
+
+// placed are the labels that go inside this piece, labels all of them
+fn code_text(piece: &Piece, lines: &mut [Line], relocs: &[Reloc], pointers: &HashMap<u32, u32>, placed: &BTreeMap<u32, &Label>, labels: &BTreeMap<u32, Label>, external: &BTreeMap<u32, String>) -> String {
+    let name_of = |reloc: &Reloc| -> String {
+        if let Some(base) = reloc.base {
+            return base.to_string();
+        }
+        let name = labels.get(&reloc.target).map(|l| &l.name).or_else(|| external.get(&reloc.target)).unwrap();
+        return symbol_text(name);
+    };
+    for reloc in relocs {
+        let name = name_of(reloc);
+        let text = match reloc.kind {
+            RelocKind::Ha                  => format!("{}@ha", name),
+            RelocKind::H                   => format!("{}@h", name),
+            RelocKind::Lo                  => format!("{}@l", name),
+            RelocKind::LoOffset(register)  => format!("{}@l(r{})", name, register),
+            RelocKind::Sda                 => format!("{}@sda21", name),
+            RelocKind::SdaOffset(register) => format!("{}@sda21(r{})", name, register),
+            RelocKind::Branch              => name,
+        };
+        if let Some(instruction) = lines[reloc.line].instruction.as_mut() {
+            instruction.operands[reloc.operand] = Operand::Text(text);
+        }
+    }
+
+    let mut text = section_directive(piece);
+    for line in lines.iter() {
+        if let Some(label) = placed.get(&line.addr) {
+            text += &label_lines(label);
+        }
+        let instruction = match (&line.instruction, pointers.get(&line.addr).and_then(|target| labels.get(target))) {
+            (Some(instruction), _) => instruction.text(&|_| None),
+            (None, Some(label))    => format!(".4byte {}", symbol_text(&label.name)),
+            (None, None)           => format!(".4byte 0x{:08X}", line.word),
+        };
+        text += &disasm::asm_line(line.addr, line.word, &instruction);
+    }
+    return text;
+}
+
+fn data_text(piece: &Piece, pointers: &HashMap<u32, u32>, placed: &BTreeMap<u32, &Label>, labels: &BTreeMap<u32, Label>) -> String {
+    let mut text = section_directive(piece);
+    let next_label = |addr: u32| placed.range(addr + 1..piece.end).next().map(|(a, _)| *a).unwrap_or(piece.end);
+    let mut addr = piece.start;
+    while addr < piece.end {
+        if let Some(label) = placed.get(&addr) {
+            text += &label_lines(label);
+        }
+        let until = next_label(addr);
+        let Some(data) = piece.data else {
+            text += &format!("\t.skip 0x{:X}\n", until - addr);
+            addr = until;
+            continue;
+        };
+        while addr < until {
+            let offset = (addr - piece.start) as usize;
+            if addr & 3 == 0 && addr + 4 <= until {
+                text += &match pointers.get(&addr).and_then(|target| labels.get(target)) {
+                    Some(label) => format!("\t.4byte {}\n", symbol_text(&label.name)),
+                    None        => format!("\t.4byte 0x{:08X}\n", u32::from_be_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])),
+                };
+                addr += 4;
+                continue;
+            }
+            // Up to the next word boundary or label
+            let stop = ((addr + 4) & !3).min(until);
+            let bytes: Vec<String> = data[offset..(stop - piece.start) as usize].iter().map(|b| format!("0x{:02X}", b)).collect();
+            text += &format!("\t.byte {}\n", bytes.join(", "));
+            addr = stop;
+        }
+    }
+    return text;
+}
+
+// GNU ld script placing every section at its original address, with the units in address order
+fn linker_script(image: &BSImageRef, units: &[Unit], pieces: &[Piece], entry: &str, bases: &[Option<u32>; 2], external: &BTreeMap<u32, String>) -> String {
+    let object = |unit: usize| format!("asm/{}.o", objdiff::unit_stem(&units[unit]));
+    let mut text = String::from("/* Assemble every asm/<unit>.s with powerpc-eabi-as -mgekko to asm/<unit>.o next to it, then link from this directory with\n");
+    text += " *   powerpc-eabi-ld -T ldscript.ld -o bs2.elf\n";
+    text += " * Every section ends up at its original address. */\n";
+    text += "OUTPUT_FORMAT(\"elf32-powerpc\")\n";
+    text += "OUTPUT_ARCH(powerpc:common)\n";
+    text += &format!("ENTRY({})\n\n", symbol_text(entry));
+
+    let objects: Vec<String> = (0..units.len()).filter(|u| pieces.iter().any(|p| p.unit == *u)).map(object).collect();
+    text += &format!("INPUT(\n{})\n\n", objects.iter().map(|o| format!("    {}\n", o)).collect::<String>());
+
+    for (n, (_, name, _)) in SDA_REGISTERS.iter().enumerate() {
+        if let Some(base) = bases[n] {
+            text += &format!("{} = 0x{:08X};\n", name, base);
+        }
+    }
+    if !external.is_empty() {
+        text += "\n/* Branch targets outside BS2 */\n";
+        for (addr, name) in external {
+            text += &format!("{} = 0x{:08X};\n", symbol_text(name), addr);
+        }
+    }
+
+    text += "\nSECTIONS\n{\n";
+    let mut sections = project::sections(image);
+    sections.sort_by_key(|s| s.addr);
+    for section in &sections {
+        let mut inside: Vec<&Piece> = pieces.iter().filter(|p| p.section == section.name).collect();
+        inside.sort_by_key(|p| p.start);
+        let noload = if section.kind == "bss" { " (NOLOAD)" } else { "" };
+        text += &format!("    {} 0x{:08X}{} :\n    {{\n", section.name, section.addr, noload);
+        for piece in inside {
+            text += &format!("        {}({})\n", object(piece.unit), section.name);
+        }
+        text += "    }\n";
+    }
+    text += "}\n";
+    return text;
+}
+
+// Disassembles the code of every unit and finds its references: branches, lis/addi pairs, small data
+// accesses and pointers in data that land inside BS2. Everything referenced gets a label.
+pub fn analyse<'a>(image: &BSImageRef<'a>, splits: &Splits, symbols: Option<&SymbolMap>) -> std::io::Result<Link<'a>> {
+    let (units, split_count) = all_units(image, splits)?;
+    let pieces = pieces(image, &units)?;
+    let symbol_list = symbols.map(|s| s.symbols.as_slice()).unwrap_or(&[]);
+
+    let mut code: Vec<Option<Vec<Line>>> = pieces.iter().map(|p| {
+        if p.kind != "code" {
+            return None;
+        }
+        return Some(disasm::disassemble(&CodeBlock { name: p.section.clone(), addr: p.start, data: p.data.unwrap() }));
+    }).collect();
+    // Data in code sections: the ROM copy and BSS tables the linker puts into .init, and the sized objects
+    // the symbols place there. Those words stay .4byte, pointers among them become symbol references.
+    let mut tables = vec![(image.bs2_addr + image.rom_table_off, (TEXT_COUNT + DATA_COUNT + 1) as u32 * 0x0C),
+                          (image.bs2_addr + image.bss_table_off, (BSS_COUNT + 1) as u32 * 0x08)];
+    tables.extend(symbol_list.iter().filter(|s| s.kind == SymbolKind::Object && s.size != 0).map(|s| (s.addr, s.size)));
+    let in_table = |addr: u32| tables.iter().any(|(start, size)| addr >= *start && addr - start < *size);
+    for lines in code.iter_mut().flatten() {
+        for line in lines.iter_mut().filter(|l| in_table(l.addr)) {
+            line.instruction = None;
+        }
+    }
+    let bases = sda_bases(&code.iter().flatten().collect::<Vec<&Vec<Line>>>());
+    let function_starts: HashSet<u32> = symbol_list.iter().filter(|s| s.kind == SymbolKind::Function).map(|s| s.addr).collect();
+
+    let relocs: Vec<Vec<Reloc>> = code.iter().map(|lines| match lines {
+        Some(lines) => code_relocations(lines, &pieces, &bases, &function_starts),
+        None        => Vec::new(),
+    }).collect();
+    let pointers: Vec<HashMap<u32, u32>> = pieces.iter().zip(&code).map(|(piece, lines)| match lines {
+        Some(lines) => lines.iter().filter(|l| in_table(l.addr) && piece_at(&pieces, l.word).is_some()).map(|l| (l.addr, l.word)).collect(),
+        None        => data_pointers(piece, &pieces).into_iter().collect(),
+    }).collect();
+
+    // Every referenced address and the units it's referenced from. Calls name their target fn_.
+    let mut references: BTreeMap<u32, (HashSet<usize>, bool)> = BTreeMap::new();
+    let mut external: BTreeMap<u32, String> = BTreeMap::new();
+    for (index, piece) in pieces.iter().enumerate() {
+        for reloc in relocs[index].iter().filter(|r| r.base.is_none()) {
+            if piece_at(&pieces, reloc.target).is_none() {
+                if !external.contains_key(&reloc.target) {
+                    let named = symbol_list.iter().find(|s| s.addr == reloc.target && !external.values().any(|n| *n == s.name));
+                    external.insert(reloc.target, named.map(|s| s.name.clone()).unwrap_or(format!("lbl_{:08X}", reloc.target)));
+                }
+                continue;
+            }
+            let call = reloc.kind == RelocKind::Branch && code[index].as_ref().unwrap()[reloc.line].word & 1 != 0;
+            let entry = references.entry(reloc.target).or_default();
+            entry.0.insert(piece.unit);
+            entry.1 |= call;
+        }
+        for target in pointers[index].values() {
+            references.entry(*target).or_default().0.insert(piece.unit);
+        }
+    }
+
+    // Labels: the symbols inside the link (a typed symbol wins over a label at the same address),
+    // then a name for everything referenced that has none, and the entry point
+    let mut labels: BTreeMap<u32, (Label, bool)> = BTreeMap::new();
+    for symbol in symbol_list.iter().filter(|s| piece_at(&pieces, s.addr).is_some()) {
+        let typed = symbol.kind != SymbolKind::Unknown;
+        if !matches!(labels.get(&symbol.addr), Some((_, true))) {
+            labels.insert(symbol.addr, (Label { name: symbol.name.clone(), global: !symbol.local }, typed));
+        }
+    }
+    for (addr, (_, call)) in &references {
+        labels.entry(*addr).or_insert_with(|| {
+            let prefix = if *call { "fn" } else { "lbl" };
+            (Label { name: format!("{}_{:08X}", prefix, addr), global: true }, false)
+        });
+    }
+    if piece_at(&pieces, image.bs2_entry).is_some() {
+        let entry = labels.entry(image.bs2_entry).or_insert((Label { name: "__start".to_string(), global: true }, true));
+        entry.0.global = true;
+    }
+    let mut labels: BTreeMap<u32, Label> = labels.into_iter().map(|(addr, (label, _))| (addr, label)).collect();
+
+    // Names have to be unique across the link: later duplicates fall back to lbl_, and locals
+    // referenced from another unit are made global
+    let mut seen: HashSet<String> = external.values().cloned().collect();
+    for (addr, label) in labels.iter_mut() {
+        if !seen.insert(label.name.clone()) {
+            label.name = format!("lbl_{:08X}", addr);
+            label.global = true;
+        }
+        let owner = piece_at(&pieces, *addr).map(|p| pieces[p].unit);
+        if references.get(addr).is_some_and(|(units, _)| units.iter().any(|u| Some(*u) != owner)) {
+            label.global = true;
+        }
+    }
+    let entry = labels.get(&image.bs2_entry).map(|l| l.name.clone()).unwrap_or("__start".to_string());
+
+    let mut functions = function_starts;
+    functions.extend(references.iter().filter(|(_, (_, call))| *call).map(|(addr, _)| *addr));
+    functions.insert(image.bs2_entry);
+
+    return Ok(Link { units, split_count, pieces, code, relocs, pointers, labels, external, functions, bases, entry });
+}
+
+impl Link<'_> {
+    pub fn label(&self, addr: u32) -> Option<&Label> {
+        return self.labels.get(&addr);
+    }
+
+    pub fn is_function(&self, addr: u32) -> bool {
+        return self.functions.contains(&addr);
+    }
+
+    // The labels that go inside a section's start..end, for a stretch one unit has
+    pub fn labels_in(&self, section: &str, start: u32, end: u32) -> Vec<(u32, &Label)> {
+        let mut placed = Vec::new();
+        for (index, piece) in self.pieces.iter().enumerate().filter(|(_, p)| p.section == section && p.start >= start && p.end <= end) {
+            placed.extend(self.labels.range(piece.start..piece.end)
+                              .filter(|(addr, _)| piece_at(&self.pieces, **addr) == Some(index))
+                              .map(|(addr, label)| (*addr, label)));
+        }
+        return placed;
+    }
+
+    fn reloc_symbol(&self, reloc: &Reloc) -> (String, Option<u32>) {
+        if let Some(base) = reloc.base {
+            return (base.to_string(), None);
+        }
+        return match self.labels.get(&reloc.target) {
+            Some(label) => (label.name.clone(), Some(reloc.target)),
+            None        => (self.external[&reloc.target].clone(), None),
+        };
+    }
+
+    // Relocations for a section's start..end, for a stretch one unit has. Branches that stay inside
+    // the function they're in are left as they are, like dtk does.
+    pub fn relocations(&self, section: &str, start: u32, end: u32) -> Vec<ObjectReloc> {
+        let mut found = Vec::new();
+        for (index, _) in self.pieces.iter().enumerate().filter(|(_, p)| p.section == section && p.start >= start && p.end <= end) {
+            let lines = self.code[index].as_deref().unwrap_or(&[]);
+            for reloc in &self.relocs[index] {
+                let line = &lines[reloc.line];
+                let (kind, field) = match reloc.kind {
+                    RelocKind::Ha                              => (R_PPC_ADDR16_HA, 2),
+                    RelocKind::H                               => (R_PPC_ADDR16_HI, 2),
+                    RelocKind::Lo | RelocKind::LoOffset(_)     => (R_PPC_ADDR16_LO, 2),
+                    RelocKind::Sda | RelocKind::SdaOffset(_)   => (R_PPC_EMB_SDA21, 0),
+                    RelocKind::Branch if line.word >> 26 == 18 => (R_PPC_REL24, 0),
+                    RelocKind::Branch                          => (R_PPC_REL14, 0),
+                };
+                if reloc.kind == RelocKind::Branch && piece_at(&self.pieces, reloc.target) == Some(index) && !self.functions.contains(&reloc.target) {
+                    continue;
+                }
+                let (symbol, target) = self.reloc_symbol(reloc);
+                found.push(ObjectReloc { addr: line.addr + field, kind, symbol, target });
+            }
+            for (addr, target) in &self.pointers[index] {
+                found.push(ObjectReloc { addr: *addr, kind: R_PPC_ADDR32, symbol: self.labels[target].name.clone(), target: Some(*target) });
+            }
+        }
+        found.sort_by_key(|r| r.addr);
+        return found;
+    }
+
+    // Every address an object relocation points at, from any unit
+    pub fn referenced(&self) -> HashSet<u32> {
+        return self.pieces.iter()
+                   .flat_map(|p| self.relocations(&p.section, p.start, p.end))
+                   .filter_map(|r| r.target)
+                   .collect();
+    }
+}
+
+// asm/<unit>.s for every unit of the splits (and auto_ units for the rest of BS2) plus ldscript.ld in out_dir.
+// The units are written for powerpc-eabi-as -mgekko and powerpc-eabi-ld. The references analyse finds are
+// written as symbol references, so the units should link back to the original bytes and keep working when
+// code moves. Nothing here assembles them though: the tests only check that the lines stand for the original words.
+pub fn write_split(out_dir: &str, image: &BSImageRef, splits: &Splits, symbols: Option<&SymbolMap>) -> std::io::Result<SplitResult> {
+    let mut link = analyse(image, splits, symbols)?;
+
+    let root = Path::new(out_dir);
+    for (unit_index, unit) in link.units.iter().enumerate() {
+        let mut text = format!("# {}\n", unit.name);
+        for (index, piece) in link.pieces.iter().enumerate().filter(|(_, p)| p.unit == unit_index) {
+            let placed: BTreeMap<u32, &Label> = link.labels.range(piece.start..piece.end)
+                                                           .filter(|(addr, _)| piece_at(&link.pieces, **addr) == Some(index))
+                                                           .map(|(addr, label)| (*addr, label))
+                                                           .collect();
+            text += "\n";
+            text += &match link.code[index].as_mut() {
+                Some(lines) => code_text(piece, lines, &link.relocs[index], &link.pointers[index], &placed, &link.labels, &link.external),
+                None        => data_text(piece, &link.pointers[index], &placed, &link.labels),
+            };
+        }
+        let file = root.join("asm").join(format!("{}.s", objdiff::unit_stem(unit)));
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(file, text)?;
+    }
+
+    fs::write(root.join("ldscript.ld"), linker_script(image, &link.units, &link.pieces, &link.entry, &link.bases, &link.external))?;
+    return Ok(SplitResult { units: link.split_count, auto_units: link.units.len() - link.split_count });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gcipl::{self, tests::{put, synthetic_bs2, temp_file}};
+    use crate::ppc;
+    use crate::profile;
+
+    // Operand i of an emitted line with its symbol replaced by the number the word holds there,
+    // written the way the decoder writes it
+    fn resolve(mnemonic: &str, operands: &[&str], i: usize, names: &HashMap<String, u32>) -> String {
+        let operand = operands[i];
+        let Some((name, relocation)) = operand.split_once('@') else {
+            return match names.get(operand) {
+                Some(addr) => format!("0x{:08X}", addr),
+                None       => operand.to_string(),
+            };
+        };
+        let value = names[name];
+        let (kind, register) = match relocation.split_once('(') {
+            Some((kind, register)) => (kind, Some(register.trim_end_matches(')'))),
+            None                   => (relocation, None),
+        };
+        // addi rD, rA, sym@sda21 has the base register before it
+        let base = match register.or(i.checked_sub(1).map(|p| operands[p])) {
+            Some("r2") => "_SDA2_BASE_",
+            _          => "_SDA_BASE_",
+        };
+        let number = match kind {
+            "ha"    => format!("0x{:X}", value.wrapping_add(0x8000) >> 16),
+            "h"     => format!("0x{:X}", value >> 16),
+            "l" if mnemonic.starts_with("ori") => format!("0x{:X}", value & 0xFFFF),
+            "l"     => ppc::hex(value as i16 as i32),
+            "sda21" => ppc::hex(value.wrapping_sub(names[base]) as i16 as i32),
+            _       => panic!("{}", operand),
+        };
+        return match register {
+            Some(register) => format!("{}({})", number, register),
+            None           => number,
+        };
+    }
+
+    #[test]
+    fn split_lines_are_the_original_words() {
+        // .text is 0x81300100-0x81300500, .rodata 0x81300580, .data 0x813005A0 and .sdata 0x813005C0.
+        // The rest of .text is the synthetic BS2's filler bytes.
+        let mut bs2_data = synthetic_bs2();
+        put(&mut bs2_data, 0x81300100, &[
+            0x3DA08131,     // lis r13, _SDA_BASE_@ha (0x813085C0)
+            0x39AD85C0,     // addi r13, r13, _SDA_BASE_@l
+            0x806D8000,     // lwz r3, .sdata@sda21(r13)
+            0x3C608130,     // lis r3, .data@ha
+            0x386305A0,     // addi r3, r3, .data@l
+            0x3C808130,     // lis r4, (.data + 4)@ha
+            0x80A405A4,     // lwz r5, (.data + 4)@l(r4)
+            0x3CC08130,     // lis r6, .rodata@h
+            0x60C60580,     // ori r6, r6, .rodata@l
+            0x480000DD,     // bl 0x81300200
+            0x4BCFFED9,     // bl 0x81000000, outside BS2
+            0x4182FFD4,     // beq 0x81300100
+            0x4E800020,     // blr
+        ]);
+        put(&mut bs2_data, 0x81300200, &[0x4E800020]);
+        put(&mut bs2_data, 0x813005A0, &[0x81300200]);
+        let image = BSImageRef::from_bs2(gcipl::BS2_ADDR, &bs2_data, profile::gc()).unwrap();
+        let splits = Splits { sections: Vec::new(), units: vec![Unit { name: "a.c".to_string(), ranges: vec![
+            SplitRange { section: ".text".to_string(), start: 0x81300100, end: 0x81300200 },
+            SplitRange { section: ".data".to_string(), start: 0x813005A0, end: 0x813005C0 },
+        ] }] };
+
+        let out_dir = std::path::PathBuf::from(temp_file("split"));
+        write_split(&out_dir.to_string_lossy(), &image, &splits, None).unwrap();
+        let script = fs::read_to_string(out_dir.join("ldscript.ld")).unwrap();
+        let files: Vec<String> = fs::read_dir(out_dir.join("asm")).unwrap().map(|f| fs::read_to_string(f.unwrap().path()).unwrap()).collect();
+        fs::remove_dir_all(&out_dir).ok();
+
+        // Where every name split uses stands for
+        let link = analyse(&image, &splits, None).unwrap();
+        let mut names: HashMap<String, u32> = link.labels.iter().map(|(addr, label)| (label.name.clone(), *addr)).collect();
+        names.extend(link.external.iter().map(|(addr, name)| (name.clone(), *addr)));
+        names.extend(SDA_REGISTERS.iter().zip(link.bases).filter_map(|((_, name, _), base)| Some((name.to_string(), base?))));
+        assert_eq!(names["_SDA_BASE_"], 0x813085C0);
+
+        // Every label is defined once, in an asm file or the linker script
+        let mut defined: Vec<String> = files.iter().flat_map(|f| f.lines()).filter_map(|l| l.strip_suffix(':')).map(|l| l.to_string()).collect();
+        defined.extend(script.lines().filter_map(|l| l.split_once(" = 0x")).map(|(name, _)| name.to_string()));
+        let unique: HashSet<&String> = defined.iter().collect();
+        assert_eq!(unique.len(), defined.len());
+
+        let mut referenced = 0;
+        for file in &files {
+            let mut pending_label: Option<&str> = None;
+            for line in file.lines() {
+                if let Some(label) = line.strip_suffix(':') {
+                    pending_label = Some(label);
+                    continue;
+                }
+                let Some((comment, text)) = line.strip_prefix("/* ").and_then(|l| l.split_once(" */\t")) else {
+                    continue;
+                };
+                let (addr, word) = comment.split_once(' ').unwrap();
+                let (addr, word) = (u32::from_str_radix(addr, 16).unwrap(), u32::from_str_radix(word, 16).unwrap());
+                assert_eq!(Some(&word.to_be_bytes()[..]), image.section_data(addr, 4), "{}", line);
+                if let Some(label) = pending_label.take() {
+                    assert_eq!(names.get(label), Some(&addr), "{}", label);
+                }
+
+                let (mnemonic, operands) = text.split_once(' ').unwrap_or((text, ""));
+                let operands: Vec<&str> = if operands.is_empty() { Vec::new() } else { operands.split(", ").collect() };
+                let symbolic = operands.iter().any(|o| names.contains_key(o.split('@').next().unwrap()));
+                if symbolic {
+                    referenced += 1;
+                    for name in operands.iter().map(|o| o.split('@').next().unwrap()).filter(|n| names.contains_key(*n)) {
+                        assert!(unique.contains(&name.to_string()), "{} has no label", name);
+                    }
+                }
+                if mnemonic == ".4byte" {
+                    let value = operands[0].strip_prefix("0x").map(|v| u32::from_str_radix(v, 16).unwrap()).unwrap_or_else(|| names[operands[0]]);
+                    assert_eq!(value, word, "{}", line);
+                    continue;
+                }
+                let resolved: Vec<String> = (0..operands.len()).map(|i| resolve(mnemonic, &operands, i, &names)).collect();
+                let resolved = if resolved.is_empty() { mnemonic.to_string() } else { format!("{} {}", mnemonic, resolved.join(", ")) };
+                assert_eq!(ppc::decode(word, addr).map(|i| i.text(&|_| None)), Some(resolved), "{}", line);
+            }
+        }
+        // The lis/addi pairs, the small data load, both calls, the branch and the tables' pointers at least
+        assert!(referenced >= 12, "{} symbolic lines", referenced);
+    }
+}
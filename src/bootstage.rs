@@ -1,30 +1,9 @@
 use std::fs;
-use std::io::{prelude::*, SeekFrom};
-
-// 0 = text
-// 1 = data
-// TODO: make it read an linkerscript file instead
-const LINK_ORDER: [i32; 10] = [
-    0,
-    1,
-    1,
-    0,
-    1,
-    1,
-    1,
-    1,
-    1,
-    1
-];
-
-const STUB_DEFAULT_ADDR : u32 = 0x81340000;
-const STUB_DEFAULT_SIZE : u32 = 0x00010000;
-
-const INIT_MEM_BOUND_START : u32 = 0x81330000;
-const UNINIT_MEM_BOUND_START : u32 = 0x81080000;
-const MEM_BOUND_END : u32 = 0x816D0000; // AFAIK no existing boot stage exceeds that boundary.
-
-const BS2_PAD : u32 = 0x20;
+use std::io::{prelude::*, Error, ErrorKind};
+
+use memmap2::Mmap;
+
+use crate::profile::{self, SectionKind, TargetProfile};
 
 pub const TEXT_COUNT : usize = 2;
 pub const DATA_COUNT : usize = 8;
@@ -63,106 +42,96 @@ pub struct BSImage {
 
     pub bss_addr: Vec<u32>,
     pub bss_len: Vec<u32>,
+
+    pub profile: TargetProfile,
 }
 
-#[allow(dead_code, unused)]
-fn read_u32(mut reader: impl Read + Seek, offset: u64) -> u32 {
-    let mut temp = [0u8;4];
+// Borrowed view of a BootStage.
+// Parses straight over the image bytes (a buffer or a memory-mapped file) without copying anything.
+pub struct BSImageRef<'a> {
+    pub bs1_addr: u32,
+    pub bs1_len: u32,
+    pub bs1_data: &'a [u8],
 
-    reader.seek(SeekFrom::Start(offset)).ok();
-    reader.read_exact(&mut temp).ok();
+    pub bs2_addr: u32,
+    pub bs2_len: u32,
+    pub bs2_data: &'a [u8],
 
-    return u32::from_be_bytes(temp);
-}
+    pub stub_addr: u32,
+    pub stub_len: u32,
 
-#[allow(dead_code)]
-fn read_u32_from_buf(buffer: &Vec<u8>, offset: u32) -> u32 {
-    let temp = [buffer[offset as usize],
-                buffer[offset as usize + 1],
-                buffer[offset as usize + 2],
-                buffer[offset as usize + 3]];
-    return u32::from_be_bytes(temp);
-}
+    pub unk_stuff: &'a [u8],
 
-#[allow(dead_code)]
-fn write_u32_from_buf(buffer: &mut Vec<u8>, offset: u32, value: u32) {
-    let temp = u32::to_be_bytes(value);
-    buffer[offset as usize] = temp[0];
-    buffer[offset as usize + 1] = temp[1];
-    buffer[offset as usize + 2] = temp[2];
-    buffer[offset as usize + 3] = temp[3];
-}
+    pub bs1_entry: u32,
+    pub bs2_entry: u32,
 
-#[allow(dead_code)]
-fn find_u32_from_buf(buffer: &Vec<u8>, value: u32, offset: u32) -> u32 {
-    let mut done = 0;
-    let mut curr_offset = offset as usize;
+    pub text_addr: [u32; TEXT_COUNT],
+    pub text_len: [u32; TEXT_COUNT],
 
-    while done == 0 {
-        let temp = [buffer[curr_offset],
-                    buffer[curr_offset + 1],
-                    buffer[curr_offset + 2],
-                    buffer[curr_offset + 3]];
-        if u32::from_be_bytes(temp) == value {
-            done = 1;
-        }
-        else {
-            curr_offset += 4;
-        }
-    }
+    pub data_addr: [u32; DATA_COUNT],
+    pub data_len: [u32; DATA_COUNT],
 
-    return curr_offset as u32;
-}
+    // Sorted by address (see open_file)
+    pub bss: [BSImageBSS; BSS_COUNT],
 
-#[allow(dead_code)]
-fn find_u32_from_buf_range(buffer: &Vec<u8>, min: u32, max: u32, offset: u32) -> u32 {
-    let mut done = 0;
-    let mut curr_offset = offset as usize;
+    // Offsets of the ROM copy and BSS tables inside BS2
+    pub rom_table_off: u32,
+    pub bss_table_off: u32,
 
-    while done == 0 {
-        let temp = [buffer[curr_offset],
-                    buffer[curr_offset + 1],
-                    buffer[curr_offset + 2],
-                    buffer[curr_offset + 3]];
-        if u32::from_be_bytes(temp) >= min && u32::from_be_bytes(temp) <= max {
-            done = 1;
-        }
-        else {
-            curr_offset += 4;
-        }
-    }
+    pub profile: TargetProfile,
+}
 
-    return curr_offset as u32;
+fn invalid_image(message: &str) -> Error {
+    return Error::new(ErrorKind::InvalidData, format!("Invalid BootStage: {}", message));
 }
 
-#[allow(dead_code)]
-fn read_u8s(mut reader: impl Read + Seek, size: usize, offset: u64) -> Vec<u8> {
-    let mut temp = vec![0u8;size];
+fn read_u32_from_buf(buffer: &[u8], offset: u32) -> std::io::Result<u32> {
+    let start = offset as usize;
+    return match buffer.get(start..start + 4) {
+        Some(temp) => Ok(u32::from_be_bytes([temp[0], temp[1], temp[2], temp[3]])),
+        None       => Err(invalid_image(&format!("read past the end at {:#X}", offset))),
+    };
+}
 
-    reader.seek(SeekFrom::Start(offset)).ok();
-    reader.read_exact(&mut temp).ok();
+fn write_u32_from_buf(buffer: &mut [u8], offset: u32, value: u32) {
+    buffer[offset as usize..offset as usize + 4].copy_from_slice(&u32::to_be_bytes(value));
+}
 
-    return temp;
+fn find_u32_from_buf(buffer: &[u8], value: u32, offset: u32) -> Option<u32> {
+    return find_u32_from_buf_range(buffer, value, value, offset);
 }
 
-#[allow(dead_code)]
-fn read_u8s_from_buf(buffer: &Vec<u8>, size: usize, offset: u32) -> Vec<u8> {
-    let mut temp = vec![0u8;size];
+fn find_u32_from_buf_range(buffer: &[u8], min: u32, max: u32, offset: u32) -> Option<u32> {
+    let mut curr_offset = offset as usize;
 
-    for i in 0..size {
-        temp[i] = buffer[offset as usize + i];
+    while curr_offset + 4 <= buffer.len() {
+        let value = u32::from_be_bytes([buffer[curr_offset],
+                                        buffer[curr_offset + 1],
+                                        buffer[curr_offset + 2],
+                                        buffer[curr_offset + 3]]);
+        if value >= min && value <= max {
+            return Some(curr_offset as u32);
+        }
+        curr_offset += 4;
     }
 
-    return temp;
+    return None;
+}
+
+fn slice_from_buf(buffer: &[u8], size: usize, offset: u32) -> std::io::Result<&[u8]> {
+    let start = offset as usize;
+    return buffer.get(start..start + size)
+                 .ok_or_else(|| invalid_image(&format!("{:#X} bytes at {:#X} exceed the file size {:#X}", size, offset, buffer.len())));
 }
 
+#[allow(clippy::unused_io_amount)]
 fn write_blank(mut writer: impl Write + Seek, size: u32) {
     let temp : Vec<u8> = vec![0;size as usize];
     writer.write(&temp).ok();
 }
 
-fn verify_unk_data(image: &BSImage) -> bool {
-    if image.unk_stuff.len() < 0x20 {
+pub fn verify_unk_data(image: &BSImage) -> bool {
+    if image.profile.bs2_pad == 0 || image.unk_stuff.len() < image.profile.bs2_pad as usize {
         return false;
     }
 
@@ -173,108 +142,258 @@ fn verify_unk_data(image: &BSImage) -> bool {
     return true;
 }
 
-#[allow(dead_code)]
-pub fn open_file(file_name: &String) -> BSImage {
-    let file = fs::File::open(&file_name).expect("File either not found or failed to open!!");
+impl<'a> BSImageRef<'a> {
+    // Parses with the profile auto-detected from the header.
+    pub fn parse(file_data: &'a [u8]) -> std::io::Result<BSImageRef<'a>> {
+        return BSImageRef::parse_with_profile(file_data, profile::detect(file_data));
+    }
 
-    // Read BS2
-    let mut new_image = BSImage {
-        bs1_addr:   read_u32(&file, 0x48),
-        bs1_len:    read_u32(&file, 0x90) - 4,
-        bs1_data:   vec![0], // temp
+    pub fn parse_with_profile(file_data: &'a [u8], profile: TargetProfile) -> std::io::Result<BSImageRef<'a>> {
+        if file_data.len() < HEADER_LENGTH {
+            return Err(invalid_image("file is smaller than the header"));
+        }
 
-        bs2_addr:   read_u32(&file, 0x64),
-        bs2_len:    read_u32(&file, 0xAC),
-        bs2_data:   vec![0], // temp
+        let bs1_off = read_u32_from_buf(file_data, 0x00)?;
+        let bs1_len = read_u32_from_buf(file_data, 0x90)?.checked_sub(4).ok_or_else(|| invalid_image("BS1 length is too small"))?;
+        let mut bs2_off = read_u32_from_buf(file_data, 0x1C)?;
 
-        stub_addr:  read_u32(&file, 0xD8),
-        stub_len:   read_u32(&file, 0xDC),
+        let mut new_image = BSImageRef {
+            bs1_addr:   read_u32_from_buf(file_data, 0x48)?,
+            bs1_len,
+            bs1_data:   &[],
 
-        unk_stuff:  vec![0],
+            bs2_addr:   read_u32_from_buf(file_data, 0x64)?,
+            bs2_len:    read_u32_from_buf(file_data, 0xAC)?,
+            bs2_data:   &[],
 
-        bs1_entry:  read_u32(&file, 0xE0),
-        bs2_entry:  read_u32(&file, 0x4FC),
+            stub_addr:  read_u32_from_buf(file_data, 0xD8)?,
+            stub_len:   read_u32_from_buf(file_data, 0xDC)?,
 
-        text_addr:  vec![0;TEXT_COUNT],
-        text_len:   vec![0;TEXT_COUNT],
+            unk_stuff:  &[],
 
-        data_addr:  vec![0;DATA_COUNT],
-        data_len:   vec![0;DATA_COUNT],
+            bs1_entry:  read_u32_from_buf(file_data, 0xE0)?,
+            // The BS2 entry point is the last word of BS1, so it moves with the BS1 size (0x4FC on retail)
+            bs2_entry:  read_u32_from_buf(file_data, bs1_off.checked_add(bs1_len).ok_or_else(|| invalid_image("BS1 offset is too large"))?)?,
 
-        bss_addr:   vec![0;BSS_COUNT],
-        bss_len:    vec![0;BSS_COUNT],
-    };
+            text_addr:  [0;TEXT_COUNT],
+            text_len:   [0;TEXT_COUNT],
+
+            data_addr:  [0;DATA_COUNT],
+            data_len:   [0;DATA_COUNT],
+
+            bss:        [BSImageBSS{addr:0,size:0};BSS_COUNT],
+            rom_table_off: 0,
+            bss_table_off: 0,
+
+            profile,
+        };
+        // BS1 with the BS2 entry word and BS2 with its pad block must end below 4 GiB, the tools add lengths to these addresses
+        if new_image.bs1_addr.checked_add(bs1_len + 4).is_none() || new_image.bs2_addr.checked_add(new_image.bs2_len).is_none() {
+            return Err(invalid_image("BS1 or BS2 runs past the end of the address space"));
+        }
 
-    let bs1_off = read_u32(&file, 0x00);
-    let mut bs2_off = read_u32(&file, 0x1C);
+        let bs2_pad = new_image.profile.bs2_pad;
+        let init_mem_bound_start = new_image.profile.init_mem_bound_start;
+        let mem_bound_end = new_image.profile.mem_bound_end;
 
-    let checker = read_u32(&file, bs2_off as u64);
-    let checker2 = read_u32(&file, bs2_off as u64 + 0x08);
+        let checker = read_u32_from_buf(file_data, bs2_off)?;
+        let checker2 = read_u32_from_buf(file_data, bs2_off + 0x08)?;
 
-    if checker >= INIT_MEM_BOUND_START && checker <= MEM_BOUND_END && checker2 == 0x00000000 {
-        new_image.unk_stuff = read_u8s(&file, BS2_PAD as usize, bs2_off as u64);
+        if bs2_pad != 0 && (init_mem_bound_start..=mem_bound_end).contains(&checker) && checker2 == 0x00000000 {
+            new_image.unk_stuff = slice_from_buf(file_data, bs2_pad as usize, bs2_off)?;
 
-        bs2_off  += BS2_PAD;
-        new_image.bs2_addr += BS2_PAD;
-        new_image.bs2_len  -= BS2_PAD;
+            bs2_off  += bs2_pad;
+            new_image.bs2_addr += bs2_pad;
+            new_image.bs2_len   = new_image.bs2_len.checked_sub(bs2_pad).ok_or_else(|| invalid_image("BS2 length is too small"))?;
+        }
+
+        if new_image.bs2_len < 4 {
+            return Err(invalid_image("BS2 length is too small"));
+        }
+
+        new_image.bs1_data = slice_from_buf(file_data, new_image.bs1_len as usize, bs1_off)?;
+        new_image.bs2_data = slice_from_buf(file_data, new_image.bs2_len as usize - 4, bs2_off)?;
+
+        new_image.read_section_tables()?;
+
+        return Ok(new_image);
     }
 
-    new_image.bs1_data = read_u8s(&file, new_image.bs1_len as usize, bs1_off as u64);
-    new_image.bs2_data = read_u8s(&file, new_image.bs2_len as usize - 4, bs2_off as u64);
+    // Reads only the section tables of a bare BS2 payload, such as the one describe stores.
+    pub fn from_bs2(bs2_addr: u32, bs2_data: &'a [u8], profile: TargetProfile) -> std::io::Result<BSImageRef<'a>> {
+        let mut new_image = BSImageRef {
+            bs1_addr:   0,
+            bs1_len:    0,
+            bs1_data:   &[],
+
+            bs2_addr,
+            bs2_len:    bs2_data.len() as u32 + 4,
+            bs2_data,
+
+            stub_addr:  0,
+            stub_len:   0,
+
+            unk_stuff:  &[],
+
+            bs1_entry:  0,
+            bs2_entry:  0,
 
-    // Read Section Info
-    let rom_offset = find_u32_from_buf(&new_image.bs2_data, INIT_MEM_BOUND_START, 0);
-    let mut read_off = rom_offset;
-    let mut text_i = 0;
-    let mut data_i = 0;
-    for i in 0..TEXT_COUNT+DATA_COUNT {
-        // Text symbol
-        if LINK_ORDER[i] == 0 {
-            new_image.text_addr[text_i] = read_u32_from_buf(&new_image.bs2_data, read_off);
-            new_image.text_len[text_i] = read_u32_from_buf(&new_image.bs2_data, read_off + 0x08);
-            text_i += 1;
+            text_addr:  [0;TEXT_COUNT],
+            text_len:   [0;TEXT_COUNT],
+
+            data_addr:  [0;DATA_COUNT],
+            data_len:   [0;DATA_COUNT],
+
+            bss:        [BSImageBSS{addr:0,size:0};BSS_COUNT],
+            rom_table_off: 0,
+            bss_table_off: 0,
+
+            profile,
+        };
+        new_image.read_section_tables()?;
+
+        return Ok(new_image);
+    }
+
+    // Fills in the sections from the ROM copy and BSS tables inside bs2_data.
+    pub fn read_section_tables(&mut self) -> std::io::Result<()> {
+        let new_image = self;
+        let init_mem_bound_start = new_image.profile.init_mem_bound_start;
+        let uninit_mem_bound_start = new_image.profile.uninit_mem_bound_start;
+        let mem_bound_end = new_image.profile.mem_bound_end;
+        if new_image.bs2_addr.checked_add(new_image.bs2_data.len() as u32).is_none() {
+            return Err(invalid_image("BS2 runs past the end of the address space"));
+        }
+
+        // Read Section Info
+        let rom_offset = find_u32_from_buf(new_image.bs2_data, init_mem_bound_start, 0)
+                         .ok_or_else(|| invalid_image("section table not found in BS2"))?;
+        let mut read_off = rom_offset;
+        let mut text_i = 0;
+        let mut data_i = 0;
+        for i in 0..TEXT_COUNT+DATA_COUNT {
+            // Text symbol
+            if new_image.profile.link_order[i] == SectionKind::Text {
+                new_image.text_addr[text_i] = read_u32_from_buf(new_image.bs2_data, read_off)?;
+                new_image.text_len[text_i] = read_u32_from_buf(new_image.bs2_data, read_off + 0x08)?;
+                text_i += 1;
+            }
+            // Data symbol
+            else if new_image.profile.link_order[i] == SectionKind::Data {
+                new_image.data_addr[data_i] = read_u32_from_buf(new_image.bs2_data, read_off)?;
+                new_image.data_len[data_i] = read_u32_from_buf(new_image.bs2_data, read_off + 0x08)?;
+                data_i += 1;
+            }
+            read_off += 0x0C;
+        }
+
+        // Read BSS Section Info
+        let bss_offset = find_u32_from_buf_range(new_image.bs2_data, uninit_mem_bound_start, mem_bound_end, read_off)
+                         .ok_or_else(|| invalid_image("BSS table not found in BS2"))?;
+        read_off = bss_offset;
+        for i in 0..BSS_COUNT {
+            new_image.bss[i].addr = read_u32_from_buf(new_image.bs2_data, read_off)?;
+            new_image.bss[i].size = read_u32_from_buf(new_image.bs2_data, read_off + 0x04)?;
+            read_off += 0x08;
         }
-        // Data symbol
-        else if LINK_ORDER[i] == 1 {
-            new_image.data_addr[data_i] = read_u32_from_buf(&new_image.bs2_data, read_off);
-            new_image.data_len[data_i] = read_u32_from_buf(&new_image.bs2_data, read_off + 0x08);
-            data_i += 1;
+
+        // HACK: Order the BSS to fix relocating
+        new_image.bss.sort_by_key(|x| x.addr);
+        if new_image.text_addr[0] >= new_image.bss[0].size && new_image.text_addr[0] >= new_image.bss[0].addr {
+            new_image.bss[0].size = new_image.text_addr[0] - new_image.bss[0].addr;
+        }
+        new_image.rom_table_off = rom_offset;
+        new_image.bss_table_off = bss_offset;
+
+        let ends = new_image.text_addr.iter().zip(new_image.text_len.iter())
+                   .chain(new_image.data_addr.iter().zip(new_image.data_len.iter()))
+                   .chain(new_image.bss.iter().map(|b| (&b.addr, &b.size)));
+        for (addr, len) in ends {
+            if addr.checked_add(*len).is_none() {
+                return Err(invalid_image(&format!("section at {:#010X} runs past the end of the address space", addr)));
+            }
         }
-        read_off += 0x0C;
+
+        return Ok(());
     }
 
-    // Read BSS Section Info
-    let mut bss_sec = vec![BSImageBSS{addr:0,size:0};BSS_COUNT];
-    let bss_offset = find_u32_from_buf_range(&new_image.bs2_data, UNINIT_MEM_BOUND_START, MEM_BOUND_END, read_off);
-    read_off = bss_offset;
-    for i in 0..BSS_COUNT {
-        bss_sec[i].addr = read_u32_from_buf(&new_image.bs2_data, read_off);
-        bss_sec[i].size = read_u32_from_buf(&new_image.bs2_data, read_off + 0x04);
-        read_off += 0x08;
+    // Bytes of a loaded section, straight out of BS2.
+    pub fn section_data(&self, addr: u32, len: u32) -> Option<&'a [u8]> {
+        let start = addr.checked_sub(self.bs2_addr)? as usize;
+        return self.bs2_data.get(start..start + len as usize);
     }
 
-    // HACK: Order the BSS to fix relocating
-    bss_sec.sort_by_key(|x| x.addr);
-    read_off = bss_offset;
-    if new_image.text_addr[0] >= bss_sec[0].size {
-        bss_sec[0].size = new_image.text_addr[0] - bss_sec[0].addr;
+    pub fn text_data(&self, index: usize) -> Option<&'a [u8]> {
+        return self.section_data(self.text_addr[index], self.text_len[index]);
     }
-    for i in 0..BSS_COUNT {
-        write_u32_from_buf(&mut new_image.bs2_data, read_off, bss_sec[i].addr);
-        write_u32_from_buf(&mut new_image.bs2_data, read_off + 0x04, bss_sec[i].size);
-        read_off += 0x08;
+
+    pub fn data_data(&self, index: usize) -> Option<&'a [u8]> {
+        return self.section_data(self.data_addr[index], self.data_len[index]);
     }
 
-    // Save the BSS
-    for i in 0..BSS_COUNT {
-        new_image.bss_addr[i] = bss_sec[i].addr;
-        new_image.bss_len[i] = bss_sec[i].size;
+    pub fn to_image(&self) -> BSImage {
+        let mut new_image = BSImage {
+            bs1_addr:   self.bs1_addr,
+            bs1_len:    self.bs1_len,
+            bs1_data:   self.bs1_data.to_vec(),
+
+            bs2_addr:   self.bs2_addr,
+            bs2_len:    self.bs2_len,
+            bs2_data:   self.bs2_data.to_vec(),
+
+            stub_addr:  self.stub_addr,
+            stub_len:   self.stub_len,
+
+            unk_stuff:  if self.unk_stuff.is_empty() { vec![0] } else { self.unk_stuff.to_vec() },
+
+            bs1_entry:  self.bs1_entry,
+            bs2_entry:  self.bs2_entry,
+
+            text_addr:  self.text_addr.to_vec(),
+            text_len:   self.text_len.to_vec(),
+
+            data_addr:  self.data_addr.to_vec(),
+            data_len:   self.data_len.to_vec(),
+
+            bss_addr:   self.bss.iter().map(|x| x.addr).collect(),
+            bss_len:    self.bss.iter().map(|x| x.size).collect(),
+
+            profile:    self.profile.clone(),
+        };
+
+        // Write the ordered BSS back so the owned image relocates properly
+        let mut read_off = self.bss_table_off;
+        for i in 0..BSS_COUNT {
+            write_u32_from_buf(&mut new_image.bs2_data, read_off, self.bss[i].addr);
+            write_u32_from_buf(&mut new_image.bs2_data, read_off + 0x04, self.bss[i].size);
+            read_off += 0x08;
+        }
+
+        return new_image;
     }
+}
 
-    return new_image;
+// Maps a file into memory for BSImageRef::parse.
+pub fn map_file(file_name: &str) -> std::io::Result<Mmap> {
+    let file = fs::File::open(file_name)?;
+    // SAFETY: the mapping is read-only and bstool never modifies its inputs while they are open.
+    return unsafe { Mmap::map(&file) };
 }
 
-#[allow(dead_code)]
+// Opens a BootStage with the given profile (name or profile file), or an auto-detected one.
+#[allow(dead_code, clippy::ptr_arg)]
+pub fn open_file(file_name: &String, profile: &Option<String>) -> std::io::Result<BSImage> {
+    let file_data = map_file(file_name)?;
+    return open_data(&file_data, profile);
+}
+
+// Same as open_file, for BootStages that come out of a container (WAD, NAND) instead of a file.
+pub fn open_data(file_data: &[u8], profile: &Option<String>) -> std::io::Result<BSImage> {
+    let profile = profile::select(profile, file_data)?;
+    return Ok(BSImageRef::parse_with_profile(file_data, profile)?.to_image());
+}
+
+#[allow(dead_code, clippy::unused_io_amount, clippy::needless_borrow, clippy::needless_borrows_for_generic_args)]
 pub fn create_file(file_name: &String, image: &BSImage) {
     let bs1_off = HEADER_LENGTH as u32;
     let bs2_off = HEADER_LENGTH as u32 + image.bs1_len + 4;
@@ -282,8 +401,8 @@ pub fn create_file(file_name: &String, image: &BSImage) {
     let mut bs2_len = image.bs2_len;
 
     if verify_unk_data(&image) {
-        bs2_addr -= 0x20;
-        bs2_len += 0x20;
+        bs2_addr -= image.profile.bs2_pad;
+        bs2_len += image.profile.bs2_pad;
     }
 
     let mut file = fs::File::create(&file_name).expect("File failed to create!!");
@@ -319,13 +438,18 @@ pub fn create_file(file_name: &String, image: &BSImage) {
 
     // BS2 (with entry point)
     if verify_unk_data(&image) {
-        file.write(&image.unk_stuff).ok();
+        file.write(&image.unk_stuff[..image.profile.bs2_pad as usize]).ok();
     }
     file.write(&image.bs2_data).ok();
 }
 
 #[allow(dead_code)]
 pub fn default() -> BSImage {
+    return default_for(profile::retail());
+}
+
+#[allow(dead_code)]
+pub fn default_for(profile: TargetProfile) -> BSImage {
     return BSImage {
         bs1_addr:   0,
         bs1_len:    0,
@@ -335,8 +459,8 @@ pub fn default() -> BSImage {
         bs2_len:    0,
         bs2_data:   vec![0],
 
-        stub_addr:  STUB_DEFAULT_ADDR,
-        stub_len:   STUB_DEFAULT_SIZE,
+        stub_addr:  profile.stub_default_addr,
+        stub_len:   profile.stub_default_size,
 
         unk_stuff:  vec![0],
 
@@ -351,7 +475,338 @@ pub fn default() -> BSImage {
 
         bss_addr:   vec![0;BSS_COUNT],
         bss_len:    vec![0;BSS_COUNT],
+
+        profile,
     };
 }
 
 
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::detect::{self, FormatKind};
+    use crate::elf;
+    use crate::gcipl::tests::temp_file;
+    use crate::meta::{self, BSImageMeta};
+
+    const BS1_ADDR : u32 = 0x81200000;
+
+    // Builds a BootStage around a BS2 at the profile's init bound:
+    // .init (holding the ROM copy and BSS tables), .text and the eight data sections.
+    pub fn synthetic_image(profile: TargetProfile, bs1_len: u32, bs2_size: usize, with_pad: bool) -> BSImage {
+        let base = profile.init_mem_bound_start;
+        let mut image = default_for(profile);
+
+        image.bs1_addr  = BS1_ADDR;
+        image.bs1_len   = bs1_len;
+        image.bs1_data  = vec![0x60; bs1_len as usize];
+        image.bs1_entry = BS1_ADDR;
+
+        image.text_addr = vec![base, base + 0x100];
+        image.text_len  = vec![0x100, 0x400];
+        image.data_addr = (0..DATA_COUNT as u32).map(|i| base + 0x500 + i * 0x20).collect();
+        image.data_len  = vec![0x20; DATA_COUNT];
+        let bss_base    = base + bs2_size as u32;
+        image.bss_addr  = vec![bss_base, bss_base + 0x100, bss_base + 0x200];
+        image.bss_len   = vec![0x100; BSS_COUNT];
+
+        let mut bs2_data = vec![0u8; bs2_size];
+        // Something that is not a pad block
+        write_u32_from_buf(&mut bs2_data, 0, 0x48000000);
+        for (i, byte) in bs2_data[0x100..0x500].iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+
+        // The tables live in .init, like the linker's _rom_copy_info/_bss_init_info
+        let mut table_off = 0x10;
+        let (mut text_i, mut data_i) = (0, 0);
+        for kind in image.profile.link_order.clone() {
+            let (addr, len) = if kind == SectionKind::Text { text_i += 1; (image.text_addr[text_i - 1], image.text_len[text_i - 1]) }
+                              else { data_i += 1; (image.data_addr[data_i - 1], image.data_len[data_i - 1]) };
+            write_u32_from_buf(&mut bs2_data, table_off, addr);
+            write_u32_from_buf(&mut bs2_data, table_off + 0x04, addr);
+            write_u32_from_buf(&mut bs2_data, table_off + 0x08, len);
+            table_off += 0x0C;
+        }
+        for i in 0..BSS_COUNT {
+            write_u32_from_buf(&mut bs2_data, table_off, image.bss_addr[i]);
+            write_u32_from_buf(&mut bs2_data, table_off + 0x04, image.bss_len[i]);
+            table_off += 0x08;
+        }
+
+        image.bs2_addr  = base;
+        image.bs2_len   = bs2_size as u32 + 4;
+        image.bs2_data  = bs2_data;
+        image.bs2_entry = base + 0x100;
+
+        if with_pad {
+            let pad = image.profile.bs2_pad as usize;
+            image.unk_stuff = vec![0; pad];
+            image.unk_stuff[0..4].copy_from_slice(&base.to_be_bytes());
+        }
+
+        return image;
+    }
+
+    // A vWii BS2 that runs past the retail bound, so only the vWii profile fits it
+    pub fn synthetic_vwii(bs1_len: u32, with_pad: bool) -> BSImage {
+        let profile = profile::vwii();
+        let bs2_size = (profile::retail().mem_bound_end - profile.init_mem_bound_start) as usize + 0x1000;
+        return synthetic_image(profile, bs1_len, bs2_size, with_pad);
+    }
+
+    // A retail image whose BSS table is out of order and has an entry below .init,
+    // the case open_file reorders and resizes
+    fn unsorted_bss_image() -> BSImage {
+        let mut image = synthetic_image(profile::retail(), 0x4FC, 0x1000, true);
+        let bss_base = image.bs2_addr + 0x1000;
+        let table_off = 0x10 + (TEXT_COUNT + DATA_COUNT) as u32 * 0x0C;
+        let bss = [(bss_base + 0x100, 0x100), (0x81100000, 0x100), (bss_base, 0x100)];
+        for (i, (addr, size)) in bss.iter().enumerate() {
+            write_u32_from_buf(&mut image.bs2_data, table_off + i as u32 * 0x08, *addr);
+            write_u32_from_buf(&mut image.bs2_data, table_off + i as u32 * 0x08 + 0x04, *size);
+        }
+        return image;
+    }
+
+    // Describes an image file, returning the document name
+    fn describe(file_data: &[u8], name: &str) -> String {
+        let doc_file = temp_file(name);
+        meta::write_meta(&doc_file, &BSImageRef::parse(file_data).unwrap(), None).unwrap();
+        return doc_file;
+    }
+
+    fn remove_described(doc_file: &str) {
+        let stem = doc_file.strip_suffix(".json").unwrap();
+        for file_name in [doc_file.to_string(), format!("{}.bs1.bin", stem), format!("{}.bs2.bin", stem)] {
+            fs::remove_file(file_name).ok();
+        }
+    }
+
+    pub fn write_and_read(image: &BSImage, name: &str) -> Vec<u8> {
+        let file_name = temp_file(name);
+        create_file(&file_name, image);
+        let file_data = fs::read(&file_name).unwrap();
+        fs::remove_file(&file_name).ok();
+        return file_data;
+    }
+
+    fn assert_same_layout(parsed: &BSImageRef, image: &BSImage) {
+        assert_eq!(parsed.bs1_addr, image.bs1_addr);
+        assert_eq!(parsed.bs1_data, &image.bs1_data[..]);
+        assert_eq!(parsed.bs2_addr, image.bs2_addr);
+        assert_eq!(parsed.bs2_len, image.bs2_len);
+        assert_eq!(parsed.bs2_entry, image.bs2_entry);
+        assert_eq!(parsed.text_addr.to_vec(), image.text_addr);
+        assert_eq!(parsed.text_len.to_vec(), image.text_len);
+        assert_eq!(parsed.data_addr.to_vec(), image.data_addr);
+        assert_eq!(parsed.data_len.to_vec(), image.data_len);
+        assert_eq!(parsed.bss.iter().map(|x| x.addr).collect::<Vec<u32>>(), image.bss_addr);
+    }
+
+    #[test]
+    fn image_ref_matches_owned_image() {
+        let file_data = write_and_read(&unsorted_bss_image(), "ref.img");
+        let file_name = temp_file("ref-open.img");
+        fs::write(&file_name, &file_data).unwrap();
+        let image = open_file(&file_name, &None).unwrap();
+        fs::remove_file(&file_name).ok();
+
+        let parsed = BSImageRef::parse(&file_data).unwrap();
+        assert_eq!(parsed.profile.name, image.profile.name);
+        assert_eq!((parsed.bs1_addr, parsed.bs1_len, parsed.bs1_entry), (image.bs1_addr, image.bs1_len, image.bs1_entry));
+        assert_eq!((parsed.bs2_addr, parsed.bs2_len, parsed.bs2_entry), (image.bs2_addr, image.bs2_len, image.bs2_entry));
+        assert_eq!((parsed.stub_addr, parsed.stub_len), (image.stub_addr, image.stub_len));
+        assert_eq!(parsed.bs1_data, &image.bs1_data[..]);
+        assert_eq!(parsed.unk_stuff, &image.unk_stuff[..]);
+        assert_same_layout(&parsed, &image);
+        assert_eq!(parsed.bss.iter().map(|x| x.size).collect::<Vec<u32>>(), image.bss_len);
+
+        // Only the BSS table differs, open_file stores it sorted
+        let table_end = parsed.bss_table_off as usize + BSS_COUNT * 0x08;
+        assert_eq!(parsed.bs2_data[..parsed.bss_table_off as usize], image.bs2_data[..parsed.bss_table_off as usize]);
+        assert_eq!(parsed.bs2_data[table_end..], image.bs2_data[table_end..]);
+        // .init holds the tables, everything else is the same bytes
+        let start = (image.text_addr[1] - image.bs2_addr) as usize;
+        assert_eq!(parsed.text_data(1).unwrap(), &image.bs2_data[start..start + image.text_len[1] as usize]);
+        for i in 0..DATA_COUNT {
+            let start = (image.data_addr[i] - image.bs2_addr) as usize;
+            assert_eq!(parsed.data_data(i).unwrap(), &image.bs2_data[start..start + image.data_len[i] as usize]);
+        }
+    }
+
+    #[test]
+    fn vwii_image_is_detected() {
+        let file_data = write_and_read(&synthetic_vwii(0x3FC, true), "detect.img");
+
+        assert_eq!(detect::detect(&file_data).kind, FormatKind::VWiiBootStage);
+        assert_eq!(profile::detect(&file_data).name, "vwii");
+    }
+
+    #[test]
+    fn ndev_image_is_detected() {
+        // BSS below the retail bound, which the vwii profile (tried first, same header bounds) doesn't allow
+        let mut image = synthetic_image(profile::ndev(), 0x3FC, 0x1000, true);
+        let table_off = 0x10 + (TEXT_COUNT + DATA_COUNT) as u32 * 0x0C;
+        for i in 0..BSS_COUNT as u32 {
+            write_u32_from_buf(&mut image.bs2_data, table_off + i * 0x08, 0x80100000 + i * 0x100);
+        }
+        let file_data = write_and_read(&image, "ndev.img");
+
+        assert!(profile::header_fits(&file_data, &profile::vwii()));
+        assert_eq!(profile::detect(&file_data).name, "ndev");
+        assert_eq!(detect::detect(&file_data).kind, FormatKind::WiiBootStage);
+        assert_eq!(BSImageRef::parse(&file_data).unwrap().bss[0].addr, 0x80100000);
+    }
+
+    #[test]
+    fn gc_bs2_image_is_detected() {
+        let file_data = write_and_read(&synthetic_image(profile::gc(), 0x3FC, 0x1000, false), "gc.img");
+
+        assert_eq!(profile::detect(&file_data).name, "gc");
+        assert_eq!(BSImageRef::parse(&file_data).unwrap().bs2_addr, profile::gc().init_mem_bound_start);
+    }
+
+    #[test]
+    fn profile_rejects_short_pad_block() {
+        let mut custom = profile::retail();
+        custom.bs2_pad = 1;
+        assert_eq!(profile::verify_profile(&custom).err().unwrap().kind(), ErrorKind::InvalidData);
+        custom.bs2_pad = 0x40;
+        assert!(profile::verify_profile(&custom).is_ok());
+    }
+
+    #[test]
+    fn vwii_image_parses() {
+        let image = synthetic_vwii(0x3FC, false);
+        let file_data = write_and_read(&image, "parse.img");
+
+        let parsed = BSImageRef::parse(&file_data).unwrap();
+        assert_eq!(parsed.profile.name, "vwii");
+        assert!(parsed.unk_stuff.is_empty());
+        assert_same_layout(&parsed, &image);
+
+        // The retail bounds can't describe it
+        assert!(!profile::header_fits(&file_data, &profile::retail()));
+    }
+
+    #[test]
+    fn vwii_pad_block_is_split_off() {
+        let image = synthetic_vwii(0x3FC, true);
+        let file_data = write_and_read(&image, "pad.img");
+
+        let parsed = BSImageRef::parse(&file_data).unwrap();
+        assert_eq!(parsed.unk_stuff, &image.unk_stuff[..]);
+        assert_same_layout(&parsed, &image);
+
+        // And put back in front of BS2 when written again
+        assert_eq!(write_and_read(&parsed.to_image(), "pad2.img"), file_data);
+    }
+
+    #[test]
+    fn bs2_entry_follows_bs1() {
+        // A BS1 of another size moves the BS2 entry point away from 0x4FC
+        let image = synthetic_vwii(0x1FC, false);
+        let file_data = write_and_read(&image, "entry.img");
+
+        let parsed = BSImageRef::parse(&file_data).unwrap();
+        assert_eq!(parsed.bs1_len, 0x1FC);
+        assert_eq!(parsed.bs2_entry, image.bs2_entry);
+        assert_same_layout(&parsed, &image);
+    }
+
+    #[test]
+    fn addresses_past_4gib_are_rejected() {
+        let file_data = write_and_read(&synthetic_image(profile::retail(), 0x4FC, 0x1000, false), "wrap.img");
+        let parse = |patch: &[(usize, u32)]| {
+            let mut patched = file_data.clone();
+            for (off, value) in patch {
+                write_u32_from_buf(&mut patched, *off as u32, *value);
+            }
+            return BSImageRef::parse_with_profile(&patched, profile::retail()).is_ok();
+        };
+        assert!(parse(&[]));
+
+        // BS1 plus the BS2 entry word, BS2 and a .init length from the ROM copy table
+        let bs2_off = u32::from_be_bytes(file_data[0x1C..0x20].try_into().unwrap()) as usize;
+        assert!(!parse(&[(0x48, 0xFFFFFC00)]));
+        assert!(!parse(&[(0x64, 0xFFFFF800)]));
+        assert!(!parse(&[(bs2_off + 0x18, 0xFFFFFFFF)]));
+    }
+
+    #[test]
+    fn vwii_elf_export_and_convert() {
+        let image = synthetic_vwii(0x3FC, true);
+        let parsed_image = BSImageRef::parse(&write_and_read(&image, "export.img")).unwrap().to_image();
+
+        let elf_name = temp_file("export.elf");
+        let names = [parsed_image.profile.text_names.clone(), parsed_image.profile.data_names.clone(), parsed_image.profile.bss_names.clone()].concat();
+        elf::turn_raw_to_elf(&elf_name, &parsed_image.bs2_data,
+                             &parsed_image.text_addr, &parsed_image.text_len,
+                             &parsed_image.data_addr, &parsed_image.data_len,
+                             &parsed_image.bss_addr, &parsed_image.bss_len,
+                             parsed_image.bs2_entry, parsed_image.bs2_addr, &names).unwrap();
+
+        let elf_data = fs::read(&elf_name).unwrap();
+        let header = elf::read_elf32_hdr(&elf_data).unwrap();
+        assert_eq!(header.e_entry, image.bs2_entry);
+        assert_eq!(elf::read_elf32_prg_hdrs(&elf_data, &header).unwrap().len(), TEXT_COUNT + DATA_COUNT + BSS_COUNT);
+
+        // Converting the ELF back over the same base gives the same sections
+        let raw = elf::turn_elf_to_raw(&elf_name, parsed_image.bs2_len as usize - 4, parsed_image.bs2_addr).unwrap();
+        fs::remove_file(&elf_name).ok();
+
+        let mut converted = parsed_image;
+        converted.bs2_data  = raw.data;
+        converted.bs2_entry = raw.entry_point;
+        let converted_data = write_and_read(&converted, "convert.img");
+        let reparsed = BSImageRef::parse(&converted_data).unwrap();
+        assert_eq!(reparsed.profile.name, "vwii");
+        assert_eq!(reparsed.unk_stuff, &image.unk_stuff[..]);
+        assert_eq!(reparsed.text_data(1).unwrap(), &image.bs2_data[0x100..0x500]);
+        assert_same_layout(&reparsed, &image);
+    }
+
+    #[test]
+    fn describe_rebuild_round_trip() {
+        let file_data = write_and_read(&unsorted_bss_image(), "describe.img");
+        let doc_file = describe(&file_data, "describe.json");
+
+        let rebuilt = meta::meta_to_image(&doc_file, None, &None);
+        remove_described(&doc_file);
+        let rebuilt_data = write_and_read(&rebuilt.unwrap(), "rebuild.img");
+
+        assert_eq!(meta::sha1_hex(&rebuilt_data), meta::sha1_hex(&file_data));
+    }
+
+    #[test]
+    fn describe_embeds_custom_profile() {
+        let file_data = write_and_read(&unsorted_bss_image(), "custom.img");
+        let mut custom = profile::retail();
+        custom.name = "custom".to_string();
+        custom.mem_bound_end = 0x81800000;
+
+        let doc_file = temp_file("custom.json");
+        meta::write_meta(&doc_file, &BSImageRef::parse_with_profile(&file_data, custom.clone()).unwrap(), None).unwrap();
+
+        // Rebuilds without the profile file around
+        let rebuilt = meta::meta_to_image(&doc_file, None, &None);
+        remove_described(&doc_file);
+        assert_eq!(rebuilt.unwrap().profile, custom);
+    }
+
+    #[test]
+    fn rebuild_rejects_edited_sections() {
+        let file_data = write_and_read(&unsorted_bss_image(), "edited.img");
+        let doc_file = describe(&file_data, "edited.json");
+
+        let mut document: BSImageMeta = meta::read_document(&doc_file).unwrap();
+        document.sections.text[1].len += 0x20;
+        meta::write_document(&doc_file, &document).unwrap();
+
+        let rebuilt = meta::meta_to_image(&doc_file, None, &None);
+        remove_described(&doc_file);
+        assert_eq!(rebuilt.err().unwrap().kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,214 @@
+use crate::bootstage::BSImage;
+
+// Shortest run of printable characters that counts as a string
+const MIN_STRING_LENGTH : usize = 6;
+
+const MONTHS : [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+// What the compiler leaves behind besides the SDK banners
+const COMPILER_MARKERS : [&str; 5] = ["Metrowerks", "CodeWarrior", "MWCC", "GCC:", "ProDG"];
+
+const VERSION_MARKERS : [&str; 5] = ["version", "Version", "VERSION", "build", "IPL"];
+
+pub struct FoundString {
+    pub addr: u32,
+    pub text: String,
+}
+
+// Every SDK library registers a banner like
+// "<< RVL_SDK - OS \trelease build: Nov 10 2008 11:47:33 (0x4199_60831) >>"
+pub struct SdkBanner {
+    pub addr: u32,
+    pub sdk: String,
+    pub library: String,
+    pub build: String,
+    pub date: String,
+    pub time: String,
+    // __CWCC__ the library was built with and the compiler build, if the banner has them
+    pub cwcc: Option<u32>,
+    pub compiler_build: Option<u32>,
+}
+
+pub struct BuildInfo {
+    pub banners: Vec<SdkBanner>,
+    pub compilers: Vec<FoundString>,
+    pub versions: Vec<FoundString>,
+    pub dates: Vec<FoundString>,
+}
+
+impl BuildInfo {
+    pub fn is_empty(&self) -> bool {
+        return self.banners.is_empty() && self.compilers.is_empty() && self.versions.is_empty() && self.dates.is_empty();
+    }
+}
+
+fn is_printable(c: u8) -> bool {
+    return (0x20..0x7F).contains(&c) || c == b'\t' || c == b'\n';
+}
+
+// NUL terminated runs of printable ASCII
+pub fn find_strings(data: &[u8], addr: u32) -> Vec<FoundString> {
+    let mut strings = Vec::new();
+    let mut start = 0;
+    for (i, &c) in data.iter().enumerate() {
+        if is_printable(c) {
+            continue;
+        }
+        if c == 0 && i - start >= MIN_STRING_LENGTH {
+            strings.push(FoundString {
+                addr: addr + start as u32,
+                text: String::from_utf8_lossy(&data[start..i]).to_string(),
+            });
+        }
+        start = i + 1;
+    }
+    return strings;
+}
+
+// "Nov 10 2008" as __DATE__ writes it
+fn is_build_date(words: &[&str]) -> bool {
+    return words.len() == 3 &&
+           MONTHS.contains(&words[0]) &&
+           words[1].parse::<u32>().is_ok_and(|day| (1..=31).contains(&day)) &&
+           words[2].len() == 4 && words[2].parse::<u32>().is_ok();
+}
+
+fn contains_build_date(text: &str) -> bool {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    return words.windows(3).any(is_build_date);
+}
+
+// "(0x4199_60831)" or "(0x2301)"
+fn parse_compiler_id(id: &str) -> (Option<u32>, Option<u32>) {
+    let id = id.trim_start_matches('(').trim_end_matches(')');
+    let Some(hex) = id.strip_prefix("0x") else {
+        return (None, None);
+    };
+    return match hex.split_once('_') {
+        Some((cwcc, build)) => (u32::from_str_radix(cwcc, 16).ok(), build.parse().ok()),
+        None                => (u32::from_str_radix(hex, 16).ok(), None),
+    };
+}
+
+pub fn parse_banner(found: &FoundString) -> Option<SdkBanner> {
+    // The string before it may run into the banner if it isn't NUL padded
+    let start = found.text.find("<< ")?;
+    let inner = found.text[start..].trim_end().strip_prefix("<<")?.strip_suffix(">>")?.trim();
+    let (sdk, rest) = inner.split_once(" - ")?;
+    let (library_build, when) = rest.split_once("build:")?;
+
+    // "OS \trelease " -> library "OS", build "release"
+    let mut library_words: Vec<&str> = library_build.split_whitespace().collect();
+    let build = library_words.pop()?;
+    let when: Vec<&str> = when.split_whitespace().collect();
+    if when.len() < 4 || !is_build_date(&when[0..3]) {
+        return None;
+    }
+    let (cwcc, compiler_build) = when.get(4).map(|id| parse_compiler_id(id)).unwrap_or((None, None));
+
+    return Some(SdkBanner {
+        addr:           found.addr + start as u32,
+        sdk:            sdk.trim().to_string(),
+        library:        library_words.join(" "),
+        build:          build.to_string(),
+        date:           when[0..3].join(" "),
+        time:           when[3].to_string(),
+        cwcc,
+        compiler_build,
+    });
+}
+
+// Looks through the data sections of BS2 for SDK banners and other build strings.
+pub fn analyze(image: &BSImage) -> BuildInfo {
+    let mut info = BuildInfo {
+        banners:    Vec::new(),
+        compilers:  Vec::new(),
+        versions:   Vec::new(),
+        dates:      Vec::new(),
+    };
+
+    for i in 0..image.data_addr.len() {
+        let start = image.data_addr[i].wrapping_sub(image.bs2_addr) as usize;
+        let Some(data) = image.bs2_data.get(start..start + image.data_len[i] as usize) else {
+            continue;
+        };
+
+        for found in find_strings(data, image.data_addr[i]) {
+            if let Some(banner) = parse_banner(&found) {
+                info.banners.push(banner);
+            }
+            else if COMPILER_MARKERS.iter().any(|m| found.text.contains(m)) {
+                info.compilers.push(found);
+            }
+            else if VERSION_MARKERS.iter().any(|m| found.text.contains(m)) {
+                info.versions.push(found);
+            }
+            else if contains_build_date(&found.text) {
+                info.dates.push(found);
+            }
+        }
+    }
+    return info;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bootstage::tests::synthetic_image;
+    use crate::profile;
+
+    #[test]
+    fn finds_banners_and_build_strings() {
+        let mut image = synthetic_image(profile::retail(), 0x100, 0x1000, false);
+        let base = image.data_addr[0] - 0x500;
+        // .rodata gets room for the strings, the other data sections stay zero
+        image.data_addr[4] = base + 0x800;
+        image.data_len[4]  = 0x300;
+
+        let strings: [&[u8]; 7] = [
+            b"<< RVL_SDK - OS \trelease build: Nov 10 2008 11:47:33 (0x4199_60831) >>\0\0\0",
+            // No NUL before the banner, so it runs into the string before it
+            b"abc<< RVL_SDK - EXI \tdebug build: Jan  3 2008 09:01:02 (0x2301) >>\0",
+            b"Metrowerks CodeWarrior\0",
+            b"IPL version 4.3\0",
+            b"Built Mar 20 2009\0",
+            b"short\0",
+            b"nothing to see here\0",
+        ];
+        let mut off = 0x800;
+        for s in strings {
+            image.bs2_data[off..off + s.len()].copy_from_slice(s);
+            off += s.len();
+        }
+
+        let info = analyze(&image);
+        assert_eq!(info.banners.len(), 2);
+        let os = &info.banners[0];
+        assert_eq!(os.addr, base + 0x800);
+        assert_eq!((os.sdk.as_str(), os.library.as_str(), os.build.as_str()), ("RVL_SDK", "OS", "release"));
+        assert_eq!((os.date.as_str(), os.time.as_str()), ("Nov 10 2008", "11:47:33"));
+        assert_eq!((os.cwcc, os.compiler_build), (Some(0x4199), Some(60831)));
+
+        let exi = &info.banners[1];
+        assert_eq!(exi.addr, base + 0x800 + strings[0].len() as u32 + 3);
+        assert_eq!((exi.library.as_str(), exi.build.as_str(), exi.date.as_str()), ("EXI", "debug", "Jan 3 2008"));
+        assert_eq!((exi.cwcc, exi.compiler_build), (Some(0x2301), None));
+
+        let texts = |found: &[FoundString]| found.iter().map(|f| f.text.clone()).collect::<Vec<String>>();
+        assert_eq!(texts(&info.compilers), ["Metrowerks CodeWarrior"]);
+        assert_eq!(texts(&info.versions), ["IPL version 4.3"]);
+        assert_eq!(texts(&info.dates), ["Built Mar 20 2009"]);
+        assert!(!info.is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_banners() {
+        let banner = |text: &str| parse_banner(&FoundString { addr: 0, text: text.to_string() });
+        assert!(banner("<< RVL_SDK - OS \trelease build: Nov 10 2008 11:47:33 (0x4199_60831) >>").is_some());
+        assert!(banner("<< RVL_SDK - OS \trelease build: Nov 10 2008 11:47:33 >>").is_some_and(|b| b.cwcc.is_none()));
+        assert!(banner("<< RVL_SDK - OS \trelease build: Foo 10 2008 11:47:33 >>").is_none());
+        assert!(banner("<< RVL_SDK - OS \trelease build: Nov 10 2008 >>").is_none());
+        assert!(banner("<< RVL_SDK OS release build: Nov 10 2008 11:47:33 >>").is_none());
+        assert!(banner("<< RVL_SDK - OS \trelease build: Nov 10 2008 11:47:33").is_none());
+    }
+}
@@ -0,0 +1,385 @@
+use std::fmt;
+
+use crate::bootstage::BSImageRef;
+use crate::elf;
+use crate::progress;
+use crate::project;
+use crate::symbols::{self, Symbol, SymbolKind, SymbolMap};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MatchClass {
+    Exact,
+    // Only fields a relocation fills in differ
+    Equivalent,
+    Mismatched,
+    // Not in the built ELF
+    Missing,
+}
+
+impl fmt::Display for MatchClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            MatchClass::Exact       => "exact",
+            MatchClass::Equivalent  => "equivalent",
+            MatchClass::Mismatched  => "mismatched",
+            MatchClass::Missing     => "missing",
+        };
+        return write!(f, "{}", name);
+    }
+}
+
+// First instruction that differs after masking, None where one function has already ended
+pub struct Difference {
+    pub offset: u32,
+    pub original: Option<u32>,
+    pub built: Option<u32>,
+}
+
+pub struct FunctionMatch<'a> {
+    pub symbol: &'a Symbol,
+    pub class: MatchClass,
+    pub difference: Option<Difference>,
+}
+
+// The relocated field of an instruction
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Field {
+    // b/bl target (R_PPC_REL24)
+    Branch,
+    // lis rD, sym@ha / sym@h
+    High(u32),
+    // addi, ori or a load/store with the @l half, off the register a lis loaded
+    Low(u32),
+    // addi or a load/store off r13 (0) or r2 (1) (R_PPC_EMB_SDA21)
+    SmallData(usize),
+}
+
+fn is_d_form_memory_or_addi(opcode: u32) -> bool {
+    // addi, then the integer and float loads/stores (lwz through stfdu)
+    return opcode == 14 || (32..=55).contains(&opcode);
+}
+
+fn writes_gpr(opcode: u32) -> bool {
+    // addi, lwz(u), lbz(u), lhz(u), lha(u), lmw
+    return opcode == 14 || (32..=35).contains(&opcode) || (40..=43).contains(&opcode) || opcode == 46;
+}
+
+// Clears the field a relocation fills in and says which one it was. hi has a bit set for every
+// register loaded by lis that hasn't been overwritten by its @l half yet.
+fn mask_instruction(word: u32, hi: &mut u32) -> (u32, Option<Field>) {
+    let opcode = word >> 26;
+    let rd = (word >> 21) & 31;
+    let ra = (word >> 16) & 31;
+
+    if opcode == 18 {
+        return (word & 0xFC00_0003, Some(Field::Branch));
+    }
+    if opcode == 15 && ra == 0 {
+        *hi |= 1 << rd;
+        return (word & 0xFFFF_0000, Some(Field::High(rd)));
+    }
+    // ori rA, rS, sym@l
+    if opcode == 24 && *hi & (1 << rd) != 0 {
+        if ra == rd {
+            *hi &= !(1 << rd);
+        }
+        return (word & 0xFFFF_0000, Some(Field::Low(rd)));
+    }
+    if is_d_form_memory_or_addi(opcode) && ra != 0 {
+        if ra == 13 || ra == 2 {
+            return (word & 0xFFFF_0000, Some(Field::SmallData(if ra == 13 { 0 } else { 1 })));
+        }
+        if *hi & (1 << ra) != 0 {
+            if writes_gpr(opcode) && rd == ra {
+                *hi &= !(1 << ra);
+            }
+            return (word & 0xFFFF_0000, Some(Field::Low(ra)));
+        }
+    }
+    return (word, None);
+}
+
+fn branch_target(word: u32, addr: u32) -> u32 {
+    let offset = (((word & 0x03FF_FFFC) << 6) as i32 >> 6) as u32;
+    return if word & 2 != 0 { offset } else { addr.wrapping_add(offset) };
+}
+
+// Address a lis and its @l half load
+fn low_address(high: u32, word: u32) -> u32 {
+    if word >> 26 == 24 {
+        return (high << 16) | (word & 0xFFFF);
+    }
+    return (high << 16).wrapping_add((word & 0xFFFF) as i16 as u32);
+}
+
+// r13 and r2 the way __start sets them up: lis rN, base@ha followed by addi (or ori) rN, rN, base@l
+fn sda_bases(words: &[u32]) -> [Option<u32>; 2] {
+    let mut bases = [None; 2];
+    let mut high = [None; 2];
+    for &word in words {
+        let (opcode, rd, ra) = (word >> 26, (word >> 21) & 31, (word >> 16) & 31);
+        for (n, register) in [13, 2].into_iter().enumerate() {
+            if opcode == 15 && ra == 0 && rd == register {
+                high[n] = Some(word & 0xFFFF);
+            }
+            else if (opcode == 14 || opcode == 24) && rd == register && ra == register && bases[n].is_none() {
+                bases[n] = high[n].map(|high| low_address(high, word));
+            }
+        }
+    }
+    return bases;
+}
+
+fn words(data: &[u8]) -> Vec<u32> {
+    return data.chunks_exact(4).map(|w| u32::from_be_bytes([w[0], w[1], w[2], w[3]])).collect();
+}
+
+// One function as the original or the built ELF has it
+struct Side<'a> {
+    data: &'a [u8],
+    addr: u32,
+    symbols: &'a SymbolMap,
+    // r13 and r2, if known
+    bases: [Option<u32>; 2],
+}
+
+// The same address, or the same place in the same symbol on both sides. Where a side has no target
+// or no symbol for it, that's only known for identical instructions.
+fn same_target(original: &Side, original_target: Option<u32>, built: &Side, built_target: Option<u32>, identical: bool) -> bool {
+    let (Some(original_target), Some(built_target)) = (original_target, built_target) else {
+        return identical;
+    };
+    if original_target == built_target {
+        return true;
+    }
+    return match (original.symbols.describe(original_target), built.symbols.describe(built_target)) {
+        (Some(original_symbol), Some(built_symbol)) => original_symbol == built_symbol,
+        _                                           => identical,
+    };
+}
+
+// Compares one function. The relocated fields may differ where they point into the same symbol on both sides:
+// branch targets, lis with its @l half (the whole address), and small data off r13/r2.
+// A lis that never meets its @l half has to be the same on both sides.
+fn compare_function(original: &Side, built: &Side) -> (MatchClass, Option<Difference>) {
+    if original.data == built.data {
+        return (MatchClass::Exact, None);
+    }
+
+    let original_words = words(original.data);
+    let built_words = words(built.data);
+    let mut hi = 0;
+    // lis values per register, and where a lis that differs is still waiting for its @l half
+    let mut high = [(0u32, 0u32); 32];
+    let mut pending: [Option<u32>; 32] = [None; 32];
+    let mismatch = |offset: u32| {
+        let word = |words: &[u32]| words.get(offset as usize / 4).copied();
+        return (MatchClass::Mismatched, Some(Difference { offset, original: word(&original_words), built: word(&built_words) }));
+    };
+
+    for i in 0..original_words.len().max(built_words.len()) {
+        let offset = i as u32 * 4;
+        let (Some(&original_word), Some(&built_word)) = (original_words.get(i), built_words.get(i)) else {
+            return pending.iter().flatten().min().map_or(mismatch(offset), |&lis| mismatch(lis));
+        };
+        let mut built_hi = hi;
+        let (original_masked, field) = mask_instruction(original_word, &mut hi);
+        if original_masked != mask_instruction(built_word, &mut built_hi).0 {
+            return mismatch(offset);
+        }
+
+        let same = match field {
+            None                        => true,
+            Some(Field::Branch)         => same_target(original, Some(branch_target(original_word, original.addr.wrapping_add(offset))),
+                                                       built, Some(branch_target(built_word, built.addr.wrapping_add(offset))), original_word == built_word),
+            Some(Field::High(rd))       => {
+                if let Some(lis) = pending[rd as usize] {
+                    return mismatch(lis);
+                }
+                high[rd as usize] = (original_word & 0xFFFF, built_word & 0xFFFF);
+                if original_word != built_word {
+                    pending[rd as usize] = Some(offset);
+                }
+                true
+            },
+            Some(Field::Low(ra))        => {
+                let (original_high, built_high) = high[ra as usize];
+                pending[ra as usize] = None;
+                same_target(original, Some(low_address(original_high, original_word)), built, Some(low_address(built_high, built_word)), false)
+            },
+            Some(Field::SmallData(n))   => {
+                let target = |side: &Side, word: u32| side.bases[n].map(|base| base.wrapping_add((word & 0xFFFF) as i16 as u32));
+                same_target(original, target(original, original_word), built, target(built, built_word), original_word == built_word)
+            },
+        };
+        if !same {
+            return mismatch(offset);
+        }
+    }
+    if let Some(&lis) = pending.iter().flatten().min() {
+        return mismatch(lis);
+    }
+    return (MatchClass::Equivalent, None);
+}
+
+// Every sized function in a code section of the original, compared by name against the built ELF
+pub fn compare_functions<'a>(image: &BSImageRef, symbols: &'a SymbolMap, built_data: &[u8]) -> std::io::Result<Vec<FunctionMatch<'a>>> {
+    let header = elf::read_elf32_hdr(built_data)?;
+    let built_sections = elf::read_elf32_sec_hdrs(built_data, &header)?;
+    let (_, built) = symbols::read_elf(built_data)?;
+    let built_symbols = SymbolMap::new(built.clone());
+    let built = progress::by_name(&built);
+    let code: Vec<project::ProjectSection> = project::sections(image).into_iter().filter(|s| s.kind == "code").collect();
+
+    let original_code: Vec<u8> = code.iter().filter_map(|s| image.section_data(s.addr, s.len)).flatten().copied().collect();
+    let built_code: Vec<u8> = built_sections.iter().filter(|s| s.sh_flags & elf::SHF_EXECINSTR != 0)
+                                            .filter_map(|s| elf::data_at(built_data, &built_sections, s.sh_addr, s.sh_size)).flatten().copied().collect();
+    let (original_bases, built_bases) = (sda_bases(&words(&original_code)), sda_bases(&words(&built_code)));
+
+    let mut matches = Vec::new();
+    for symbol in symbols.symbols.iter().filter(|s| s.kind == SymbolKind::Function && s.size != 0) {
+        if !code.iter().any(|s| symbol.addr >= s.addr && symbol.addr - s.addr < s.len) {
+            continue;
+        }
+        let Some(original) = image.section_data(symbol.addr, symbol.size) else {
+            continue;
+        };
+        let built_data = built.get(symbol.name.as_str())
+                              .and_then(|other| Some((other.addr, elf::data_at(built_data, &built_sections, other.addr, other.size)?)));
+        let (class, difference) = match built_data {
+            Some((built_addr, built_data)) => compare_function(&Side { data: original,   addr: symbol.addr, symbols,                  bases: original_bases },
+                                                               &Side { data: built_data, addr: built_addr,  symbols: &built_symbols, bases: built_bases }),
+            None                           => (MatchClass::Missing, None),
+        };
+        matches.push(FunctionMatch { symbol, class, difference });
+    }
+    return Ok(matches);
+}
+
+pub fn print_matches(matches: &[FunctionMatch], all: bool) {
+    for class in [MatchClass::Exact, MatchClass::Equivalent, MatchClass::Mismatched, MatchClass::Missing] {
+        println!("{:<12} {}", format!("{}:", class), matches.iter().filter(|m| m.class == class).count());
+    }
+
+    let shown: Vec<&FunctionMatch> = matches.iter().filter(|m| all || m.class != MatchClass::Exact).collect();
+    if shown.is_empty() {
+        return;
+    }
+    println!();
+    for function in shown {
+        let mut line = format!("{:<12} {:#010X} {}", function.class.to_string(), function.symbol.addr, function.symbol.name);
+        if let Some(difference) = &function.difference {
+            let word = |w: Option<u32>| w.map(|w| format!("{:08X}", w)).unwrap_or("(end)".to_string());
+            line += &format!(", first difference at +{:#X} ({:#010X}): {} original, {} built",
+                             difference.offset, function.symbol.addr + difference.offset, word(difference.original), word(difference.built));
+        }
+        println!("{}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol_map(symbols: &[(&str, u32, u32)]) -> SymbolMap {
+        return SymbolMap::new(symbols.iter().map(|(name, addr, size)| {
+            Symbol { name: name.to_string(), addr: *addr, size: *size, kind: SymbolKind::Unknown, section: None, local: false }
+        }).collect());
+    }
+
+    fn bytes(words: &[u32]) -> Vec<u8> {
+        return words.iter().flat_map(|w| w.to_be_bytes()).collect();
+    }
+
+    #[test]
+    fn masks_relocated_fields() {
+        const R3 : u32 = 1 << 3;
+        // (word, lis registers before, masked word, field, lis registers after)
+        let cases: [(u32, u32, u32, Option<Field>, u32); 12] = [
+            (0x48000101, 0,  0x48000001, Some(Field::Branch),       0),     // bl +0x100
+            (0x4BFFFF00, 0,  0x48000000, Some(Field::Branch),       0),     // b -0x100
+            (0x3C608130, 0,  0x3C600000, Some(Field::High(3)),      R3),    // lis r3, 0x8130
+            (0x386305A4, R3, 0x38630000, Some(Field::Low(3)),       0),     // addi r3, r3, 0x5A4
+            (0x80830010, R3, 0x80830000, Some(Field::Low(3)),       R3),    // lwz r4, 0x10(r3)
+            (0x60631234, R3, 0x60630000, Some(Field::Low(3)),       0),     // ori r3, r3, 0x1234
+            (0x806D0008, 0,  0x806D0000, Some(Field::SmallData(0)), 0),     // lwz r3, 0x8(r13)
+            (0xC0220010, 0,  0xC0220000, Some(Field::SmallData(1)), 0),     // lfs f1, 0x10(r2)
+            (0x386305A4, 0,  0x386305A4, None,                      0),     // addi r3, r3, 0x5A4 without a lis
+            (0x38600005, R3, 0x38600005, None,                      R3),    // li r3, 5
+            (0x90010008, 0,  0x90010008, None,                      0),     // stw r0, 0x8(r1)
+            (0x60631234, 0,  0x60631234, None,                      0),     // ori r3, r3, 0x1234 without a lis
+        ];
+        for (word, hi_before, masked, field, hi_after) in cases {
+            let mut hi = hi_before;
+            assert_eq!(mask_instruction(word, &mut hi), (masked, field), "{:08X}", word);
+            assert_eq!(hi, hi_after, "{:08X}", word);
+        }
+    }
+
+    // Original words, built words, class and offset of the first difference
+    type Case<'a> = (&'a [u32], &'a [u32], MatchClass, Option<u32>);
+
+    #[test]
+    fn classifies_functions() {
+        // The built ELF has everything somewhere else, and its small data at other offsets
+        let original_symbols = symbol_map(&[("fn_a", 0x81300100, 0x40), ("fn_b", 0x81300200, 0x10), ("fn_c", 0x81300300, 0x10),
+                                            ("obj_x", 0x81380000, 0x10), ("obj_y", 0x81380010, 0x08),
+                                            ("s_a", 0x81390008, 0x04), ("s_b", 0x8139000C, 0x04)]);
+        let built_symbols = symbol_map(&[("fn_a", 0x80003100, 0x40), ("fn_b", 0x80004000, 0x10), ("fn_c", 0x80005000, 0x10),
+                                         ("obj_x", 0x80100000, 0x10), ("obj_y", 0x80100010, 0x08),
+                                         ("s_a", 0x80200010, 0x04), ("s_b", 0x80200014, 0x04)]);
+
+        let cases: [Case; 11] = [
+            // Exact
+            (&[0x60000000, 0x4E800020], &[0x60000000, 0x4E800020], MatchClass::Exact, None),
+            // bl fn_b, lis/addi obj_y, lwz s_a off r13, all moved
+            (&[0x48000101, 0x3C608138, 0x38630010, 0x808D0008, 0x4E800020],
+             &[0x48000F01, 0x3C608010, 0x38630010, 0x808D0010, 0x4E800020], MatchClass::Equivalent, None),
+            // lis/ori obj_x
+            (&[0x3C608138, 0x60630000], &[0x3C608010, 0x60630000], MatchClass::Equivalent, None),
+            // A lis that's a constant (lis r0, 0x4330 vs 0x4338, stored to the stack)
+            (&[0x3C004330, 0x90010008, 0x4E800020], &[0x3C004338, 0x90010008, 0x4E800020], MatchClass::Mismatched, Some(0)),
+            // lwz r3, 0x8(r13) vs lwz r3, 0xC(r13): s_a vs no symbol
+            (&[0x806D0008], &[0x806D000C], MatchClass::Mismatched, Some(0)),
+            // s_a vs s_b
+            (&[0x806D0008], &[0x806D0014], MatchClass::Mismatched, Some(0)),
+            // lis/addi obj_y vs obj_x
+            (&[0x3C608138, 0x38630010], &[0x3C608010, 0x38630000], MatchClass::Mismatched, Some(4)),
+            // Identical bytes are exact, wherever the function went
+            (&[0x3C608138, 0x38630010], &[0x3C608138, 0x38630010], MatchClass::Exact, None),
+            // bl fn_b vs bl fn_c
+            (&[0x48000101], &[0x48002F01], MatchClass::Mismatched, Some(0)),
+            // Built one longer, original one longer
+            (&[0x60000000, 0x4E800020], &[0x60000000, 0x4E800020, 0x60000000], MatchClass::Mismatched, Some(8)),
+            (&[0x60000000, 0x4E800020, 0x60000000], &[0x60000000, 0x4E800020], MatchClass::Mismatched, Some(8)),
+        ];
+        for (i, (original_words, built_words, class, offset)) in cases.iter().enumerate() {
+            let (original_data, built_data) = (bytes(original_words), bytes(built_words));
+            let original = Side { data: &original_data, addr: 0x81300100, symbols: &original_symbols, bases: [Some(0x81390000), None] };
+            let built = Side { data: &built_data, addr: 0x80003100, symbols: &built_symbols, bases: [Some(0x80200000), None] };
+            let (found, difference) = compare_function(&original, &built);
+            assert_eq!((found, difference.as_ref().map(|d| d.offset)), (*class, *offset), "case {}", i);
+        }
+
+        // Which words differ, and the end of the shorter one
+        let (original_data, built_data) = (bytes(&[0x60000000]), bytes(&[0x60000000, 0x4E800020]));
+        let original = Side { data: &original_data, addr: 0x81300100, symbols: &original_symbols, bases: [None; 2] };
+        let built = Side { data: &built_data, addr: 0x80003100, symbols: &built_symbols, bases: [None; 2] };
+        let difference = compare_function(&original, &built).1.unwrap();
+        assert_eq!((difference.original, difference.built), (None, Some(0x4E800020)));
+
+        // Without a known r13 only identical small data accesses match
+        let (original_data, built_data) = (bytes(&[0x806D0008]), bytes(&[0x806D0010]));
+        let original = Side { data: &original_data, addr: 0x81300100, symbols: &original_symbols, bases: [None; 2] };
+        let built = Side { data: &built_data, addr: 0x80003100, symbols: &built_symbols, bases: [Some(0x80200000), None] };
+        assert_eq!(compare_function(&original, &built).0, MatchClass::Mismatched);
+    }
+
+    #[test]
+    fn finds_small_data_bases() {
+        // lis r13, 0x8139; addi r13, r13, -0x8000 and lis r2, 0x8138; ori r2, r2, 0x8000, with an unrelated lis in between
+        let words = [0x3DA08139, 0x3C608000, 0x39AD8000, 0x3C408138, 0x60428000];
+        assert_eq!(sda_bases(&words), [Some(0x81388000), Some(0x81388000)]);
+        assert_eq!(sda_bases(&[0x39AD8000]), [None, None]);
+    }
+}
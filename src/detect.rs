@@ -0,0 +1,301 @@
+use std::fmt;
+
+use crate::bootstage::{BSImageRef, HEADER_LENGTH};
+use crate::dol;
+use crate::gcipl;
+use crate::profile;
+use crate::wad;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FormatKind {
+    WiiBootStage,
+    VWiiBootStage,
+    GameCubeIpl,
+    Dol,
+    Elf,
+    Apploader,
+    Wad,
+    Raw,
+}
+
+impl fmt::Display for FormatKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            FormatKind::WiiBootStage    => "Wii IPL BootStage",
+            FormatKind::VWiiBootStage   => "vWii BootStage",
+            FormatKind::GameCubeIpl     => "GameCube IPL ROM",
+            FormatKind::Dol             => "DOL executable",
+            FormatKind::Elf             => "ELF",
+            FormatKind::Apploader       => "Disc apploader",
+            FormatKind::Wad             => "WAD",
+            FormatKind::Raw             => "Raw binary",
+        };
+        return write!(f, "{}", name);
+    }
+}
+
+pub struct Detection {
+    pub kind: FormatKind,
+    // 0-100
+    pub confidence: u32,
+    pub reasons: Vec<String>,
+}
+
+type Sniffer = fn(&[u8]) -> Option<Detection>;
+
+// Every known format, each sniffer only answers when it sees something of its own.
+const DETECTORS: [Sniffer; 7] = [
+    sniff_elf,
+    sniff_wad,
+    sniff_bootstage,
+    sniff_gc_ipl,
+    sniff_dol,
+    sniff_apploader,
+    sniff_raw,
+];
+
+fn read_u32(file_data: &[u8], offset: usize) -> u32 {
+    return match file_data.get(offset..offset + 4) {
+        Some(temp) => u32::from_be_bytes([temp[0], temp[1], temp[2], temp[3]]),
+        None       => 0,
+    };
+}
+
+fn in_mem1(addr: u32) -> bool {
+    return (0x80000000..0x81800000).contains(&addr);
+}
+
+fn sniff_elf(file_data: &[u8]) -> Option<Detection> {
+    if !file_data.starts_with(b"\x7FELF") {
+        return None;
+    }
+
+    let mut detection = Detection { kind: FormatKind::Elf, confidence: 60, reasons: vec!["ELF magic".to_string()] };
+
+    if file_data.get(4) == Some(&1) && file_data.get(5) == Some(&2) {
+        detection.confidence += 20;
+        detection.reasons.push("32-bit big-endian".to_string());
+    }
+    if file_data.len() >= 0x14 && u16::from_be_bytes([file_data[0x12], file_data[0x13]]) == 20 {
+        detection.confidence += 20;
+        detection.reasons.push("machine is PowerPC".to_string());
+    }
+    else {
+        detection.reasons.push("machine is not PowerPC".to_string());
+    }
+
+    return Some(detection);
+}
+
+fn sniff_wad(file_data: &[u8]) -> Option<Detection> {
+    let header = wad::read_header(file_data).ok()?;
+
+    let mut detection = Detection { kind: FormatKind::Wad, confidence: 60, reasons: vec![format!("WAD header of type {:#06X}", header.wad_type)] };
+
+    match wad::parse(file_data) {
+        Ok(parsed) => {
+            detection.confidence += 35;
+            detection.reasons.push(format!("ticket and TMD parse ({} contents)", parsed.tmd.contents.len()));
+        }
+        Err(e) => detection.reasons.push(format!("does not parse: {}", e)),
+    }
+
+    return Some(detection);
+}
+
+fn sniff_bootstage(file_data: &[u8]) -> Option<Detection> {
+    if file_data.len() < HEADER_LENGTH || read_u32(file_data, 0x00) != HEADER_LENGTH as u32 {
+        return None;
+    }
+
+    let mut detection = Detection { kind: FormatKind::WiiBootStage, confidence: 20, reasons: vec!["BS1 starts right after the 0x100 byte header".to_string()] };
+
+    let bs1_len = read_u32(file_data, 0x90);
+    if read_u32(file_data, 0x1C) == HEADER_LENGTH as u32 + bs1_len {
+        detection.confidence += 20;
+        detection.reasons.push("BS2 follows BS1".to_string());
+    }
+    if in_mem1(read_u32(file_data, 0x48)) && in_mem1(read_u32(file_data, 0x64)) {
+        detection.confidence += 20;
+        detection.reasons.push("BS1/BS2 load addresses are in MEM1".to_string());
+    }
+
+    let profile = profile::detect(file_data);
+    if profile::header_fits(file_data, &profile) {
+        detection.reasons.push(format!("header fits the \"{}\" profile", profile.name));
+        if profile.name == "vwii" {
+            detection.kind = FormatKind::VWiiBootStage;
+        }
+    }
+
+    // A BootStage header is laid out like a DOL header, the BS2 section tables are what tell them apart
+    match BSImageRef::parse_with_profile(file_data, profile) {
+        Ok(_)  => {
+            detection.confidence += 40;
+            detection.reasons.push("section tables found in BS2".to_string());
+        }
+        Err(e) => detection.reasons.push(format!("does not parse: {}", e)),
+    }
+
+    return Some(detection);
+}
+
+fn sniff_gc_ipl(file_data: &[u8]) -> Option<Detection> {
+    let copyright = file_data.get(0..0x100)?;
+    if !copyright.starts_with(b"(C) ") || !copyright.windows(8).any(|w| w == b"Nintendo") {
+        return None;
+    }
+
+    let mut detection = Detection { kind: FormatKind::GameCubeIpl, confidence: 70, reasons: vec!["Nintendo copyright header".to_string()] };
+
+    if file_data.len() == gcipl::IPL_SIZE {
+        detection.confidence += 25;
+        detection.reasons.push("file is exactly 2 MiB".to_string());
+    }
+    else {
+        detection.reasons.push(format!("file is {:#X} bytes, expected {:#X}", file_data.len(), gcipl::IPL_SIZE));
+    }
+
+    return Some(detection);
+}
+
+fn sniff_dol(file_data: &[u8]) -> Option<Detection> {
+    let header = dol::read_header(file_data)?;
+
+    let mut detection = Detection { kind: FormatKind::Dol, confidence: 50, reasons: vec!["section offsets and sizes fit in the file".to_string()] };
+
+    let sections = header.text_addr.iter().chain(header.data_addr.iter())
+                         .zip(header.text_size.iter().chain(header.data_size.iter()))
+                         .filter(|(_, &size)| size != 0)
+                         .collect::<Vec<_>>();
+
+    if sections.iter().all(|(&addr, _)| in_mem1(addr)) {
+        detection.confidence += 20;
+        detection.reasons.push("all sections load into MEM1".to_string());
+    }
+    if header.text_addr.iter().zip(header.text_size.iter())
+             .any(|(&addr, &size)| header.entry_point >= addr && header.entry_point < addr.wrapping_add(size)) {
+        detection.confidence += 25;
+        detection.reasons.push("entry point is inside a text section".to_string());
+    }
+
+    return Some(detection);
+}
+
+fn sniff_apploader(file_data: &[u8]) -> Option<Detection> {
+    let date = file_data.get(0..10)?;
+    let is_date = date.iter().enumerate().all(|(i, &c)| if i == 4 || i == 7 { c == b'/' } else { c.is_ascii_digit() });
+    if !is_date {
+        return None;
+    }
+
+    let mut detection = Detection { kind: FormatKind::Apploader, confidence: 40, reasons: vec!["build date string at the start".to_string()] };
+
+    let entry = read_u32(file_data, 0x10);
+    let size = read_u32(file_data, 0x14) as usize;
+    let trailer = read_u32(file_data, 0x18) as usize;
+
+    if in_mem1(entry) {
+        detection.confidence += 25;
+        detection.reasons.push(format!("entry point {:#010X} is in MEM1", entry));
+    }
+    if 0x20 + size + trailer <= file_data.len() && size != 0 {
+        detection.confidence += 30;
+        detection.reasons.push("size and trailer size fit in the file".to_string());
+    }
+
+    return Some(detection);
+}
+
+fn sniff_raw(file_data: &[u8]) -> Option<Detection> {
+    return Some(Detection {
+        kind: FormatKind::Raw,
+        confidence: 10,
+        reasons: vec![format!("{:#X} bytes without a recognised header", file_data.len())],
+    });
+}
+
+// All candidate formats, most likely first.
+pub fn detect_all(file_data: &[u8]) -> Vec<Detection> {
+    let mut detections: Vec<Detection> = DETECTORS.iter().filter_map(|sniff| sniff(file_data)).collect();
+    detections.sort_by_key(|d| std::cmp::Reverse(d.confidence));
+    return detections;
+}
+
+pub fn detect(file_data: &[u8]) -> Detection {
+    return detect_all(file_data).remove(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apploader::tests::synthetic_apploader;
+    use crate::bootstage::tests::{synthetic_image, synthetic_vwii, write_and_read};
+    use crate::gcipl::tests::synthetic_rom;
+
+    fn push_u32s(buffer: &mut Vec<u8>, values: &[u32]) {
+        for value in values {
+            buffer.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+
+    // A DOL with .text0 right after the header and .data0 right after it, which is also
+    // where a BootStage has BS1 and BS2
+    fn minimal_dol() -> Vec<u8> {
+        let mut offsets = [0u32; dol::TEXT_COUNT + dol::DATA_COUNT];
+        let mut addrs = offsets;
+        let mut sizes = offsets;
+        (offsets[0], addrs[0], sizes[0]) = (0x100, 0x80003100, 0x20);
+        (offsets[dol::TEXT_COUNT], addrs[dol::TEXT_COUNT], sizes[dol::TEXT_COUNT]) = (0x120, 0x80004000, 0x20);
+
+        let mut file_data = Vec::new();
+        push_u32s(&mut file_data, &offsets);
+        push_u32s(&mut file_data, &addrs);
+        push_u32s(&mut file_data, &sizes);
+        push_u32s(&mut file_data, &[0x80005000, 0x100, 0x80003100]);
+        file_data.resize(0x100, 0);
+        file_data.extend_from_slice(&[0x60; 0x40]);
+        return file_data;
+    }
+
+    fn minimal_elf() -> Vec<u8> {
+        let mut file_data = vec![0u8; 0x34];
+        file_data[..7].copy_from_slice(b"\x7FELF\x01\x02\x01");
+        file_data[0x12..0x14].copy_from_slice(&20u16.to_be_bytes());
+        return file_data;
+    }
+
+    fn minimal_wad() -> Vec<u8> {
+        let mut file_data = Vec::new();
+        push_u32s(&mut file_data, &[wad::HEADER_LENGTH as u32, (wad::TYPE_INSTALLABLE as u32) << 16, 0, 0, 0, 0, 0, 0]);
+        return file_data;
+    }
+
+    #[test]
+    fn detects_each_format() {
+        let table = [
+            (write_and_read(&synthetic_image(profile::retail(), 0x3FC, 0x1000, true), "detect-wii.img"), FormatKind::WiiBootStage),
+            (write_and_read(&synthetic_vwii(0x3FC, true), "detect-vwii.img"), FormatKind::VWiiBootStage),
+            (synthetic_rom(), FormatKind::GameCubeIpl),
+            (minimal_dol(), FormatKind::Dol),
+            (minimal_elf(), FormatKind::Elf),
+            (minimal_wad(), FormatKind::Wad),
+            (synthetic_apploader(), FormatKind::Apploader),
+            (vec![0xFF; 0x200], FormatKind::Raw),
+        ];
+        for (file_data, kind) in table {
+            assert_eq!(detect(&file_data).kind, kind);
+        }
+    }
+
+    #[test]
+    fn dol_is_not_a_bootstage() {
+        // The header reads like a BootStage's (BS1 at 0x100, BS2 after it, both in MEM1),
+        // only the missing BS2 section tables give it away
+        let detections = detect_all(&minimal_dol());
+        let bootstage = detections.iter().find(|d| d.kind == FormatKind::WiiBootStage).unwrap();
+        assert_eq!(bootstage.confidence, 60);
+        assert!(bootstage.reasons.iter().any(|r| r.starts_with("does not parse")));
+        assert_eq!(detections[0].kind, FormatKind::Dol);
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+
+use crate::bootstage::BSImageRef;
+use crate::ppc::{self, Instruction};
+use crate::retools;
+use crate::symbols::{SymbolKind, SymbolMap};
+
+// A stretch of code to disassemble
+pub struct CodeBlock<'a> {
+    pub name: String,
+    pub addr: u32,
+    pub data: &'a [u8],
+}
+
+pub struct Line {
+    pub addr: u32,
+    pub word: u32,
+    // None if the word isn't an instruction
+    pub instruction: Option<Instruction>,
+}
+
+// BS1 and the BS2 text sections, cut down to start..end if given
+pub fn code_blocks<'a>(image: &BSImageRef<'a>, start: Option<u32>, end: Option<u32>) -> Vec<CodeBlock<'a>> {
+    let mut blocks = Vec::new();
+    for block in retools::memory_blocks(image).into_iter().filter(|b| b.perms == "rx") {
+        let Some(data) = block.data else {
+            continue;
+        };
+        let block_end = block.addr + block.len;
+        let from = start.unwrap_or(block.addr).max(block.addr) & !3;
+        let to = end.unwrap_or(block_end).min(block_end);
+        if from >= to {
+            continue;
+        }
+        blocks.push(CodeBlock { name: block.name, addr: from, data: &data[(from - block.addr) as usize..(to - block.addr) as usize] });
+    }
+    return blocks;
+}
+
+pub fn disassemble(block: &CodeBlock) -> Vec<Line> {
+    return block.data.chunks_exact(4).enumerate().map(|(i, w)| {
+        let addr = block.addr + i as u32 * 4;
+        let word = u32::from_be_bytes([w[0], w[1], w[2], w[3]]);
+        Line { addr, word, instruction: ppc::decode(word, addr) }
+    }).collect();
+}
+
+// Label for every address something should be called at: the symbols starting there,
+// and lbl_ names for branch targets inside the blocks that have no symbol.
+pub fn labels(blocks: &[CodeBlock], lines: &[Vec<Line>], symbols: Option<&SymbolMap>) -> BTreeMap<u32, (String, bool)> {
+    let mut labels = BTreeMap::new();
+    for symbol in symbols.map(|s| s.symbols.as_slice()).unwrap_or(&[]) {
+        // A typed symbol wins over a label at the same address
+        let typed = symbol.kind != SymbolKind::Unknown;
+        match labels.get(&symbol.addr) {
+            Some((_, _, true)) => {},
+            _                  => { labels.insert(symbol.addr, (symbol.name.clone(), !symbol.local, typed)); },
+        }
+    }
+    for target in lines.iter().flatten().filter_map(|l| l.instruction.as_ref()?.target()) {
+        let inside = blocks.iter().any(|b| target >= b.addr && target - b.addr < b.data.len() as u32);
+        if inside && !labels.contains_key(&target) {
+            labels.insert(target, (format!("lbl_{:08X}", target), false, false));
+        }
+    }
+    return labels.into_iter().map(|(addr, (name, global, _))| (addr, (name, global))).collect();
+}
+
+// Label at addr, or symbol+offset for addresses inside a sized symbol
+fn name_of(labels: &BTreeMap<u32, (String, bool)>, symbols: Option<&SymbolMap>, addr: u32) -> Option<String> {
+    if let Some((name, _)) = labels.get(&addr) {
+        return Some(name.clone());
+    }
+    return symbols?.describe(addr);
+}
+
+// addr  word  instruction, with the labels on their own lines
+pub fn listing(blocks: &[CodeBlock], lines: &[Vec<Line>], labels: &BTreeMap<u32, (String, bool)>, symbols: Option<&SymbolMap>) -> String {
+    let resolve = |addr: u32| name_of(labels, symbols, addr);
+    let mut text = String::new();
+    for (block, lines) in blocks.iter().zip(lines) {
+        text += &format!("{}:\n", block.name);
+        for line in lines {
+            if let Some((name, _)) = labels.get(&line.addr) {
+                text += &format!("{}:\n", name);
+            }
+            let instruction = match &line.instruction {
+                Some(instruction) => instruction.text(&resolve),
+                None              => format!(".4byte 0x{:08X}", line.word),
+            };
+            text += &format!("  {:08X}  {:08X}  {}\n", line.addr, line.word, instruction);
+        }
+        text += "\n";
+    }
+    return text;
+}
+
+// One assembler line with the address and word as a comment in front, like dtk's asm
+pub fn asm_line(addr: u32, word: u32, text: &str) -> String {
+    return format!("/* {:08X} {:08X} */\t{}\n", addr, word, text);
+}
+
+// Source for powerpc-eabi-as -mgekko -mregnames or mwasm. Every block becomes a section of its own.
+pub fn assembly(blocks: &[CodeBlock], lines: &[Vec<Line>], labels: &BTreeMap<u32, (String, bool)>, symbols: Option<&SymbolMap>) -> String {
+    let resolve = |addr: u32| name_of(labels, symbols, addr);
+    let mut text = String::new();
+    for (block, lines) in blocks.iter().zip(lines) {
+        let section = if block.name.starts_with('.') { block.name.clone() } else { format!(".{}", block.name.to_lowercase()) };
+        text += &format!(".section {}, \"ax\"\n", section);
+        for line in lines {
+            if let Some((name, global)) = labels.get(&line.addr) {
+                text += "\n";
+                if *global {
+                    text += &format!(".global {}\n", name);
+                }
+                text += &format!("{}:\n", name);
+            }
+            let instruction = match &line.instruction {
+                Some(instruction) => instruction.text(&resolve),
+                None              => format!(".4byte 0x{:08X}", line.word),
+            };
+            text += &asm_line(line.addr, line.word, &instruction);
+        }
+        text += "\n";
+    }
+    return text;
+}
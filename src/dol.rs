@@ -6,7 +6,7 @@ pub const DATA_COUNT : usize = 11;
 
 pub const HEADER_LENGTH : usize = 0x100;
 
-struct DOLImage {
+pub struct DOLImage {
     pub text_off:    Vec<u32>,
     pub data_off:    Vec<u32>,
 
@@ -22,6 +22,57 @@ struct DOLImage {
     pub entry_point: u32,
 }
 
+fn read_section_info(file_data: &[u8], offset: usize, count: usize) -> Vec<u32> {
+    return (0..count).map(|i| {
+        let pos = offset + i * 4;
+        u32::from_be_bytes([file_data[pos], file_data[pos + 1], file_data[pos + 2], file_data[pos + 3]])
+    }).collect();
+}
+
+// Reads a DOL header, None if the sections don't fit in the file.
+pub fn read_header(file_data: &[u8]) -> Option<DOLImage> {
+    if file_data.len() < HEADER_LENGTH {
+        return None;
+    }
+
+    let count = TEXT_COUNT + DATA_COUNT;
+    let offsets = read_section_info(file_data, 0x00, count);
+    let addrs   = read_section_info(file_data, count * 4, count);
+    let sizes   = read_section_info(file_data, count * 8, count);
+    let tail    = read_section_info(file_data, count * 12, 3);
+
+    let mut any_section = false;
+    for i in 0..count {
+        if sizes[i] == 0 {
+            continue;
+        }
+        if (offsets[i] as usize) < HEADER_LENGTH || offsets[i] as usize + sizes[i] as usize > file_data.len() {
+            return None;
+        }
+        any_section = true;
+    }
+    if !any_section {
+        return None;
+    }
+
+    return Some(DOLImage {
+        text_off:    offsets[..TEXT_COUNT].to_vec(),
+        data_off:    offsets[TEXT_COUNT..].to_vec(),
+
+        text_addr:   addrs[..TEXT_COUNT].to_vec(),
+        data_addr:   addrs[TEXT_COUNT..].to_vec(),
+
+        text_size:   sizes[..TEXT_COUNT].to_vec(),
+        data_size:   sizes[TEXT_COUNT..].to_vec(),
+
+        bss_addr:    tail[0],
+        bss_size:    tail[1],
+
+        entry_point: tail[2],
+    });
+}
+
+#[allow(clippy::unused_io_amount, clippy::ptr_arg, clippy::needless_range_loop)]
 fn write_section_info(mut writer: impl Write, for_text: &Vec<u32>, for_data: &Vec<u32>) {
     for i in 0..TEXT_COUNT {
         writer.write(&u32::to_be_bytes(for_text[i])).ok();
@@ -31,6 +82,7 @@ fn write_section_info(mut writer: impl Write, for_text: &Vec<u32>, for_data: &Ve
     }
 }
 
+#[allow(clippy::unused_io_amount)]
 fn write_padding(mut writer: impl Write + Seek, stopper: u32) {
     let cur_off = writer.stream_position().unwrap() as usize;
     let size = stopper as usize - cur_off;
@@ -38,7 +90,7 @@ fn write_padding(mut writer: impl Write + Seek, stopper: u32) {
     writer.write(&temp).ok();
 }
 
-#[allow(dead_code)]
+#[allow(dead_code, clippy::unused_io_amount, clippy::needless_borrow, clippy::needless_borrows_for_generic_args, clippy::ptr_arg, clippy::too_many_arguments)]
 pub fn turn_raw_to_dol(file_name: &String,
                         raw_data: &Vec<u8>,
                         text_addr: &Vec<u32>,
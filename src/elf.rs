@@ -1,8 +1,8 @@
 use std::fs;
-use std::io::{prelude::*, SeekFrom};
+use std::io::{Error, ErrorKind};
 
 pub struct Elf32Hdr {
-    pub e_ident: Vec<u8>,
+    pub e_ident: [u8; 16],
     pub e_type: u16,
     pub e_machine: u16,
     pub e_version: u32,
@@ -41,6 +41,41 @@ pub struct Elf32Phdr {
     pub p_align: u32,
 }
 
+pub struct Elf32Shdr {
+    pub sh_name: u32,
+    pub sh_type: u32,
+    pub sh_flags: u32,
+    pub sh_addr: u32,
+    pub sh_offset: u32,
+    pub sh_size: u32,
+    pub sh_link: u32,
+    pub sh_info: u32,
+    pub sh_addralign: u32,
+    pub sh_entsize: u32,
+
+    // Resolved from .shstrtab
+    pub name: String,
+}
+
+pub struct Elf32Sym {
+    pub name: String,
+    pub st_value: u32,
+    pub st_size: u32,
+    pub st_info: u8,
+    pub st_other: u8,
+    pub st_shndx: u16,
+}
+
+impl Elf32Sym {
+    pub fn bind(&self) -> u8 {
+        return self.st_info >> 4;
+    }
+
+    pub fn kind(&self) -> u8 {
+        return self.st_info & 0xF;
+    }
+}
+
 pub struct RawELF {
     pub data: Vec<u8>,
 
@@ -48,112 +83,200 @@ pub struct RawELF {
     pub entry_point: u32,
 }
 
-#[allow(dead_code)]
-fn read_u32(mut reader: impl Read + Seek) -> u32 {
-    let mut temp = [0u8;4];
-    reader.read_exact(&mut temp).ok();
-    return u32::from_be_bytes(temp);
+pub const ELF32_HDR_SIZE : usize = 0x34;
+pub const ELF32_PHDR_SIZE : usize = 0x20;
+pub const ELF32_SHDR_SIZE : usize = 0x28;
+pub const ELF32_SYM_SIZE : usize = 0x10;
+
+pub const SHT_SYMTAB : u32 = 2;
+pub const SHN_UNDEF : u16 = 0;
+pub const SHN_LORESERVE : u16 = 0xFF00;
+pub const STB_LOCAL : u8 = 0;
+pub const STB_GLOBAL : u8 = 1;
+pub const STT_NOTYPE : u8 = 0;
+pub const STT_OBJECT : u8 = 1;
+pub const STT_FUNC : u8 = 2;
+pub const STT_SECTION : u8 = 3;
+
+fn invalid_elf(message: &str) -> Error {
+    return Error::new(ErrorKind::InvalidData, message.to_string());
 }
 
-#[allow(dead_code)]
-fn read_u16(mut reader: impl Read + Seek) -> u16 {
-    let mut temp = [0u8;2];
-    reader.read_exact(&mut temp).ok();
-    return u16::from_be_bytes(temp);
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    return u32::from_be_bytes([buffer[offset], buffer[offset + 1], buffer[offset + 2], buffer[offset + 3]]);
 }
 
-#[allow(dead_code)]
-fn read_u8s_from_offset(mut reader: impl Read + Seek, size: usize, offset: u64) -> Vec<u8> {
-    let mut temp = vec![0u8;size];
-    reader.seek(SeekFrom::Start(offset)).ok();
-    reader.read_exact(&mut temp).ok();
-    return temp;
+fn read_u16(buffer: &[u8], offset: usize) -> u16 {
+    return u16::from_be_bytes([buffer[offset], buffer[offset + 1]]);
 }
 
-#[allow(dead_code)]
-fn read_u8s(mut reader: impl Read + Seek, size: usize) -> Vec<u8> {
-    let mut temp = vec![0u8;size];
-    reader.read_exact(&mut temp).ok();
-    return temp;
-}
+pub fn read_elf32_hdr(file_data: &[u8]) -> std::io::Result<Elf32Hdr> {
+    if file_data.len() < ELF32_HDR_SIZE {
+        return Err(invalid_elf("Invalid ELF File!"));
+    }
 
-fn read_elf32_hdr(mut reader: impl Read + Seek) -> Elf32Hdr {
-    return Elf32Hdr {
-        e_ident:        read_u8s(&mut reader, 16),
-        e_type:         read_u16(&mut reader),
-        e_machine:      read_u16(&mut reader),
-        e_version:      read_u32(&mut reader),
+    let mut e_ident = [0u8; 16];
+    e_ident.copy_from_slice(&file_data[0..16]);
 
-        e_entry:        read_u32(&mut reader),
-        e_phoff:        read_u32(&mut reader),
-        e_shoff:        read_u32(&mut reader),
+    return Ok(Elf32Hdr {
+        e_ident,
+        e_type:         read_u16(file_data, 0x10),
+        e_machine:      read_u16(file_data, 0x12),
+        e_version:      read_u32(file_data, 0x14),
 
-        e_flags:        read_u32(&mut reader),
+        e_entry:        read_u32(file_data, 0x18),
+        e_phoff:        read_u32(file_data, 0x1C),
+        e_shoff:        read_u32(file_data, 0x20),
 
-        e_ehsize:       read_u16(&mut reader),
+        e_flags:        read_u32(file_data, 0x24),
 
-        e_phentsize:    read_u16(&mut reader),
-        e_phnum:        read_u16(&mut reader),
-        
-        e_shentsize:    read_u16(&mut reader),
-        e_shnum:        read_u16(&mut reader),
+        e_ehsize:       read_u16(file_data, 0x28),
 
-        e_shstrndx:     read_u16(&mut reader)
-    }
+        e_phentsize:    read_u16(file_data, 0x2A),
+        e_phnum:        read_u16(file_data, 0x2C),
+
+        e_shentsize:    read_u16(file_data, 0x2E),
+        e_shnum:        read_u16(file_data, 0x30),
+
+        e_shstrndx:     read_u16(file_data, 0x32),
+    });
 }
 
-fn read_elf32_prg_hdr(mut reader: impl Read + Seek) -> Elf32Phdr {
-    return Elf32Phdr {
-        p_type:     read_u32(&mut reader),
+pub fn read_elf32_prg_hdr(file_data: &[u8], offset: usize) -> std::io::Result<Elf32Phdr> {
+    if offset + ELF32_PHDR_SIZE > file_data.len() {
+        return Err(invalid_elf("ELF program header is out of bounds!"));
+    }
 
-        p_offset:   read_u32(&mut reader),
+    return Ok(Elf32Phdr {
+        p_type:     read_u32(file_data, offset),
 
-        p_vaddr:    read_u32(&mut reader),
-        p_paddr:    read_u32(&mut reader),
+        p_offset:   read_u32(file_data, offset + 0x04),
 
-        p_filesz:   read_u32(&mut reader),
-        p_memsz:    read_u32(&mut reader),
+        p_vaddr:    read_u32(file_data, offset + 0x08),
+        p_paddr:    read_u32(file_data, offset + 0x0C),
 
-        p_flags:    read_u32(&mut reader),
+        p_filesz:   read_u32(file_data, offset + 0x10),
+        p_memsz:    read_u32(file_data, offset + 0x14),
 
-        p_align:    read_u32(&mut reader),
-    }
+        p_flags:    read_u32(file_data, offset + 0x18),
+
+        p_align:    read_u32(file_data, offset + 0x1C),
+    });
 }
 
-fn verify_elf32_hdr(header: &Elf32Hdr) {
-    if  header.e_ident.len() < 16 ||
+fn verify_elf32_hdr(header: &Elf32Hdr) -> std::io::Result<()> {
+    if  &header.e_ident[0..4] != b"\x7FELF" ||
         header.e_ident[4] != 1 ||
         header.e_ident[6] != 1 ||
         header.e_version != 1 ||
         header.e_type != 2
     {
-        panic!("Invalid ELF File!");
+        return Err(invalid_elf("Invalid ELF File!"));
     }
 
     if header.e_machine != 20 {
-        panic!("Not PowerPC ELF!");
+        return Err(invalid_elf("Not PowerPC ELF!"));
     }
 
     if header.e_phnum == 0 || header.e_phoff == 0 {
-        panic!("This ELF got nothing!");
+        return Err(invalid_elf("This ELF got nothing!"));
     }
+
+    return Ok(());
 }
 
-#[allow(dead_code)]
-pub fn turn_elf_to_raw(file_name: &String, image_size: usize, base_addr: u32) -> RawELF {
-    let mut file = fs::File::open(&file_name).expect("File either not found or failed to open!!");
+pub fn read_elf32_prg_hdrs(file_data: &[u8], header: &Elf32Hdr) -> std::io::Result<Vec<Elf32Phdr>> {
+    let entsize = if header.e_phentsize == 0 { ELF32_PHDR_SIZE } else { header.e_phentsize as usize };
+    return (0..header.e_phnum as usize).map(|i| read_elf32_prg_hdr(file_data, header.e_phoff as usize + i * entsize))
+                                       .collect();
+}
 
-    // Read ELF header
-    let elf_header = read_elf32_hdr(&file);
-    verify_elf32_hdr(&elf_header);
+// NUL terminated string at offset inside a string table
+fn read_string(strtab: &[u8], offset: usize) -> String {
+    let bytes = strtab.get(offset..).unwrap_or(&[]);
+    let end = bytes.iter().position(|&c| c == 0).unwrap_or(bytes.len());
+    return String::from_utf8_lossy(&bytes[..end]).to_string();
+}
 
-    // Read program headers
-    let mut elf_prg_hdr = vec![elf_prg_hdr_default();elf_header.e_phnum as usize];
-    file.seek(SeekFrom::Start(elf_header.e_phoff as u64)).ok();
+fn section_data<'a>(file_data: &'a [u8], section: &Elf32Shdr) -> std::io::Result<&'a [u8]> {
+    let start = section.sh_offset as usize;
+    return file_data.get(start..start + section.sh_size as usize)
+                    .ok_or_else(|| invalid_elf(&format!("ELF section {} is out of bounds!", section.name)));
+}
 
-    for i in 0..elf_header.e_phnum as usize {
-        elf_prg_hdr[i] = read_elf32_prg_hdr(&file);
+pub fn read_elf32_sec_hdrs(file_data: &[u8], header: &Elf32Hdr) -> std::io::Result<Vec<Elf32Shdr>> {
+    let entsize = if header.e_shentsize == 0 { ELF32_SHDR_SIZE } else { header.e_shentsize as usize };
+    let mut sections = Vec::new();
+    for i in 0..header.e_shnum as usize {
+        let offset = header.e_shoff as usize + i * entsize;
+        if offset + ELF32_SHDR_SIZE > file_data.len() {
+            return Err(invalid_elf("ELF section header is out of bounds!"));
+        }
+        sections.push(Elf32Shdr {
+            sh_name:        read_u32(file_data, offset),
+            sh_type:        read_u32(file_data, offset + 0x04),
+            sh_flags:       read_u32(file_data, offset + 0x08),
+            sh_addr:        read_u32(file_data, offset + 0x0C),
+            sh_offset:      read_u32(file_data, offset + 0x10),
+            sh_size:        read_u32(file_data, offset + 0x14),
+            sh_link:        read_u32(file_data, offset + 0x18),
+            sh_info:        read_u32(file_data, offset + 0x1C),
+            sh_addralign:   read_u32(file_data, offset + 0x20),
+            sh_entsize:     read_u32(file_data, offset + 0x24),
+
+            name:           String::new(),
+        });
+    }
+
+    if let Some(shstrtab) = sections.get(header.e_shstrndx as usize) {
+        let shstrtab = section_data(file_data, shstrtab)?.to_vec();
+        for section in &mut sections {
+            section.name = read_string(&shstrtab, section.sh_name as usize);
+        }
     }
+    return Ok(sections);
+}
+
+// Every entry of .symtab (the null symbol included), empty if the ELF is stripped.
+pub fn read_elf32_symbols(file_data: &[u8], sections: &[Elf32Shdr]) -> std::io::Result<Vec<Elf32Sym>> {
+    let Some(symtab) = sections.iter().find(|s| s.sh_type == SHT_SYMTAB) else {
+        return Ok(Vec::new());
+    };
+    let strtab = sections.get(symtab.sh_link as usize)
+                         .ok_or_else(|| invalid_elf("ELF symbol table has no string table!"))?;
+    let strtab = section_data(file_data, strtab)?;
+    let data = section_data(file_data, symtab)?;
+
+    let entsize = if symtab.sh_entsize == 0 { ELF32_SYM_SIZE } else { symtab.sh_entsize as usize };
+    return Ok(data.chunks_exact(entsize).map(|entry| Elf32Sym {
+        name:       read_string(strtab, read_u32(entry, 0x00) as usize),
+        st_value:   read_u32(entry, 0x04),
+        st_size:    read_u32(entry, 0x08),
+        st_info:    entry[0x0C],
+        st_other:   entry[0x0D],
+        st_shndx:   read_u16(entry, 0x0E),
+    }).collect());
+}
+
+// Contents of the loaded (SHF_ALLOC) section holding addr..addr+len, None if it isn't all in one
+pub fn data_at<'a>(file_data: &'a [u8], sections: &[Elf32Shdr], addr: u32, len: u32) -> Option<&'a [u8]> {
+    let section = sections.iter().find(|s| s.sh_flags & SHF_ALLOC != 0 && s.sh_type != SHT_NOBITS
+                                           && addr >= s.sh_addr && addr as u64 + len as u64 <= s.sh_addr as u64 + s.sh_size as u64)?;
+    let data = section_data(file_data, section).ok()?;
+    let start = (addr - section.sh_addr) as usize;
+    return data.get(start..start + len as usize);
+}
+
+#[allow(dead_code, clippy::needless_range_loop)]
+pub fn turn_elf_to_raw(file_name: &String, image_size: usize, base_addr: u32) -> std::io::Result<RawELF> {
+    let file_data = fs::read(file_name)?;
+
+    // Read ELF header
+    let elf_header = read_elf32_hdr(&file_data)?;
+    verify_elf32_hdr(&elf_header)?;
+
+    // Read program headers
+    let elf_prg_hdr = read_elf32_prg_hdrs(&file_data, &elf_header)?;
 
     // Copy data to raw
     let mut raw_image = raw_elf_default(image_size);
@@ -164,16 +287,316 @@ pub fn turn_elf_to_raw(file_name: &String, image_size: usize, base_addr: u32) ->
         let offset = elf_prg_hdr[i].p_offset as usize;
 
         if memsz != 0 && vaddr != 0 && filesz != 0 && filesz <= memsz {
-            file.seek(SeekFrom::Start(offset as u64)).ok();
-            let data = read_u8s(&file, memsz);
-            raw_image.data[vaddr - base_addr as usize..vaddr - base_addr as usize + memsz].copy_from_slice(&data);
+            let data = file_data.get(offset..offset + memsz)
+                                .ok_or_else(|| invalid_elf("ELF segment is out of bounds!"))?;
+            let start = vaddr.checked_sub(base_addr as usize)
+                             .ok_or_else(|| invalid_elf(&format!("ELF segment at {:#010X} is below the base address {:#010X}!", vaddr, base_addr)))?;
+            if start + memsz > raw_image.data.len() {
+                return Err(invalid_elf(&format!("ELF segment at {:#010X} does not fit in the image size {:#X}!", vaddr, image_size)));
+            }
+            raw_image.data[start..start + memsz].copy_from_slice(data);
         }
     }
 
     raw_image.base_addr = base_addr;
     raw_image.entry_point = elf_header.e_entry;
 
-    return raw_image;
+    return Ok(raw_image);
+}
+
+fn push_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_be_bytes());
+}
+
+fn push_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_be_bytes());
+}
+
+fn align_to(buffer: &mut Vec<u8>, align: usize) {
+    buffer.resize(buffer.len().next_multiple_of(align), 0);
+}
+
+const PT_LOAD : u32 = 1;
+const SHT_PROGBITS : u32 = 1;
+const SHT_STRTAB : u32 = 3;
+const SHT_RELA : u32 = 4;
+const SHT_NOBITS : u32 = 8;
+pub const SHF_WRITE : u32 = 1;
+pub const SHF_ALLOC : u32 = 2;
+pub const SHF_EXECINSTR : u32 = 4;
+const ELF32_RELA_SIZE : usize = 0x0C;
+
+// PowerPC relocation types
+pub const R_PPC_ADDR32 : u32 = 1;
+pub const R_PPC_ADDR16_LO : u32 = 4;
+pub const R_PPC_ADDR16_HI : u32 = 5;
+pub const R_PPC_ADDR16_HA : u32 = 6;
+pub const R_PPC_REL24 : u32 = 10;
+pub const R_PPC_REL14 : u32 = 11;
+pub const R_PPC_EMB_SDA21 : u32 = 109;
+
+// Same inputs as turn_raw_to_dol, but keeps the section names (text, data, then bss order).
+// Every non-empty section gets its own segment so the ELF loads exactly like the DOL would.
+#[allow(dead_code, clippy::ptr_arg, clippy::too_many_arguments)]
+pub fn turn_raw_to_elf(file_name: &String,
+                       raw_data: &Vec<u8>,
+                       text_addr: &Vec<u32>,
+                       text_size: &Vec<u32>,
+                       data_addr: &Vec<u32>,
+                       data_size: &Vec<u32>,
+                       bss_addr: &Vec<u32>,
+                       bss_size: &Vec<u32>,
+                       entry_point: u32,
+                       base_addr: u32,
+                       section_names: &Vec<String>) -> std::io::Result<()> {
+    // (name, addr, size, type, flags)
+    let mut sections : Vec<(&str, u32, u32, u32, u32)> = Vec::new();
+    for i in 0..text_addr.len() {
+        sections.push((&section_names[i], text_addr[i], text_size[i], SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR));
+    }
+    for i in 0..data_addr.len() {
+        sections.push((&section_names[text_addr.len() + i], data_addr[i], data_size[i], SHT_PROGBITS, SHF_ALLOC | SHF_WRITE));
+    }
+    for i in 0..bss_addr.len() {
+        sections.push((&section_names[text_addr.len() + data_addr.len() + i], bss_addr[i], bss_size[i], SHT_NOBITS, SHF_ALLOC | SHF_WRITE));
+    }
+    sections.retain(|section| section.2 != 0);
+
+    let phnum = sections.len();
+    let shnum = sections.len() + 2;
+
+    // Section contents, right after the header and program headers
+    let mut body = vec![0u8; ELF32_HDR_SIZE + phnum * ELF32_PHDR_SIZE];
+    let mut offsets = Vec::new();
+    for &(name, addr, size, sh_type, _) in &sections {
+        align_to(&mut body, 0x20);
+        offsets.push(body.len() as u32);
+        if sh_type == SHT_PROGBITS {
+            let start = addr.checked_sub(base_addr).map(|x| x as usize);
+            let data = start.and_then(|start| raw_data.get(start..start + size as usize))
+                            .ok_or_else(|| invalid_elf(&format!("Section {} at {:#010X} is outside the image!", name, addr)))?;
+            body.extend_from_slice(data);
+        }
+    }
+
+    let mut shstrtab = vec![0u8];
+    let mut name_offs = Vec::new();
+    for &(name, ..) in &sections {
+        name_offs.push(shstrtab.len() as u32);
+        shstrtab.extend_from_slice(name.as_bytes());
+        shstrtab.push(0);
+    }
+    let shstrtab_name = shstrtab.len() as u32;
+    shstrtab.extend_from_slice(b".shstrtab\0");
+
+    let shstrtab_off = body.len() as u32;
+    body.extend_from_slice(&shstrtab);
+    align_to(&mut body, 4);
+    let shoff = body.len() as u32;
+
+    // ELF header
+    let mut header = Vec::with_capacity(ELF32_HDR_SIZE + phnum * ELF32_PHDR_SIZE);
+    header.extend_from_slice(b"\x7FELF");
+    header.extend_from_slice(&[1, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    push_u16(&mut header, 2);   // ET_EXEC
+    push_u16(&mut header, 20);  // EM_PPC
+    push_u32(&mut header, 1);
+    push_u32(&mut header, entry_point);
+    push_u32(&mut header, ELF32_HDR_SIZE as u32);
+    push_u32(&mut header, shoff);
+    push_u32(&mut header, 0);
+    push_u16(&mut header, ELF32_HDR_SIZE as u16);
+    push_u16(&mut header, ELF32_PHDR_SIZE as u16);
+    push_u16(&mut header, phnum as u16);
+    push_u16(&mut header, 0x28);
+    push_u16(&mut header, shnum as u16);
+    push_u16(&mut header, shnum as u16 - 1);
+
+    // Program headers
+    for (i, &(_, addr, size, sh_type, flags)) in sections.iter().enumerate() {
+        let p_flags = 4 | if flags & SHF_EXECINSTR != 0 { 1 } else { 2 };
+        push_u32(&mut header, PT_LOAD);
+        push_u32(&mut header, offsets[i]);
+        push_u32(&mut header, addr);
+        push_u32(&mut header, addr);
+        push_u32(&mut header, if sh_type == SHT_NOBITS { 0 } else { size });
+        push_u32(&mut header, size);
+        push_u32(&mut header, p_flags);
+        push_u32(&mut header, 0x20);
+    }
+    body[..header.len()].copy_from_slice(&header);
+
+    // Section headers
+    body.extend_from_slice(&[0u8; 0x28]);
+    for (i, &(_, addr, size, sh_type, flags)) in sections.iter().enumerate() {
+        for value in [name_offs[i], sh_type, flags, addr, offsets[i], size, 0, 0, 0x20, 0] {
+            push_u32(&mut body, value);
+        }
+    }
+    for value in [shstrtab_name, SHT_STRTAB, 0, 0, shstrtab_off, shstrtab.len() as u32, 0, 0, 1, 0] {
+        push_u32(&mut body, value);
+    }
+
+    return fs::write(file_name, body);
+}
+
+// A section of a relocatable object. data is None for NOBITS (bss) sections.
+pub struct ObjSection {
+    pub name: String,
+    pub flags: u32,
+    pub align: u32,
+    pub size: u32,
+    pub data: Option<Vec<u8>>,
+    pub relocs: Vec<ObjReloc>,
+}
+
+pub struct ObjReloc {
+    // Offset into the section
+    pub offset: u32,
+    // R_PPC_ type
+    pub kind: u32,
+    // Index into the symbols passed along
+    pub symbol: usize,
+    pub addend: i32,
+}
+
+pub struct ObjSymbol {
+    pub name: String,
+    // Index into the sections passed along, None for undefined symbols
+    pub section: Option<usize>,
+    // Offset into the section
+    pub value: u32,
+    pub size: u32,
+    pub kind: u8,
+    pub local: bool,
+}
+
+// Relocatable (ET_REL) object with the given sections, a .rela section for every one with relocations and a .symtab.
+// Section symbols come first, then the local symbols, then the global ones, as ELF wants it.
+pub fn relocatable_elf(sections: &[ObjSection], symbols: &[ObjSymbol]) -> Vec<u8> {
+    // null, sections, .rela sections, .symtab, .strtab, .shstrtab
+    let relocated: Vec<usize> = (0..sections.len()).filter(|i| !sections[*i].relocs.is_empty()).collect();
+    let symtab_index = sections.len() + relocated.len() + 1;
+    let shnum = symtab_index + 3;
+
+    let mut body = vec![0u8; ELF32_HDR_SIZE];
+    let mut offsets = Vec::new();
+    for section in sections {
+        align_to(&mut body, section.align.max(1) as usize);
+        offsets.push(body.len() as u32);
+        if let Some(data) = &section.data {
+            body.extend_from_slice(data);
+        }
+    }
+
+    let mut strtab = vec![0u8];
+    let mut symtab = vec![0u8; ELF32_SYM_SIZE];
+    for i in 0..sections.len() {
+        for value in [0, 0, 0] {
+            push_u32(&mut symtab, value);
+        }
+        symtab.push((STB_LOCAL << 4) | STT_SECTION);
+        symtab.push(0);
+        push_u16(&mut symtab, i as u16 + 1);
+    }
+    let mut ordered: Vec<usize> = (0..symbols.len()).filter(|i| symbols[*i].local).collect();
+    let first_global = 1 + sections.len() + ordered.len();
+    ordered.extend((0..symbols.len()).filter(|i| !symbols[*i].local));
+    let mut symbol_index = vec![0; symbols.len()];
+    for (i, symbol) in ordered.iter().enumerate() {
+        symbol_index[*symbol] = 1 + sections.len() + i;
+    }
+    for symbol in ordered.iter().map(|i| &symbols[*i]) {
+        push_u32(&mut symtab, strtab.len() as u32);
+        strtab.extend_from_slice(symbol.name.as_bytes());
+        strtab.push(0);
+        push_u32(&mut symtab, symbol.value);
+        push_u32(&mut symtab, symbol.size);
+        symtab.push((if symbol.local { STB_LOCAL } else { STB_GLOBAL } << 4) | symbol.kind);
+        symtab.push(0);
+        push_u16(&mut symtab, symbol.section.map(|s| s as u16 + 1).unwrap_or(SHN_UNDEF));
+    }
+
+    let mut rela_names = Vec::new();
+    let mut relas = Vec::new();
+    for i in &relocated {
+        rela_names.push(format!(".rela{}", sections[*i].name));
+        let mut rela = Vec::new();
+        for reloc in &sections[*i].relocs {
+            push_u32(&mut rela, reloc.offset);
+            push_u32(&mut rela, (symbol_index[reloc.symbol] as u32) << 8 | reloc.kind);
+            push_u32(&mut rela, reloc.addend as u32);
+        }
+        relas.push(rela);
+    }
+
+    let mut shstrtab = vec![0u8];
+    let mut name_offs = Vec::new();
+    for name in sections.iter().map(|s| s.name.as_str()).chain(rela_names.iter().map(|n| n.as_str())).chain([".symtab", ".strtab", ".shstrtab"]) {
+        name_offs.push(shstrtab.len() as u32);
+        shstrtab.extend_from_slice(name.as_bytes());
+        shstrtab.push(0);
+    }
+
+    align_to(&mut body, 4);
+    let mut rela_offs = Vec::new();
+    for rela in &relas {
+        rela_offs.push(body.len() as u32);
+        body.extend_from_slice(rela);
+    }
+    let symtab_off = body.len() as u32;
+    body.extend_from_slice(&symtab);
+    let strtab_off = body.len() as u32;
+    body.extend_from_slice(&strtab);
+    let shstrtab_off = body.len() as u32;
+    body.extend_from_slice(&shstrtab);
+    align_to(&mut body, 4);
+    let shoff = body.len() as u32;
+
+    // ELF header
+    let mut header = Vec::with_capacity(ELF32_HDR_SIZE);
+    header.extend_from_slice(b"\x7FELF");
+    header.extend_from_slice(&[1, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    push_u16(&mut header, 1);   // ET_REL
+    push_u16(&mut header, 20);  // EM_PPC
+    push_u32(&mut header, 1);
+    push_u32(&mut header, 0);
+    push_u32(&mut header, 0);
+    push_u32(&mut header, shoff);
+    push_u32(&mut header, 0);
+    push_u16(&mut header, ELF32_HDR_SIZE as u16);
+    push_u16(&mut header, 0);
+    push_u16(&mut header, 0);
+    push_u16(&mut header, ELF32_SHDR_SIZE as u16);
+    push_u16(&mut header, shnum as u16);
+    push_u16(&mut header, shnum as u16 - 1);
+    body[..header.len()].copy_from_slice(&header);
+
+    // Section headers
+    body.extend_from_slice(&[0u8; ELF32_SHDR_SIZE]);
+    for (i, section) in sections.iter().enumerate() {
+        let sh_type = if section.data.is_some() { SHT_PROGBITS } else { SHT_NOBITS };
+        for value in [name_offs[i], sh_type, section.flags, 0, offsets[i], section.size, 0, 0, section.align.max(1), 0] {
+            push_u32(&mut body, value);
+        }
+    }
+    for (n, i) in relocated.iter().enumerate() {
+        let name = name_offs[sections.len() + n];
+        for value in [name, SHT_RELA, 0, 0, rela_offs[n], relas[n].len() as u32, symtab_index as u32, *i as u32 + 1, 4, ELF32_RELA_SIZE as u32] {
+            push_u32(&mut body, value);
+        }
+    }
+    let names = &name_offs[sections.len() + relocated.len()..];
+    for value in [names[0], SHT_SYMTAB, 0, 0, symtab_off, symtab.len() as u32, symtab_index as u32 + 1, first_global as u32, 4, ELF32_SYM_SIZE as u32] {
+        push_u32(&mut body, value);
+    }
+    for value in [names[1], SHT_STRTAB, 0, 0, strtab_off, strtab.len() as u32, 0, 0, 1, 0] {
+        push_u32(&mut body, value);
+    }
+    for value in [names[2], SHT_STRTAB, 0, 0, shstrtab_off, shstrtab.len() as u32, 0, 0, 1, 0] {
+        push_u32(&mut body, value);
+    }
+    return body;
 }
 
 #[allow(dead_code)]
@@ -0,0 +1,462 @@
+use std::fs;
+use std::io::{BufWriter, Error, ErrorKind};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::gcipl;
+use crate::meta;
+use crate::yay0;
+
+pub const FONT_HEADER_LENGTH : usize = 0x30;
+
+// Texture formats of the glyph sheets (GX numbering)
+pub const SHEET_FORMAT_I4 : u16 = 0;
+pub const SHEET_FORMAT_IA4 : u16 = 2;
+
+// ROM font names and where they live
+pub const ROM_FONTS : [(&str, usize, usize); 2] = [
+    ("sjis", gcipl::SJIS_FONT_OFFSET, gcipl::ANSI_FONT_OFFSET),
+    ("ansi", gcipl::ANSI_FONT_OFFSET, gcipl::IPL_SIZE),
+];
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct FontHeader {
+    pub font_type: u16,
+    pub first_char: u16,
+    pub last_char: u16,
+    pub inval_char: u16,
+    pub asc: u16,
+    pub desc: u16,
+    pub width: u16,
+    pub leading: u16,
+    pub cell_width: u16,
+    pub cell_height: u16,
+    pub sheet_size: u32,
+    pub sheet_format: u16,
+    pub sheet_column: u16,
+    pub sheet_row: u16,
+    pub sheet_width: u16,
+    pub sheet_height: u16,
+    pub width_table: u16,
+    pub sheet_image: u32,
+    pub sheet_fullsize: u32,
+    // 2-bit to 4-bit expansion table of I4 sheets
+    pub colors: [u8; 4],
+}
+
+// What extract-fonts writes next to the PNG sheets
+#[derive(Serialize, Deserialize)]
+pub struct FontMetrics {
+    pub header: FontHeader,
+    pub widths: Vec<u8>,
+    pub sheets: Vec<String>,
+}
+
+fn invalid_font(message: &str) -> Error {
+    return Error::new(ErrorKind::InvalidData, format!("Invalid font: {}", message));
+}
+
+fn read_u16(buffer: &[u8], offset: usize) -> u16 {
+    return u16::from_be_bytes([buffer[offset], buffer[offset + 1]]);
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    return u32::from_be_bytes([buffer[offset], buffer[offset + 1], buffer[offset + 2], buffer[offset + 3]]);
+}
+
+pub fn read_header(font_data: &[u8]) -> std::io::Result<FontHeader> {
+    if font_data.len() < FONT_HEADER_LENGTH {
+        return Err(invalid_font("smaller than the header"));
+    }
+
+    let header = FontHeader {
+        font_type:      read_u16(font_data, 0x00),
+        first_char:     read_u16(font_data, 0x02),
+        last_char:      read_u16(font_data, 0x04),
+        inval_char:     read_u16(font_data, 0x06),
+        asc:            read_u16(font_data, 0x08),
+        desc:           read_u16(font_data, 0x0A),
+        width:          read_u16(font_data, 0x0C),
+        leading:        read_u16(font_data, 0x0E),
+        cell_width:     read_u16(font_data, 0x10),
+        cell_height:    read_u16(font_data, 0x12),
+        sheet_size:     read_u32(font_data, 0x14),
+        sheet_format:   read_u16(font_data, 0x18),
+        sheet_column:   read_u16(font_data, 0x1A),
+        sheet_row:      read_u16(font_data, 0x1C),
+        sheet_width:    read_u16(font_data, 0x1E),
+        sheet_height:   read_u16(font_data, 0x20),
+        width_table:    read_u16(font_data, 0x22),
+        sheet_image:    read_u32(font_data, 0x24),
+        sheet_fullsize: read_u32(font_data, 0x28),
+        colors:         [font_data[0x2C], font_data[0x2D], font_data[0x2E], font_data[0x2F]],
+    };
+
+    if header.sheet_format != SHEET_FORMAT_I4 && header.sheet_format != SHEET_FORMAT_IA4 {
+        return Err(invalid_font(&format!("unknown sheet format {}", header.sheet_format)));
+    }
+    if header.sheet_size == 0 || !header.sheet_width.is_multiple_of(8) || !header.sheet_height.is_multiple_of(8) ||
+       header.sheet_size as usize != sheet_texels(&header) * texel_bits(&header) / 8 {
+        return Err(invalid_font("sheet size does not match its dimensions"));
+    }
+    if packed_size(&header) + header.sheet_image as usize > font_data.len() {
+        return Err(invalid_font("glyph sheets run past the end"));
+    }
+    if header.width_table as usize > font_data.len() {
+        return Err(invalid_font("width table starts past the end"));
+    }
+
+    return Ok(header);
+}
+
+pub fn write_header(header: &FontHeader, font_data: &mut [u8]) {
+    let mut put_u16 = |offset: usize, value: u16| font_data[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+    put_u16(0x00, header.font_type);
+    put_u16(0x02, header.first_char);
+    put_u16(0x04, header.last_char);
+    put_u16(0x06, header.inval_char);
+    put_u16(0x08, header.asc);
+    put_u16(0x0A, header.desc);
+    put_u16(0x0C, header.width);
+    put_u16(0x0E, header.leading);
+    put_u16(0x10, header.cell_width);
+    put_u16(0x12, header.cell_height);
+    put_u16(0x18, header.sheet_format);
+    put_u16(0x1A, header.sheet_column);
+    put_u16(0x1C, header.sheet_row);
+    put_u16(0x1E, header.sheet_width);
+    put_u16(0x20, header.sheet_height);
+    put_u16(0x22, header.width_table);
+    font_data[0x14..0x18].copy_from_slice(&header.sheet_size.to_be_bytes());
+    font_data[0x24..0x28].copy_from_slice(&header.sheet_image.to_be_bytes());
+    font_data[0x28..0x2C].copy_from_slice(&header.sheet_fullsize.to_be_bytes());
+    font_data[0x2C..0x30].copy_from_slice(&header.colors);
+}
+
+fn texel_bits(header: &FontHeader) -> usize {
+    return if header.sheet_format == SHEET_FORMAT_I4 { 4 } else { 8 };
+}
+
+fn sheet_texels(header: &FontHeader) -> usize {
+    return header.sheet_width as usize * header.sheet_height as usize;
+}
+
+fn sheet_count(header: &FontHeader) -> usize {
+    return header.sheet_fullsize as usize / header.sheet_size as usize;
+}
+
+// I4 sheets are stored at 2 bits per texel and expanded on load
+fn packed_size(header: &FontHeader) -> usize {
+    return if header.sheet_format == SHEET_FORMAT_I4 { header.sheet_fullsize as usize / 2 } else { header.sheet_fullsize as usize };
+}
+
+fn glyph_count(header: &FontHeader) -> usize {
+    return header.last_char.saturating_sub(header.first_char) as usize + 1;
+}
+
+// 2-bit texels to I4, same as the OS does it: the even texel takes the high nibble of its color, the odd one the low nibble
+fn expand_sheets(header: &FontHeader, packed: &[u8]) -> Vec<u8> {
+    let mut expanded = vec![0u8; packed.len() * 2];
+    for (i, &byte) in packed.iter().enumerate() {
+        let c = |shift: u32| header.colors[((byte >> shift) & 3) as usize];
+        expanded[i * 2]     = (c(6) & 0xF0) | (c(4) & 0x0F);
+        expanded[i * 2 + 1] = (c(2) & 0xF0) | (c(0) & 0x0F);
+    }
+    return expanded;
+}
+
+fn nearest_color(header: &FontHeader, value: u8, high: bool) -> u8 {
+    let mut best = 0;
+    for k in 0..4u8 {
+        let nibble = |k: u8| if high { header.colors[k as usize] >> 4 } else { header.colors[k as usize] & 0x0F };
+        if (nibble(k) as i32 - value as i32).abs() < (nibble(best) as i32 - value as i32).abs() {
+            best = k;
+        }
+    }
+    return best;
+}
+
+// Inverse of expand_sheets, snapping every texel to the closest of the four colors.
+fn pack_sheets(header: &FontHeader, expanded: &[u8]) -> Vec<u8> {
+    let mut packed = vec![0u8; expanded.len() / 2];
+    for i in 0..packed.len() {
+        let hi = expanded[i * 2];
+        let lo = expanded[i * 2 + 1];
+        packed[i] = (nearest_color(header, hi >> 4, true) << 6) |
+                    (nearest_color(header, hi & 0x0F, false) << 4) |
+                    (nearest_color(header, lo >> 4, true) << 2) |
+                    nearest_color(header, lo & 0x0F, false);
+    }
+    return packed;
+}
+
+fn tile_size(header: &FontHeader) -> (usize, usize) {
+    return if header.sheet_format == SHEET_FORMAT_I4 { (8, 8) } else { (8, 4) };
+}
+
+// Tiled texture to gray+alpha pixels
+fn decode_sheet(header: &FontHeader, texture: &[u8]) -> Vec<u8> {
+    let width = header.sheet_width as usize;
+    let height = header.sheet_height as usize;
+    let (tile_w, tile_h) = tile_size(header);
+    let mut pixels = vec![0u8; width * height * 2];
+
+    let mut texel = 0;
+    for ty in (0..height).step_by(tile_h) {
+        for tx in (0..width).step_by(tile_w) {
+            for y in 0..tile_h {
+                for x in 0..tile_w {
+                    let (gray, alpha) = if header.sheet_format == SHEET_FORMAT_I4 {
+                        let byte = texture[texel / 2];
+                        let value = if texel % 2 == 0 { byte >> 4 } else { byte & 0x0F };
+                        (value * 0x11, 0xFF)
+                    }
+                    else {
+                        let byte = texture[texel];
+                        ((byte & 0x0F) * 0x11, (byte >> 4) * 0x11)
+                    };
+                    let pos = ((ty + y) * width + tx + x) * 2;
+                    pixels[pos] = gray;
+                    pixels[pos + 1] = alpha;
+                    texel += 1;
+                }
+            }
+        }
+    }
+    return pixels;
+}
+
+fn encode_sheet(header: &FontHeader, pixels: &[u8]) -> Vec<u8> {
+    let width = header.sheet_width as usize;
+    let height = header.sheet_height as usize;
+    let (tile_w, tile_h) = tile_size(header);
+    let mut texture = vec![0u8; header.sheet_size as usize];
+
+    let mut texel = 0;
+    for ty in (0..height).step_by(tile_h) {
+        for tx in (0..width).step_by(tile_w) {
+            for y in 0..tile_h {
+                for x in 0..tile_w {
+                    let pos = ((ty + y) * width + tx + x) * 2;
+                    let gray = pixels[pos] >> 4;
+                    let alpha = pixels[pos + 1] >> 4;
+                    if header.sheet_format == SHEET_FORMAT_I4 {
+                        texture[texel / 2] |= if texel % 2 == 0 { gray << 4 } else { gray };
+                    }
+                    else {
+                        texture[texel] = (alpha << 4) | gray;
+                    }
+                    texel += 1;
+                }
+            }
+        }
+    }
+    return texture;
+}
+
+fn write_png(path: &Path, width: u32, height: u32, pixels: &[u8]) -> std::io::Result<()> {
+    let writer = BufWriter::new(fs::File::create(path)?);
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::GrayscaleAlpha);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(|e| Error::other(e.to_string()))?;
+    writer.write_image_data(pixels).map_err(|e| Error::other(e.to_string()))?;
+    return Ok(());
+}
+
+// Reads any 8-bit PNG back as gray+alpha pixels
+fn read_png(path: &Path, width: u32, height: u32) -> std::io::Result<Vec<u8>> {
+    let png_error = |e: png::DecodingError| Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e));
+
+    let mut decoder = png::Decoder::new(fs::File::open(path)?);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(png_error)?;
+    let mut buffer = vec![0u8; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(png_error)?;
+
+    if info.width != width || info.height != height {
+        return Err(Error::new(ErrorKind::InvalidData,
+                              format!("{}: sheet is {}x{}, expected {}x{}", path.display(), info.width, info.height, width, height)));
+    }
+
+    let channels = info.color_type.samples();
+    let mut pixels = Vec::with_capacity((width * height * 2) as usize);
+    for pixel in buffer[..info.buffer_size()].chunks(channels) {
+        let (gray, alpha) = match channels {
+            1 => (pixel[0], 0xFF),
+            2 => (pixel[0], pixel[1]),
+            3 => (((pixel[0] as u32 + pixel[1] as u32 + pixel[2] as u32) / 3) as u8, 0xFF),
+            _ => (((pixel[0] as u32 + pixel[1] as u32 + pixel[2] as u32) / 3) as u8, pixel[3]),
+        };
+        pixels.push(gray);
+        pixels.push(alpha);
+    }
+    return Ok(pixels);
+}
+
+// Writes <name>.json and one <name>_<n>.png per glyph sheet.
+pub fn extract(font_data: &[u8], out_dir: &Path, name: &str) -> std::io::Result<()> {
+    let header = read_header(font_data)?;
+
+    let width_start = header.width_table as usize;
+    let width_end = (width_start + glyph_count(&header)).min(font_data.len());
+    let widths = font_data[width_start..width_end].to_vec();
+
+    let packed = &font_data[header.sheet_image as usize..header.sheet_image as usize + packed_size(&header)];
+    let textures = if header.sheet_format == SHEET_FORMAT_I4 { expand_sheets(&header, packed) } else { packed.to_vec() };
+
+    let mut sheets = Vec::new();
+    for (i, texture) in textures.chunks(header.sheet_size as usize).take(sheet_count(&header)).enumerate() {
+        let sheet_name = format!("{}_{}.png", name, i);
+        write_png(&out_dir.join(&sheet_name), header.sheet_width as u32, header.sheet_height as u32, &decode_sheet(&header, texture))?;
+        sheets.push(sheet_name);
+    }
+
+    let metrics = FontMetrics { header, widths, sheets };
+    return meta::write_document(&out_dir.join(format!("{}.json", name)).to_string_lossy(), &metrics);
+}
+
+// Puts the (possibly edited) sheets, widths and metrics from extract back into a font.
+// The sheet layout has to stay the same, everything else in the header may change.
+pub fn insert(font_data: &[u8], in_dir: &Path, name: &str) -> std::io::Result<Vec<u8>> {
+    let header = read_header(font_data)?;
+    let metrics: FontMetrics = meta::read_document(&in_dir.join(format!("{}.json", name)).to_string_lossy())?;
+    let edited = &metrics.header;
+
+    if edited.sheet_format != header.sheet_format || edited.sheet_size != header.sheet_size ||
+       edited.sheet_width != header.sheet_width || edited.sheet_height != header.sheet_height ||
+       edited.sheet_image != header.sheet_image || edited.sheet_fullsize != header.sheet_fullsize ||
+       edited.width_table != header.width_table || edited.colors != header.colors {
+        return Err(invalid_font(&format!("{}: the sheet layout can't be changed", name)));
+    }
+    if metrics.sheets.len() != sheet_count(&header) {
+        return Err(invalid_font(&format!("{}: expected {} sheets, got {}", name, sheet_count(&header), metrics.sheets.len())));
+    }
+    if header.width_table as usize + metrics.widths.len() > header.sheet_image as usize {
+        return Err(invalid_font(&format!("{}: width table overlaps the glyph sheets", name)));
+    }
+
+    let mut new_font = font_data.to_vec();
+    write_header(edited, &mut new_font);
+    new_font[header.width_table as usize..header.width_table as usize + metrics.widths.len()].copy_from_slice(&metrics.widths);
+
+    let mut textures = Vec::with_capacity(header.sheet_fullsize as usize);
+    for sheet_name in &metrics.sheets {
+        let pixels = read_png(&in_dir.join(sheet_name), header.sheet_width as u32, header.sheet_height as u32)?;
+        textures.extend_from_slice(&encode_sheet(&header, &pixels));
+    }
+    let packed = if header.sheet_format == SHEET_FORMAT_I4 { pack_sheets(&header, &textures) } else { textures };
+    new_font[header.sheet_image as usize..header.sheet_image as usize + packed.len()].copy_from_slice(&packed);
+
+    return Ok(new_font);
+}
+
+pub fn extract_rom_fonts(rom: &[u8], out_dir: &str) -> std::io::Result<()> {
+    fs::create_dir_all(out_dir)?;
+    for (name, start, end) in ROM_FONTS {
+        let font_data = yay0::decompress(&rom[start..end]).map_err(|e| Error::new(e.kind(), format!("{} font: {}", name, e)))?;
+        extract(&font_data, Path::new(out_dir), name)?;
+    }
+    return Ok(());
+}
+
+// Recompresses the fonts found in in_dir into a copy of the ROM.
+pub fn insert_rom_fonts(rom: &[u8], in_dir: &str) -> std::io::Result<Vec<u8>> {
+    let mut new_rom = rom.to_vec();
+    for (name, start, end) in ROM_FONTS {
+        if !Path::new(in_dir).join(format!("{}.json", name)).is_file() {
+            continue;
+        }
+
+        let font_data = yay0::decompress(&rom[start..end]).map_err(|e| Error::new(e.kind(), format!("{} font: {}", name, e)))?;
+        let compressed = yay0::compress(&insert(&font_data, Path::new(in_dir), name)?);
+        if compressed.len() > end - start {
+            return Err(Error::new(ErrorKind::InvalidData,
+                                  format!("{} font compresses to {:#X} bytes, only {:#X} fit", name, compressed.len(), end - start)));
+        }
+
+        new_rom[start..end].fill(0);
+        new_rom[start..start + compressed.len()].copy_from_slice(&compressed);
+    }
+    return Ok(new_rom);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gcipl::tests::temp_file;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::path::PathBuf::from(temp_file(name));
+        fs::create_dir_all(&dir).unwrap();
+        return dir;
+    }
+
+    // An I4 font with two 16x8 sheets of 2-bit texels
+    fn synthetic_font() -> Vec<u8> {
+        let header = FontHeader {
+            font_type:      0,
+            first_char:     0x20,
+            last_char:      0x2F,
+            inval_char:     0x20,
+            asc:            6,
+            desc:           2,
+            width:          8,
+            leading:        8,
+            cell_width:     8,
+            cell_height:    8,
+            sheet_size:     16 * 8 / 2,
+            sheet_format:   SHEET_FORMAT_I4,
+            sheet_column:   2,
+            sheet_row:      1,
+            sheet_width:    16,
+            sheet_height:   8,
+            width_table:    FONT_HEADER_LENGTH as u16,
+            sheet_image:    0x40,
+            sheet_fullsize: 16 * 8,
+            colors:         [0x00, 0x5A, 0xA5, 0xFF],
+        };
+
+        let mut font_data = vec![0u8; header.sheet_image as usize + packed_size(&header)];
+        write_header(&header, &mut font_data);
+        for (i, width) in font_data[FONT_HEADER_LENGTH..FONT_HEADER_LENGTH + glyph_count(&header)].iter_mut().enumerate() {
+            *width = 4 + (i % 4) as u8;
+        }
+        for (i, byte) in font_data[header.sheet_image as usize..].iter_mut().enumerate() {
+            *byte = (i * 0x1B) as u8;
+        }
+        return font_data;
+    }
+
+    #[test]
+    fn width_table_past_the_end() {
+        let mut font_data = synthetic_font();
+        let len = font_data.len() as u16;
+        font_data[0x22..0x24].copy_from_slice(&(len + 1).to_be_bytes());
+        assert!(read_header(&font_data).is_err());
+    }
+
+    #[test]
+    fn extract_insert_round_trip() {
+        let font_data = synthetic_font();
+        let dir = temp_dir("fonts");
+        extract(&font_data, &dir, "test").unwrap();
+
+        let unchanged = insert(&font_data, &dir, "test");
+
+        // An edited width ends up in the width table
+        let mut metrics: FontMetrics = meta::read_document(&dir.join("test.json").to_string_lossy()).unwrap();
+        metrics.widths[3] = 7;
+        meta::write_document(&dir.join("test.json").to_string_lossy(), &metrics).unwrap();
+        let edited = insert(&font_data, &dir, "test");
+        fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(unchanged.unwrap(), font_data);
+        let edited = edited.unwrap();
+        assert_eq!(edited[FONT_HEADER_LENGTH + 3], 7);
+        assert_eq!(edited[..FONT_HEADER_LENGTH + 3], font_data[..FONT_HEADER_LENGTH + 3]);
+        assert_eq!(edited[FONT_HEADER_LENGTH + 4..], font_data[FONT_HEADER_LENGTH + 4..]);
+    }
+}
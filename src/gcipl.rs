@@ -0,0 +1,274 @@
+use std::io::{Error, ErrorKind};
+
+use crate::bootstage::{self, BSImage, BSImageRef};
+use crate::profile::{self, TargetProfile};
+
+pub const IPL_SIZE : usize = 0x200000;
+
+// Everything between the copyright header and the fonts is scrambled
+pub const SCRAMBLE_START : usize = 0x100;
+pub const SCRAMBLE_END : usize = 0x1AFF00;
+
+pub const BS1_OFFSET : usize = 0x100;
+pub const BS1_LENGTH : usize = 0x700;
+pub const BS1_ADDR : u32 = 0x81200000;
+
+pub const BS2_OFFSET : usize = 0x820;
+pub const BS2_ADDR : u32 = 0x81300000;
+
+pub const SJIS_FONT_OFFSET : usize = 0x1AFF00;
+pub const ANSI_FONT_OFFSET : usize = 0x1FCF00;
+
+// Bootrom descrambler, reversed by segher.
+// XORs the data with the output of three LFSRs, so running it twice gives back the input.
+pub fn descramble(data: &mut [u8]) {
+    let mut acc : u8 = 0;
+    let mut nacc : u8 = 0;
+
+    let mut t : u16 = 0x2953;
+    let mut u : u16 = 0xD9C2;
+    let mut v : u16 = 0x3FF1;
+
+    let mut x : u8 = 1;
+
+    let mut it = 0;
+    while it < data.len() {
+        let t0 = (t & 1) as u8;
+        let t1 = ((t >> 1) & 1) as u8;
+        let u0 = (u & 1) as u8;
+        let u1 = ((u >> 1) & 1) as u8;
+        let v0 = (v & 1) as u8;
+
+        x ^= t1 ^ v0;
+        x ^= u0 | u1;
+        x ^= (t0 ^ u1 ^ v0) & (t0 ^ u0);
+
+        if t0 == u0 {
+            v >>= 1;
+            if v0 != 0 {
+                v ^= 0xB3D0;
+            }
+        }
+
+        if t0 == 0 {
+            u >>= 1;
+            if u0 != 0 {
+                u ^= 0xFB10;
+            }
+        }
+
+        t >>= 1;
+        if t0 != 0 {
+            t ^= 0xA740;
+        }
+
+        nacc += 1;
+        acc = acc.wrapping_mul(2).wrapping_add(x);
+        if nacc == 8 {
+            data[it] ^= acc;
+            it += 1;
+            nacc = 0;
+        }
+    }
+}
+
+// Copy of the ROM with the scrambled region descrambled.
+pub fn descramble_rom(file_data: &[u8]) -> std::io::Result<Vec<u8>> {
+    if file_data.len() != IPL_SIZE {
+        return Err(Error::new(ErrorKind::InvalidData,
+                              format!("Invalid GameCube IPL: size is {:#X}, expected {:#X}", file_data.len(), IPL_SIZE)));
+    }
+
+    let mut rom = file_data.to_vec();
+    descramble(&mut rom[SCRAMBLE_START..SCRAMBLE_END]);
+    return Ok(rom);
+}
+
+// Copyright/revision string at the start of the ROM.
+pub fn copyright(rom: &[u8]) -> String {
+    let header = &rom[..SCRAMBLE_START];
+    let end = header.iter().position(|&c| c == 0).unwrap_or(header.len());
+    return String::from_utf8_lossy(&header[..end]).trim().to_string();
+}
+
+// BS1 and BS2 out of a descrambled ROM.
+// BS2 has no length field we know of, so it ends where its last initialized section does.
+pub fn parse(rom: &[u8], profile: TargetProfile) -> std::io::Result<BSImageRef<'_>> {
+    let mut image = BSImageRef {
+        bs1_addr:   BS1_ADDR,
+        bs1_len:    BS1_LENGTH as u32,
+        bs1_data:   &rom[BS1_OFFSET..BS1_OFFSET + BS1_LENGTH],
+
+        bs2_addr:   BS2_ADDR,
+        bs2_len:    0,
+        bs2_data:   &rom[BS2_OFFSET..SCRAMBLE_END],
+
+        stub_addr:  profile.stub_default_addr,
+        stub_len:   profile.stub_default_size,
+
+        unk_stuff:  &[],
+
+        // The reset vector lands on the start of BS1, which branches to the start of BS2
+        bs1_entry:  BS1_ADDR,
+        bs2_entry:  BS2_ADDR,
+
+        text_addr:  [0;bootstage::TEXT_COUNT],
+        text_len:   [0;bootstage::TEXT_COUNT],
+
+        data_addr:  [0;bootstage::DATA_COUNT],
+        data_len:   [0;bootstage::DATA_COUNT],
+
+        bss:        [bootstage::BSImageBSS{addr:0,size:0};bootstage::BSS_COUNT],
+        rom_table_off: 0,
+        bss_table_off: 0,
+
+        profile,
+    };
+
+    image.read_section_tables().map_err(|e| Error::new(e.kind(), format!("GameCube IPL BS2: {}", e)))?;
+
+    let bs2_end = image.text_addr.iter().zip(image.text_len.iter())
+                       .chain(image.data_addr.iter().zip(image.data_len.iter()))
+                       .filter(|(_, &len)| len != 0)
+                       .map(|(&addr, &len)| addr as u64 + len as u64)
+                       .max()
+                       .unwrap_or(BS2_ADDR as u64);
+    let bs2_size = (bs2_end - BS2_ADDR as u64) as usize;
+
+    if bs2_size > image.bs2_data.len() {
+        return Err(Error::new(ErrorKind::InvalidData,
+                              format!("Invalid GameCube IPL: BS2 sections end at {:#010X}, past the scrambled region", bs2_end)));
+    }
+
+    image.bs2_data = &image.bs2_data[..bs2_size];
+    // Keep the BSImage convention of bs2_len covering 4 bytes more than bs2_data
+    image.bs2_len = bs2_size as u32 + 4;
+
+    return Ok(image);
+}
+
+#[allow(dead_code, clippy::ptr_arg)]
+pub fn open_file(file_name: &String, profile: &Option<String>) -> std::io::Result<BSImage> {
+    let file_data = bootstage::map_file(file_name)?;
+    let rom = descramble_rom(&file_data)?;
+    let profile = match profile {
+        Some(name) => profile::find(name)?,
+        None       => profile::gc(),
+    };
+    return Ok(parse(&rom, profile)?.to_image());
+}
+
+// Writes a ROM with a new BS2, keeping the header, BS1 and fonts of the base ROM.
+// The base is descrambled, BS2 replaced and the whole region scrambled again.
+#[allow(dead_code)]
+pub fn create_file(file_name: &String, base_file_data: &[u8], bs2_data: &[u8]) -> std::io::Result<()> {
+    let mut rom = descramble_rom(base_file_data)?;
+
+    if BS2_OFFSET + bs2_data.len() > SCRAMBLE_END {
+        return Err(Error::new(ErrorKind::InvalidData,
+                              format!("BS2 is {:#X} bytes, but only {:#X} fit before the fonts", bs2_data.len(), SCRAMBLE_END - BS2_OFFSET)));
+    }
+
+    // Clear whatever the old BS2 left behind
+    rom[BS2_OFFSET..SCRAMBLE_END].fill(0);
+    rom[BS2_OFFSET..BS2_OFFSET + bs2_data.len()].copy_from_slice(bs2_data);
+
+    descramble(&mut rom[SCRAMBLE_START..SCRAMBLE_END]);
+
+    return std::fs::write(file_name, &rom);
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::bootstage::{BSS_COUNT, DATA_COUNT};
+    use crate::profile::SectionKind;
+
+    // A path in the temp directory no other test run uses
+    pub fn temp_file(name: &str) -> String {
+        return std::env::temp_dir().join(format!("bstool-{}-{}", std::process::id(), name)).to_string_lossy().to_string();
+    }
+
+    // Writes words into a BS2 from synthetic_bs2 at their load address
+    pub fn put(bs2_data: &mut [u8], addr: u32, words: &[u32]) {
+        for (i, word) in words.iter().enumerate() {
+            write_u32(bs2_data, (addr - BS2_ADDR) as usize + i * 4, *word);
+        }
+    }
+
+    fn write_u32(buffer: &mut [u8], offset: usize, value: u32) {
+        buffer[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+    }
+
+    // A BS2 with .init (holding the tables) at 0x81300000, .text after it and the eight data sections,
+    // ending exactly where the last data section does
+    pub fn synthetic_bs2() -> Vec<u8> {
+        let text = [(BS2_ADDR, 0x100), (BS2_ADDR + 0x100, 0x400)];
+        let data: Vec<(u32, u32)> = (0..DATA_COUNT as u32).map(|i| (BS2_ADDR + 0x500 + i * 0x20, 0x20)).collect();
+
+        let mut bs2_data = vec![0u8; 0x600];
+        write_u32(&mut bs2_data, 0, 0x48000000);
+        for (i, byte) in bs2_data[0x100..].iter_mut().enumerate() {
+            *byte = (i * 7) as u8;
+        }
+
+        let mut table_off = 0x10;
+        let (mut text_i, mut data_i) = (0, 0);
+        for kind in profile::gc().link_order {
+            let (addr, len) = if kind == SectionKind::Text { text_i += 1; text[text_i - 1] } else { data_i += 1; data[data_i - 1] };
+            write_u32(&mut bs2_data, table_off, addr);
+            write_u32(&mut bs2_data, table_off + 0x04, addr);
+            write_u32(&mut bs2_data, table_off + 0x08, len);
+            table_off += 0x0C;
+        }
+        for i in 0..BSS_COUNT as u32 {
+            write_u32(&mut bs2_data, table_off, BS2_ADDR + 0x600 + i * 0x100);
+            write_u32(&mut bs2_data, table_off + 0x04, 0x100);
+            table_off += 0x08;
+        }
+
+        return bs2_data;
+    }
+
+    // A scrambled ROM with a copyright header and a recognisable BS1
+    pub fn synthetic_rom() -> Vec<u8> {
+        let mut rom = vec![0u8; IPL_SIZE];
+        let header = b"(C) 1999-2001 Nintendo.  All rights reserved.";
+        rom[..header.len()].copy_from_slice(header);
+        rom[BS1_OFFSET..BS1_OFFSET + BS1_LENGTH].fill(0x60);
+        descramble(&mut rom[SCRAMBLE_START..SCRAMBLE_END]);
+        return rom;
+    }
+
+    #[test]
+    fn scramble_round_trip() {
+        let original: Vec<u8> = (0..0x1000u32).map(|i| (i ^ (i >> 8)) as u8).collect();
+
+        let mut data = original.clone();
+        descramble(&mut data);
+        assert_ne!(data, original);
+        descramble(&mut data);
+        assert_eq!(data, original);
+    }
+
+    #[test]
+    fn create_and_parse_round_trip() {
+        let bs2_data = synthetic_bs2();
+        let file_name = temp_file("ipl.bin");
+        create_file(&file_name, &synthetic_rom(), &bs2_data).unwrap();
+        let file_data = std::fs::read(&file_name).unwrap();
+        std::fs::remove_file(&file_name).ok();
+
+        let rom = descramble_rom(&file_data).unwrap();
+        assert_eq!(copyright(&rom), "(C) 1999-2001 Nintendo.  All rights reserved.");
+
+        let image = parse(&rom, profile::gc()).unwrap();
+        assert_eq!(image.bs1_data, &[0x60; BS1_LENGTH][..]);
+        assert_eq!(image.bs2_data, &bs2_data[..]);
+        assert_eq!(image.bs2_len, bs2_data.len() as u32 + 4);
+        assert_eq!(image.text_addr, [BS2_ADDR, BS2_ADDR + 0x100]);
+        assert_eq!(image.bss[0].addr, BS2_ADDR + 0x600);
+        // The fonts are not scrambled and stay as they were
+        assert_eq!(&file_data[SCRAMBLE_END..], &vec![0u8; IPL_SIZE - SCRAMBLE_END][..]);
+    }
+}
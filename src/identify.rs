@@ -0,0 +1,190 @@
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bootstage::BSImageRef;
+use crate::meta::{self, BSSectionMeta, BSSectionsMeta};
+
+// One known System Menu BootStage.
+// Any of the hashes may be missing, the layout is what "closest known" goes by.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct KnownImage {
+    pub version: String,
+    pub region: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title_version: Option<u16>,
+
+    // Whole content, BS1 and BS2 (as stored, before any BSS table fix-up)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha1: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bs1_sha1: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bs2_sha1: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sections: Option<BSSectionsMeta>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct KnownDb {
+    #[serde(default)]
+    pub images: Vec<KnownImage>,
+}
+
+// System Menu (1-2) title versions. The low nibble is the region.
+pub const SYSTEM_MENU_VERSIONS : [(u16, &str); 43] = [
+    (33,  "1.0U"),
+    (128, "2.0J"), (97,  "2.0U"), (130, "2.0E"),
+    (162, "2.1E"),
+    (192, "2.2J"), (193, "2.2U"), (194, "2.2E"),
+    (224, "3.0J"), (225, "3.0U"), (226, "3.0E"),
+    (256, "3.1J"), (257, "3.1U"), (258, "3.1E"),
+    (288, "3.2J"), (289, "3.2U"), (290, "3.2E"),
+    (352, "3.3J"), (353, "3.3U"), (354, "3.3E"), (326, "3.3K"),
+    (384, "3.4J"), (385, "3.4U"), (386, "3.4E"),
+    (390, "3.5K"),
+    (416, "4.0J"), (417, "4.0U"), (418, "4.0E"),
+    (448, "4.1J"), (449, "4.1U"), (450, "4.1E"), (454, "4.1K"),
+    (480, "4.2J"), (481, "4.2U"), (482, "4.2E"), (486, "4.2K"),
+    (512, "4.3J"), (513, "4.3U"), (514, "4.3E"), (518, "4.3K"),
+    // vWii
+    (608, "4.3J"), (609, "4.3U"), (610, "4.3E"),
+];
+
+// The built-in table, in the format `identify --record` writes so recorded entries can be moved over as they are.
+// Entries are only added once their hashes are checked against real dumps.
+const KNOWN_IMAGES : &str = include_str!("known_images.json");
+
+pub fn builtin() -> std::io::Result<Vec<KnownImage>> {
+    let known_db: KnownDb = serde_json::from_str(KNOWN_IMAGES).map_err(|e| Error::new(ErrorKind::InvalidData, format!("built-in known images: {}", e)))?;
+    return Ok(known_db.images);
+}
+
+pub fn version_name(title_version: u16) -> Option<&'static str> {
+    return SYSTEM_MENU_VERSIONS.iter().find(|(v, _)| *v == title_version).map(|(_, name)| *name);
+}
+
+// First (Wii, not vWii) title version of "4.3U" and the like
+pub fn title_version_of(version_region: &str) -> Option<u16> {
+    return SYSTEM_MENU_VERSIONS.iter().find(|(_, name)| *name == version_region).map(|(v, _)| *v);
+}
+
+// "4.3U" -> ("4.3", "U")
+pub fn split_version(version_region: &str) -> (String, String) {
+    let split = version_region.trim_end_matches(|c: char| c.is_ascii_alphabetic()).len();
+    return (version_region[..split].to_string(), version_region[split..].to_string());
+}
+
+pub fn read_db(db_file: &str) -> std::io::Result<KnownDb> {
+    if !Path::new(db_file).is_file() {
+        return Ok(KnownDb::default());
+    }
+    return meta::read_document(db_file);
+}
+
+pub struct Hashes {
+    pub sha1: String,
+    pub bs1_sha1: String,
+    pub bs2_sha1: String,
+}
+
+pub fn hashes(file_data: &[u8], image: &BSImageRef) -> Hashes {
+    return Hashes {
+        sha1:       meta::sha1_hex(file_data),
+        bs1_sha1:   meta::sha1_hex(image.bs1_data),
+        bs2_sha1:   meta::sha1_hex(image.bs2_data),
+    };
+}
+
+pub fn sections(image: &BSImageRef) -> BSSectionsMeta {
+    let to_meta = |addr: &[u32], len: &[u32]| -> Vec<BSSectionMeta> {
+        return addr.iter().zip(len).map(|(&addr, &len)| BSSectionMeta { name: None, addr, len }).collect();
+    };
+    return BSSectionsMeta {
+        text:   to_meta(&image.text_addr, &image.text_len),
+        data:   to_meta(&image.data_addr, &image.data_len),
+        bss:    to_meta(&image.bss.iter().map(|x| x.addr).collect::<Vec<u32>>(), &image.bss.iter().map(|x| x.size).collect::<Vec<u32>>()),
+    };
+}
+
+#[derive(PartialEq, Debug)]
+pub enum MatchKind {
+    Image,
+    BS1AndBS2,
+    BS2,
+    BS1,
+}
+
+fn hash_match(known: &KnownImage, hashes: &Hashes) -> Option<MatchKind> {
+    let same = |known: &Option<String>, hash: &String| known.as_ref().is_some_and(|k| k.eq_ignore_ascii_case(hash));
+
+    if same(&known.sha1, &hashes.sha1) {
+        return Some(MatchKind::Image);
+    }
+    return match (same(&known.bs1_sha1, &hashes.bs1_sha1), same(&known.bs2_sha1, &hashes.bs2_sha1)) {
+        (true, true)  => Some(MatchKind::BS1AndBS2),
+        (false, true) => Some(MatchKind::BS2),
+        (true, false) => Some(MatchKind::BS1),
+        _             => None,
+    };
+}
+
+// Best hash matches first
+pub fn find_matches<'a>(known: &'a [KnownImage], hashes: &Hashes) -> Vec<(&'a KnownImage, MatchKind)> {
+    let mut matches: Vec<(&KnownImage, MatchKind)> = known.iter().filter_map(|k| hash_match(k, hashes).map(|m| (k, m))).collect();
+    matches.sort_by_key(|(_, m)| match m { MatchKind::Image => 0, MatchKind::BS1AndBS2 => 1, MatchKind::BS2 => 2, MatchKind::BS1 => 3 });
+    return matches;
+}
+
+fn section_similarity(a: &BSSectionMeta, b: &BSSectionMeta) -> f64 {
+    if a.addr == b.addr && a.len == b.len {
+        return 1.0;
+    }
+    let larger = a.len.max(b.len);
+    if larger == 0 {
+        return 0.0;
+    }
+    // Scaled by how close the sizes are, and halved if the section moved
+    let size = a.len.min(b.len) as f64 / larger as f64;
+    return if a.addr == b.addr { size } else { size / 2.0 };
+}
+
+// 0.0 - 1.0, how alike two section layouts are
+pub fn layout_similarity(a: &BSSectionsMeta, b: &BSSectionsMeta) -> f64 {
+    let pairs: Vec<(&BSSectionMeta, &BSSectionMeta)> = a.text.iter().zip(&b.text)
+                                                          .chain(a.data.iter().zip(&b.data))
+                                                          .chain(a.bss.iter().zip(&b.bss))
+                                                          .collect();
+    if pairs.is_empty() {
+        return 0.0;
+    }
+    return pairs.iter().map(|(a, b)| section_similarity(a, b)).sum::<f64>() / pairs.len() as f64;
+}
+
+pub fn closest<'a>(known: &'a [KnownImage], layout: &BSSectionsMeta) -> Option<(&'a KnownImage, f64)> {
+    return known.iter()
+                .filter_map(|k| k.sections.as_ref().map(|s| (k, layout_similarity(s, layout))))
+                .max_by(|a, b| a.1.total_cmp(&b.1));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_sha1(hash: &Option<String>) -> bool {
+        return hash.as_ref().is_none_or(|h| h.len() == 40 && h.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+
+    #[test]
+    fn builtin_table_is_well_formed() {
+        for known in builtin().unwrap() {
+            let name = format!("{}{}", known.version, known.region);
+            assert!(title_version_of(&name).is_some(), "{} is not a System Menu version", name);
+            assert!(known.title_version.is_none_or(|v| version_name(v) == Some(&name)), "{} has the wrong title version", name);
+            assert!(is_sha1(&known.sha1) && is_sha1(&known.bs1_sha1) && is_sha1(&known.bs2_sha1), "{} has a bad hash", name);
+            assert!(known.sha1.is_some() || known.bs1_sha1.is_some() || known.bs2_sha1.is_some(), "{} has no hash", name);
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use crate::apploader::{self, Apploader};
+use crate::bootstage::BSImage;
+use crate::buildinfo::{self, BuildInfo, FoundString};
+use crate::dol::DOLImage;
+use crate::elf::{Elf32Hdr, Elf32Phdr};
+use crate::identify;
+use crate::splits::{self, Splits};
+use crate::symbols::{self, SymbolMap};
+use crate::title::{self, Tmd};
+use crate::wad::Wad;
+
+// Names from a decomp project (dtk symbols.txt/splits.txt) to label addresses with
+#[derive(Default)]
+pub struct Labels {
+    pub symbols: Option<SymbolMap>,
+    pub splits: Option<Splits>,
+}
+
+impl Labels {
+    pub fn read(symbols_file: &Option<String>, splits_file: &Option<String>) -> std::io::Result<Labels> {
+        return Ok(Labels {
+            symbols:    symbols_file.as_ref().map(|f| symbols::read_file(f)).transpose()?,
+            splits:     splits_file.as_ref().map(|f| splits::read_file(f)).transpose()?,
+        });
+    }
+
+    // " (OSInit+0x4 in OS/OS.c)"
+    fn name(&self, addr: u32) -> String {
+        let symbol = self.symbols.as_ref().and_then(|s| s.describe(addr));
+        let unit = self.splits.as_ref().and_then(|s| s.unit_of(addr)).map(|(unit, _)| unit.name.as_str());
+        return match (symbol, unit) {
+            (Some(symbol), Some(unit)) => format!(" ({} in {})", symbol, unit),
+            (Some(symbol), None)       => format!(" ({})", symbol),
+            (None, Some(unit))         => format!(" (in {})", unit),
+            (None, None)               => String::new(),
+        };
+    }
+
+    // ", 12 symbols, 3 units"
+    fn counts(&self, addr: u32, len: u32) -> String {
+        let inside = |start: u32| start >= addr && start - addr < len;
+        let mut counts = String::new();
+        if let Some(symbols) = &self.symbols {
+            counts += &format!(", {} symbols", symbols.symbols.iter().filter(|s| inside(s.addr)).count());
+        }
+        if let Some(splits) = &self.splits {
+            counts += &format!(", {} units", splits.units.iter().filter(|u| u.ranges.iter().any(|r| inside(r.start))).count());
+        }
+        return counts;
+    }
+}
+
+fn print_section(name: &str, addr: u32, len: u32) {
+    print_section_labeled(name, addr, len, &Labels::default());
+}
+
+fn print_section_labeled(name: &str, addr: u32, len: u32, labels: &Labels) {
+    if len == 0 {
+        println!("  {:<12} (empty)", name);
+    }
+    else {
+        println!("  {:<12} {:#010X}-{:#010X} ({:#X} bytes{})", name, addr, addr.wrapping_add(len), len, labels.counts(addr, len));
+    }
+}
+
+pub fn print_bootstage(image: &BSImage) {
+    print_bootstage_labeled(image, &Labels::default());
+}
+
+pub fn print_bootstage_labeled(image: &BSImage, labels: &Labels) {
+    println!("Profile:     {} ({})", image.profile.name, image.profile.description);
+    println!("BS1:         {:#010X} ({:#X} bytes), entry {:#010X}{}", image.bs1_addr, image.bs1_len, image.bs1_entry, labels.name(image.bs1_entry));
+    println!("BS2:         {:#010X} ({:#X} bytes), entry {:#010X}{}", image.bs2_addr, image.bs2_len, image.bs2_entry, labels.name(image.bs2_entry));
+    if image.stub_len != 0 {
+        println!("Stub:        {:#010X} ({:#X} bytes)", image.stub_addr, image.stub_len);
+    }
+    if image.profile.bs2_pad != 0 {
+        println!("Pad block:   {}", if crate::bootstage::verify_unk_data(image) { "present" } else { "none" });
+    }
+
+    println!("BS2 sections:");
+    for i in 0..image.text_addr.len() {
+        print_section_labeled(&image.profile.text_names[i], image.text_addr[i], image.text_len[i], labels);
+    }
+    for i in 0..image.data_addr.len() {
+        print_section_labeled(&image.profile.data_names[i], image.data_addr[i], image.data_len[i], labels);
+    }
+    for i in 0..image.bss_addr.len() {
+        print_section_labeled(&image.profile.bss_names[i], image.bss_addr[i], image.bss_len[i], labels);
+    }
+
+    let build_info = buildinfo::analyze(image);
+    if !build_info.is_empty() {
+        print_build_info(&build_info);
+    }
+}
+
+fn print_found_strings(title: &str, strings: &[FoundString]) {
+    if strings.is_empty() {
+        return;
+    }
+    println!("  {}:", title);
+    for found in strings {
+        println!("    {:#010X} \"{}\"", found.addr, found.text.escape_debug());
+    }
+}
+
+pub fn print_build_info(info: &BuildInfo) {
+    println!("Build info:");
+
+    // One line per SDK and build kind, then the libraries
+    let mut sdks: Vec<(&str, &str)> = info.banners.iter().map(|b| (b.sdk.as_str(), b.build.as_str())).collect();
+    sdks.dedup();
+    for (sdk, build) in &sdks {
+        println!("  SDK:       {} ({} build)", sdk, build);
+    }
+    if !info.banners.is_empty() {
+        println!("  Libraries:");
+    }
+    for banner in &info.banners {
+        let compiler = match (banner.cwcc, banner.compiler_build) {
+            (Some(cwcc), Some(build)) => format!(" __CWCC__ {:#X} build {}", cwcc, build),
+            (Some(cwcc), None)        => format!(" __CWCC__ {:#X}", cwcc),
+            _                         => String::new(),
+        };
+        println!("    {:<12} {} {}{}", banner.library, banner.date, banner.time, compiler);
+    }
+
+    let mut compilers: Vec<u32> = info.banners.iter().filter_map(|b| b.cwcc).collect();
+    compilers.sort();
+    compilers.dedup();
+    for cwcc in compilers {
+        println!("  Compiler:  CodeWarrior, __CWCC__ {:#X}", cwcc);
+    }
+
+    print_found_strings("Compiler strings", &info.compilers);
+    print_found_strings("Version strings", &info.versions);
+    print_found_strings("Build dates", &info.dates);
+}
+
+pub fn print_apploader(loader: &Apploader) {
+    println!("Date:        {}", loader.date_string());
+    println!("Entry point: {:#010X}", loader.entry_point);
+    println!("Size:        {:#X} (+ {:#X} bytes trailer)", loader.size, loader.trailer_size);
+    println!("Loaded:");
+    print_section("code", apploader::LOAD_ADDR, loader.size);
+    print_section("trailer", apploader::LOAD_ADDR.wrapping_add(loader.size), loader.trailer_size);
+}
+
+pub fn print_tmd(tmd: &Tmd) {
+    let version = match identify::version_name(tmd.title_version) {
+        Some(name) if tmd.title_id == title::SYSTEM_MENU_TITLE_ID => format!(" (System Menu {})", name),
+        _                                                         => String::new(),
+    };
+    println!("Title:       {} v{}{}", title::title_string(tmd.title_id), tmd.title_version, version);
+    println!("Contents:");
+    for content in &tmd.contents {
+        println!("  {:08x} index {:<3} type {:#06X} {:#10X} bytes{}",
+                 content.content_id, content.index, content.kind, content.size,
+                 if content.index == tmd.boot_index { " (boot)" } else { "" });
+    }
+}
+
+pub fn print_wad(wad: &Wad) {
+    println!("Common key:  index {}", wad.ticket.common_key_index);
+    print_tmd(&wad.tmd);
+}
+
+pub fn print_dol(header: &DOLImage, labels: &Labels) {
+    println!("Entry point: {:#010X}{}", header.entry_point, labels.name(header.entry_point));
+    println!("Sections:");
+    for i in 0..header.text_addr.len() {
+        if header.text_size[i] != 0 {
+            print_section_labeled(&format!("text{}", i), header.text_addr[i], header.text_size[i], labels);
+        }
+    }
+    for i in 0..header.data_addr.len() {
+        if header.data_size[i] != 0 {
+            print_section_labeled(&format!("data{}", i), header.data_addr[i], header.data_size[i], labels);
+        }
+    }
+    print_section_labeled("bss", header.bss_addr, header.bss_size, labels);
+}
+
+pub fn print_elf(header: &Elf32Hdr, prg_hdrs: &[Elf32Phdr]) {
+    println!("Entry point: {:#010X}", header.e_entry);
+    println!("Segments:");
+    for (i, phdr) in prg_hdrs.iter().enumerate() {
+        let flags = format!("{}{}{}",
+                            if phdr.p_flags & 4 != 0 { "r" } else { "-" },
+                            if phdr.p_flags & 2 != 0 { "w" } else { "-" },
+                            if phdr.p_flags & 1 != 0 { "x" } else { "-" });
+        println!("  {:<3} type {:#X} {} {:#010X} file {:#X} mem {:#X}",
+                 i, phdr.p_type, flags, phdr.p_vaddr, phdr.p_filesz, phdr.p_memsz);
+    }
+}
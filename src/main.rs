@@ -1,9 +1,40 @@
+// Functions in this crate spell out their returns explicitly.
+#![allow(clippy::needless_return)]
+
 #[allow(dead_code, unused)]
 use argp::{FromArgs};
 
+use detect::FormatKind;
+
+pub mod apploader;
+pub mod asmsplit;
 pub mod bootstage;
+pub mod buildinfo;
+pub mod compare;
+pub mod detect;
+pub mod disasm;
 pub mod dol;
 pub mod elf;
+pub mod fonts;
+pub mod gcipl;
+pub mod identify;
+pub mod info;
+pub mod meta;
+pub mod mwmap;
+pub mod nand;
+pub mod objdiff;
+pub mod ppc;
+pub mod profile;
+pub mod progress;
+pub mod project;
+pub mod retools;
+pub mod sffs;
+pub mod splits;
+pub mod symbols;
+pub mod title;
+pub mod verify;
+pub mod wad;
+pub mod yay0;
 
 /// Tool for IPL BootStage files
 #[derive(FromArgs, PartialEq, Debug)]
@@ -14,25 +45,125 @@ struct ProcessArg {
 
 #[derive(FromArgs, PartialEq, Debug)]
 #[argp(subcommand)]
+#[allow(clippy::upper_case_acronyms)]
 enum ProcessEnum {
     DTK(DTKArgs),
+    DTKPROJECT(DTKProjectArgs),
+    OBJDIFF(ObjdiffArgs),
+    SPLIT(SplitArgs),
     CONVERT(ConvertArgs),
+    DESCRIBE(DescribeArgs),
+    REBUILD(RebuildArgs),
+    PROFILES(ProfilesArgs),
+    DETECT(DetectArgs),
+    INFO(InfoArgs),
+    INSTALL(InstallArgs),
+    NANDDUMP(NandDumpArgs),
+    IDENTIFY(IdentifyArgs),
+    VERIFY(VerifyArgs),
+    PROGRESS(ProgressArgs),
+    COMPARE(CompareArgs),
+    LOOKUP(LookupArgs),
+    DISASM(DisasmArgs),
+    MAP2SYMS(Map2SymsArgs),
+    EXPORTMAP(ExportMapArgs),
+    REIMPORT(ReImportArgs),
+    EXTRACTFONTS(ExtractFontsArgs),
 }
 
-/// Convert BootStage to DOL file for DTK.
+/// Convert BootStage (or GameCube IPL BS2, or apploader) to DOL or ELF file for DTK.
 #[derive(FromArgs, PartialEq, Debug)]
 #[argp(subcommand, name = "dtk")]
 struct DTKArgs {
-    /// Input BootStage file.
+    /// Input BootStage, GameCube IPL or apploader file.
     #[argp(option, short = 'i')]
     in_file: String,
     
     /// Output DOL file.
     #[argp(option, short = 'o')]
     out_file: String,
+
+    /// Target profile. (wii, vwii, ndev, gc or a profile file; auto-detected if omitted)
+    #[argp(option, short = 'p')]
+    profile: Option<String>,
+
+    /// Write an ELF with named sections instead of a DOL.
+    #[argp(switch, short = 'e')]
+    elf: bool,
 }
 
-/// Convert ELF to BootStage.
+/// Write a decomp-toolkit project (DOL, config.yml, symbols.txt and splits.txt) for a BootStage or GameCube IPL.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argp(subcommand, name = "dtk-project")]
+struct DTKProjectArgs {
+    /// Input BootStage or GameCube IPL file.
+    #[argp(option, short = 'i')]
+    in_file: String,
+
+    /// Output project directory.
+    #[argp(option, short = 'o')]
+    out_dir: String,
+
+    /// Target profile. (wii, vwii, ndev, gc or a profile file; auto-detected if omitted)
+    #[argp(option, short = 'p')]
+    profile: Option<String>,
+}
+
+/// Write an objdiff.json and the target object of every unit in a splits.txt, cut out of a BootStage or GameCube IPL.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argp(subcommand, name = "objdiff")]
+struct ObjdiffArgs {
+    /// Input BootStage or GameCube IPL file.
+    #[argp(option, short = 'i')]
+    in_file: String,
+
+    /// dtk splits.txt with the units.
+    #[argp(option)]
+    splits: String,
+
+    /// Symbols to put in the target objects (dtk symbols.txt or a symbol map).
+    #[argp(option, short = 's')]
+    symbols: Option<String>,
+
+    /// Project root to write objdiff.json to.
+    #[argp(option, short = 'o')]
+    out_dir: String,
+
+    /// Build directory, relative to the project root. (build/<input name> if omitted)
+    #[argp(option)]
+    build_dir: Option<String>,
+
+    /// Target profile. (wii, vwii, ndev, gc or a profile file; auto-detected if omitted)
+    #[argp(option, short = 'p')]
+    profile: Option<String>,
+}
+
+/// Write an assembly file per unit of a splits.txt and a linker script that links them back into BS2.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argp(subcommand, name = "split")]
+struct SplitArgs {
+    /// Input BootStage or GameCube IPL file.
+    #[argp(option, short = 'i')]
+    in_file: String,
+
+    /// dtk splits.txt with the units.
+    #[argp(option)]
+    splits: String,
+
+    /// Symbols to label the assembly with (dtk symbols.txt or a symbol map).
+    #[argp(option, short = 's')]
+    symbols: Option<String>,
+
+    /// Directory to write asm/ and ldscript.ld to.
+    #[argp(option, short = 'o')]
+    out_dir: String,
+
+    /// Target profile. (wii, vwii, ndev, gc or a profile file; auto-detected if omitted)
+    #[argp(option, short = 'p')]
+    profile: Option<String>,
+}
+
+/// Convert ELF to BootStage (or GameCube IPL, or apploader).
 #[derive(FromArgs, PartialEq, Debug)]
 #[argp(subcommand, name = "convert")]
 struct ConvertArgs {
@@ -40,7 +171,7 @@ struct ConvertArgs {
     #[argp(option, short = 'i')]
     in_file: String,
     
-    /// Base Bootstage file. (For meta data and BS1)
+    /// Base Bootstage, GameCube IPL or apploader file. (For meta data and BS1)
     #[argp(option, short = 'b')]
     base_file: String,
 
@@ -55,38 +186,616 @@ struct ConvertArgs {
     /// Output DOL file.
     #[argp(option, short = 'o')]
     out_file: String,
+
+    /// Target profile. (wii, vwii, ndev, gc or a profile file; auto-detected if omitted)
+    #[argp(option, short = 'p')]
+    profile: Option<String>,
+}
+
+/// Describe BootStage as a JSON/YAML/TOML document.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argp(subcommand, name = "describe")]
+struct DescribeArgs {
+    /// Input BootStage file.
+    #[argp(option, short = 'i')]
+    in_file: String,
+
+    /// Output document. (.json, .yaml/.yml or .toml)
+    #[argp(option, short = 'o')]
+    out_file: String,
+
+    /// Store BS1/BS2 as <sha1>.bin in this directory instead of next to the document.
+    #[argp(option, short = 'd')]
+    blob_dir: Option<String>,
+
+    /// Target profile. (wii, vwii, ndev or a profile file; auto-detected if omitted)
+    #[argp(option, short = 'p')]
+    profile: Option<String>,
+}
+
+/// Rebuild BootStage from a JSON/YAML/TOML document.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argp(subcommand, name = "rebuild")]
+struct RebuildArgs {
+    /// Input document. (.json, .yaml/.yml or .toml)
+    #[argp(option, short = 'i')]
+    in_file: String,
+
+    /// Directory holding <sha1>.bin payloads referenced by hash.
+    #[argp(option, short = 'd')]
+    blob_dir: Option<String>,
+
+    /// Output BootStage file.
+    #[argp(option, short = 'o')]
+    out_file: String,
+
+    /// Target profile. (overrides the one named in the document)
+    #[argp(option, short = 'p')]
+    profile: Option<String>,
+}
+
+/// List the built-in target profiles.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argp(subcommand, name = "profiles")]
+struct ProfilesArgs {
+    /// Write this profile as a JSON/YAML/TOML template for a custom profile.
+    #[argp(option, short = 'o')]
+    out_file: Option<String>,
+
+    /// Profile to write as template. (default: wii)
+    #[argp(option, short = 'p')]
+    profile: Option<String>,
+}
+
+/// Detect what kind of file this is.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argp(subcommand, name = "detect")]
+struct DetectArgs {
+    /// Input file.
+    #[argp(option, short = 'i')]
+    in_file: String,
+}
+
+/// Show information about a BootStage, GameCube IPL, apploader, WAD, DOL or ELF.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argp(subcommand, name = "info")]
+struct InfoArgs {
+    /// Input file.
+    #[argp(option, short = 'i')]
+    in_file: Option<String>,
+
+    /// Input WAD, its boot content is shown as a BootStage.
+    #[argp(option)]
+    wad: Option<String>,
+
+    /// Dolphin NAND root (the Wii folder), shows the System Menu's boot content.
+    #[argp(option, short = 'n')]
+    nand: Option<String>,
+
+    /// Wii common key for WADs. (16 byte key, keys.bin or hex; default: common-key.bin or keys.bin)
+    #[argp(option, short = 'k')]
+    common_key: Option<String>,
+
+    /// Symbols to label addresses with. (dtk symbols.txt or a symbol map)
+    #[argp(option, short = 's')]
+    symbols: Option<String>,
+
+    /// dtk splits.txt to label addresses with their translation unit.
+    #[argp(option)]
+    splits: Option<String>,
+
+    /// Target profile. (wii, vwii, ndev, gc or a profile file; auto-detected if omitted)
+    #[argp(option, short = 'p')]
+    profile: Option<String>,
+}
+
+/// Install a BootStage as the System Menu's boot content in a Dolphin NAND root.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argp(subcommand, name = "install")]
+struct InstallArgs {
+    /// Input BootStage file.
+    #[argp(option, short = 'i')]
+    in_file: String,
+
+    /// Dolphin NAND root (the Wii folder).
+    #[argp(option, short = 'n')]
+    nand: String,
+}
+
+/// Extract the System Menu BootStage from a console NAND dump.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argp(subcommand, name = "nand-dump")]
+struct NandDumpArgs {
+    /// Input NAND dump (nand.bin, with or without ECC).
+    #[argp(option, short = 'i')]
+    in_file: String,
+
+    /// BootMii keys.bin of the console. (default: the keys appended to the dump)
+    #[argp(option, short = 'k')]
+    keys: Option<String>,
+
+    /// Output BootStage file. (shows its information if omitted)
+    #[argp(option, short = 'o')]
+    out_file: Option<String>,
+
+    /// List the installed titles instead.
+    #[argp(switch)]
+    list_titles: bool,
+
+    /// Target profile. (wii, vwii, ndev or a profile file; auto-detected if omitted)
+    #[argp(option, short = 'p')]
+    profile: Option<String>,
+}
+
+/// Identify which System Menu version a BootStage is.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argp(subcommand, name = "identify")]
+struct IdentifyArgs {
+    /// Input BootStage file.
+    #[argp(option, short = 'i')]
+    in_file: String,
+
+    /// Database of known images, used next to the built-in table. (.json, .yaml/.yml or .toml)
+    #[argp(option, short = 'd')]
+    db: Option<String>,
+
+    /// Add the input to the database as this version and region. (e.g. 4.3U)
+    #[argp(option)]
+    record: Option<String>,
+
+    /// Title version to record. (default: the usual one of the version)
+    #[argp(option)]
+    title_version: Option<u16>,
+
+    /// Target profile. (wii, vwii, ndev or a profile file; auto-detected if omitted)
+    #[argp(option, short = 'p')]
+    profile: Option<String>,
+}
+
+/// Check that a built BootStage matches the original, and show where it doesn't.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argp(subcommand, name = "verify")]
+struct VerifyArgs {
+    /// Built BootStage file.
+    #[argp(positional)]
+    built: String,
+
+    /// Original BootStage file to compare against.
+    #[argp(positional)]
+    original: Option<String>,
+
+    /// Expected SHA-1. (hex or a sha1sum file)
+    #[argp(option)]
+    sha1: Option<String>,
+
+    /// Symbols to name the functions that differ. (dtk symbols.txt, address size name lines or a Dolphin .map)
+    #[argp(option, short = 'm')]
+    map: Option<String>,
+
+    /// Target profile. (wii, vwii, ndev or a profile file; auto-detected if omitted)
+    #[argp(option, short = 'p')]
+    profile: Option<String>,
+}
+
+/// Report how much of an original BootStage or GameCube IPL a built ELF matches, per section and translation unit.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argp(subcommand, name = "progress")]
+struct ProgressArgs {
+    /// Original BootStage or GameCube IPL file.
+    #[argp(option)]
+    target: String,
+
+    /// Built ELF file.
+    #[argp(option)]
+    built: String,
+
+    /// Symbols of the original, with sizes. (dtk symbols.txt or a symbol map)
+    #[argp(option, short = 's')]
+    symbols: String,
+
+    /// dtk splits.txt for the per-unit numbers.
+    #[argp(option)]
+    splits: Option<String>,
+
+    /// Output JSON report file.
+    #[argp(option, short = 'o')]
+    out_file: Option<String>,
+
+    /// Target profile. (wii, vwii, ndev, gc or a profile file; auto-detected if omitted)
+    #[argp(option, short = 'p')]
+    profile: Option<String>,
+}
+
+/// Compare every function of a built ELF with the original. Relocated fields may differ if they point into the same symbol.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argp(subcommand, name = "compare")]
+struct CompareArgs {
+    /// Original BootStage or GameCube IPL file.
+    #[argp(option)]
+    target: String,
+
+    /// Built ELF file.
+    #[argp(option)]
+    built: String,
+
+    /// Symbols of the original, with sizes. (dtk symbols.txt or a symbol map)
+    #[argp(option, short = 's')]
+    symbols: String,
+
+    /// List the exact matches too.
+    #[argp(switch)]
+    all: bool,
+
+    /// Target profile. (wii, vwii, ndev, gc or a profile file; auto-detected if omitted)
+    #[argp(option, short = 'p')]
+    profile: Option<String>,
+}
+
+/// Name the function and translation unit an address is in.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argp(subcommand, name = "lookup")]
+struct LookupArgs {
+    /// Address. (hex)
+    #[argp(positional)]
+    addr: String,
+
+    /// Symbols. (dtk symbols.txt or a symbol map)
+    #[argp(option, short = 's')]
+    symbols: Option<String>,
+
+    /// dtk splits.txt for the translation unit.
+    #[argp(option)]
+    splits: Option<String>,
+
+    /// BootStage or GameCube IPL file for the section.
+    #[argp(option, short = 'i')]
+    in_file: Option<String>,
+
+    /// Target profile. (wii, vwii, ndev, gc or a profile file; auto-detected if omitted)
+    #[argp(option, short = 'p')]
+    profile: Option<String>,
+}
+
+/// Disassemble BS1 and the BS2 text sections of a BootStage or GameCube IPL, or an address range of them.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argp(subcommand, name = "disasm")]
+struct DisasmArgs {
+    /// Input BootStage or GameCube IPL file.
+    #[argp(option, short = 'i')]
+    in_file: String,
+
+    /// First address to disassemble. (hex)
+    #[argp(option)]
+    start: Option<String>,
+
+    /// Address to stop at. (hex, exclusive)
+    #[argp(option)]
+    end: Option<String>,
+
+    /// Symbols to name branch targets with. (dtk symbols.txt or a symbol map)
+    #[argp(option, short = 's')]
+    symbols: Option<String>,
+
+    /// Write assembler source (GNU as -mgekko -mregnames or mwasm) instead of a listing.
+    #[argp(switch)]
+    asm: bool,
+
+    /// Output file. (standard output if omitted)
+    #[argp(option, short = 'o')]
+    out_file: Option<String>,
+
+    /// Target profile. (wii, vwii, ndev, gc or a profile file; auto-detected if omitted)
+    #[argp(option, short = 'p')]
+    profile: Option<String>,
+}
+
+/// Convert a CodeWarrior linker map to a dtk symbols.txt.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argp(subcommand, name = "map2syms")]
+struct Map2SymsArgs {
+    /// Input .MAP file.
+    #[argp(option, short = 'i')]
+    in_file: String,
+
+    /// Output symbols.txt file.
+    #[argp(option, short = 'o')]
+    out_file: String,
+
+    /// BootStage or GameCube IPL file to check the symbols against.
+    #[argp(option, short = 'b')]
+    base_file: Option<String>,
+
+    /// Target profile. (wii, vwii, ndev, gc or a profile file; auto-detected if omitted)
+    #[argp(option, short = 'p')]
+    profile: Option<String>,
+}
+
+/// Write a Dolphin symbol map from an ELF's symbol table.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argp(subcommand, name = "export-map")]
+struct ExportMapArgs {
+    /// Input ELF file.
+    #[argp(option, short = 'i')]
+    in_file: String,
+
+    /// Output .map file.
+    #[argp(option, short = 'o')]
+    out_file: String,
+}
+
+/// Write a Ghidra XML program and an IDA Python script (plus the .bytes both load) for a BootStage or GameCube IPL.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argp(subcommand, name = "re-import")]
+struct ReImportArgs {
+    /// Input BootStage or GameCube IPL file.
+    #[argp(option, short = 'i')]
+    in_file: String,
+
+    /// Output path without extension, .xml, .py and .bytes get added.
+    #[argp(option, short = 'o')]
+    out_base: String,
+
+    /// Symbols to name and create functions from (dtk symbols.txt or a symbol map).
+    #[argp(option, short = 's')]
+    symbols: Option<String>,
+
+    /// Target profile. (wii, vwii, ndev, gc or a profile file; auto-detected if omitted)
+    #[argp(option, short = 'p')]
+    profile: Option<String>,
+}
+
+/// Extract the fonts of a GameCube IPL ROM as PNG sheets and metrics, or put edited ones back.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argp(subcommand, name = "extract-fonts")]
+struct ExtractFontsArgs {
+    /// Input GameCube IPL ROM.
+    #[argp(option, short = 'i')]
+    in_file: String,
+
+    /// Output directory, or the output ROM with --insert.
+    #[argp(option, short = 'o')]
+    out_file: String,
+
+    /// Directory with edited fonts to recompress into the ROM.
+    #[argp(option)]
+    insert: Option<String>,
 }
 
-fn main() -> std::io::Result<()> {
+const BOOTSTAGE_KINDS: [FormatKind; 2] = [FormatKind::WiiBootStage, FormatKind::VWiiBootStage];
+const BOOT_IMAGE_KINDS: [FormatKind; 3] = [FormatKind::WiiBootStage, FormatKind::VWiiBootStage, FormatKind::GameCubeIpl];
+const LOADER_KINDS: [FormatKind; 4] = [FormatKind::WiiBootStage, FormatKind::VWiiBootStage, FormatKind::GameCubeIpl, FormatKind::Apploader];
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> std::io::Result<()> {
     let args: ProcessArg = argp::parse_args_or_exit(argp::DEFAULT);
     match args.processes {
-        ProcessEnum::DTK(le_args)     => bs_to_dtk(le_args.in_file, le_args.out_file)?,
+        ProcessEnum::DTK(le_args)     => bs_to_dtk(le_args.in_file, le_args.out_file, le_args.profile, le_args.elf)?,
+        ProcessEnum::DTKPROJECT(le_args) => dtk_project(le_args.in_file, le_args.out_dir, le_args.profile)?,
+        ProcessEnum::OBJDIFF(le_args) => objdiff_project(le_args.in_file, le_args.splits, le_args.symbols, le_args.out_dir, le_args.build_dir, le_args.profile)?,
+        ProcessEnum::SPLIT(le_args)   => split_units(le_args.in_file, le_args.splits, le_args.symbols, le_args.out_dir, le_args.profile)?,
         ProcessEnum::CONVERT(le_args) => {
-            let image_size : usize = if let Some(v) = le_args.image_size { v } else { 0xFFFFFFFF };
-            let base_addr  : u32   = if let Some(v) = le_args.base_addr  { v } else { 0xFFFFFFFF };
-            elf_to_bs(le_args.base_file, le_args.in_file, le_args.out_file, image_size, base_addr)?
+            let image_size : usize = le_args.image_size.unwrap_or(0xFFFFFFFF);
+            let base_addr  : u32   = le_args.base_addr.unwrap_or(0xFFFFFFFF);
+            elf_to_bs(le_args.base_file, le_args.in_file.clone(), le_args.out_file.clone(), image_size, base_addr, le_args.profile)?;
+
+            // Symbols for Dolphin (Load Symbol Map), next to the image
+            let map_file = std::path::Path::new(&le_args.out_file).with_extension("map").to_string_lossy().to_string();
+            export_map(&le_args.in_file, &map_file)?;
+        },
+        ProcessEnum::DESCRIBE(le_args) => {
+            check_format(&le_args.in_file, &BOOTSTAGE_KINDS, "describe")?;
+            let file_data = bootstage::map_file(&le_args.in_file)?;
+            let image = bootstage::BSImageRef::parse_with_profile(&file_data, profile::select(&le_args.profile, &file_data)?)?;
+            meta::write_meta(&le_args.out_file, &image, le_args.blob_dir.as_deref())?
+        },
+        ProcessEnum::REBUILD(le_args) => {
+            let image = meta::meta_to_image(&le_args.in_file, le_args.blob_dir.as_deref(), &le_args.profile)?;
+            bootstage::create_file(&le_args.out_file, &image)
         },
+        ProcessEnum::PROFILES(le_args) => list_profiles(le_args.out_file, le_args.profile)?,
+        ProcessEnum::DETECT(le_args)   => detect_file(le_args.in_file)?,
+        ProcessEnum::INFO(le_args)     => {
+            if let Some(nand_root) = le_args.nand {
+                return nand_info(nand_root, le_args.profile);
+            }
+            let in_file = le_args.wad.or(le_args.in_file)
+                                 .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "info needs -i or --wad"))?;
+            let labels = info::Labels::read(&le_args.symbols, &le_args.splits)?;
+            info_file(in_file, le_args.profile, le_args.common_key, &labels)?
+        },
+        ProcessEnum::INSTALL(le_args)  => install_bootstage(le_args.in_file, le_args.nand)?,
+        ProcessEnum::NANDDUMP(le_args) => nand_dump(le_args.in_file, le_args.keys, le_args.out_file, le_args.list_titles, le_args.profile)?,
+        ProcessEnum::IDENTIFY(le_args) => identify_file(le_args.in_file, le_args.db, le_args.record, le_args.title_version, le_args.profile)?,
+        ProcessEnum::VERIFY(le_args)   => verify_file(le_args.built, le_args.original, le_args.sha1, le_args.map, le_args.profile)?,
+        ProcessEnum::PROGRESS(le_args) => progress_report(le_args.target, le_args.built, le_args.symbols, le_args.splits, le_args.out_file, le_args.profile)?,
+        ProcessEnum::COMPARE(le_args)  => compare_functions(le_args.target, le_args.built, le_args.symbols, le_args.all, le_args.profile)?,
+        ProcessEnum::LOOKUP(le_args)   => lookup_addr(le_args.addr, le_args.symbols, le_args.splits, le_args.in_file, le_args.profile)?,
+        ProcessEnum::DISASM(le_args)   => disassemble(le_args.in_file, le_args.start, le_args.end, le_args.symbols, le_args.asm, le_args.out_file, le_args.profile)?,
+        ProcessEnum::MAP2SYMS(le_args) => map_to_symbols(le_args.in_file, le_args.out_file, le_args.base_file, le_args.profile)?,
+        ProcessEnum::EXPORTMAP(le_args) => {
+            if !export_map(&le_args.in_file, &le_args.out_file)? {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{} has no symbol table", le_args.in_file)));
+            }
+        },
+        ProcessEnum::REIMPORT(le_args) => re_import(le_args.in_file, le_args.out_base, le_args.symbols, le_args.profile)?,
+        ProcessEnum::EXTRACTFONTS(le_args) => ipl_fonts(le_args.in_file, le_args.out_file, le_args.insert)?,
     }
     Ok(())
 }
 
-fn bs_to_dtk(in_file: String, out_file: String) -> std::io::Result<()> {
-    let image = bootstage::open_file(&in_file);
-    dol::turn_raw_to_dol(&out_file,
-                        &image.bs2_data,
-                        &image.text_addr,
-                        &image.text_len,
-                        &image.data_addr,
-                        &image.data_len,
-                        &image.bss_addr,
-                        &image.bss_len,
-                         image.bs2_entry,
-                         image.bs2_addr);
+// Makes sure the input is something the command can take, instead of parsing garbage.
+fn check_format(file_name: &String, allowed: &[FormatKind], command: &str) -> std::io::Result<detect::Detection> {
+    let file_data = bootstage::map_file(file_name)?;
+    let detection = detect::detect(&file_data);
+
+    if !allowed.contains(&detection.kind) {
+        let wanted: Vec<String> = allowed.iter().map(|k| k.to_string()).collect();
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                                       format!("{} was detected as {} ({}% sure: {}), but {} takes a {}",
+                                               file_name, detection.kind, detection.confidence, detection.reasons.join(", "),
+                                               command, wanted.join(" or "))));
+    }
+
+    Ok(detection)
+}
+
+// Opens anything with BS1/BS2 boot stages with the parser for its format.
+fn open_boot_image(file_name: &String, profile: &Option<String>, command: &str) -> std::io::Result<(FormatKind, bootstage::BSImage)> {
+    let detection = check_format(file_name, &BOOT_IMAGE_KINDS, command)?;
+    match detection.kind {
+        FormatKind::GameCubeIpl => Ok((detection.kind, gcipl::open_file(file_name, profile)?)),
+        _                       => Ok((detection.kind, bootstage::open_file(file_name, profile)?)),
+    }
+}
+
+// Writes the loaded sections as a DOL, or as an ELF with the section names (text, data, then bss order).
+#[allow(clippy::too_many_arguments)]
+fn write_dtk_file(out_file: &String,
+                  elf: bool,
+                  raw_data: &Vec<u8>,
+                  text_addr: &Vec<u32>,
+                  text_len: &Vec<u32>,
+                  data_addr: &Vec<u32>,
+                  data_len: &Vec<u32>,
+                  bss_addr: &Vec<u32>,
+                  bss_len: &Vec<u32>,
+                  entry_point: u32,
+                  base_addr: u32,
+                  section_names: &Vec<String>) -> std::io::Result<()> {
+    if elf {
+        return elf::turn_raw_to_elf(out_file, raw_data, text_addr, text_len, data_addr, data_len, bss_addr, bss_len, entry_point, base_addr, section_names);
+    }
+    dol::turn_raw_to_dol(out_file, raw_data, text_addr, text_len, data_addr, data_len, bss_addr, bss_len, entry_point, base_addr);
+    Ok(())
+}
+
+fn bs_to_dtk(in_file: String, out_file: String, profile: Option<String>, elf: bool) -> std::io::Result<()> {
+    let detection = check_format(&in_file, &LOADER_KINDS, "dtk")?;
+    if detection.kind == FormatKind::Apploader {
+        // No section table in there, so the whole thing becomes one text section
+        let loader = apploader::open_file(&in_file)?;
+        let load_end = apploader::LOAD_ADDR + loader.load_size();
+        return write_dtk_file(&out_file, elf, &loader.data,
+                              &vec![apploader::LOAD_ADDR], &vec![loader.load_size()],
+                              &vec![], &vec![],
+                              &vec![load_end], &vec![0],
+                              loader.entry_point, apploader::LOAD_ADDR,
+                              &vec![".text".to_string(), ".bss".to_string()]);
+    }
+
+    let (_, image) = open_boot_image(&in_file, &profile, "dtk")?;
+    let section_names = [image.profile.text_names.clone(), image.profile.data_names.clone(), image.profile.bss_names.clone()].concat();
+    return write_dtk_file(&out_file, elf, &image.bs2_data,
+                          &image.text_addr, &image.text_len,
+                          &image.data_addr, &image.data_len,
+                          &image.bss_addr, &image.bss_len,
+                          image.bs2_entry, image.bs2_addr,
+                          &section_names);
+}
+
+// Like open_boot_image, but borrowed and unpatched. GameCube IPLs are descrambled into rom first.
+fn parse_boot_image_ref<'a>(kind: FormatKind, file_data: &'a [u8], rom: &'a mut Vec<u8>, profile: &Option<String>) -> std::io::Result<bootstage::BSImageRef<'a>> {
+    if kind != FormatKind::GameCubeIpl {
+        return bootstage::BSImageRef::parse_with_profile(file_data, profile::select(profile, file_data)?);
+    }
+    *rom = gcipl::descramble_rom(file_data)?;
+    let profile = match profile {
+        Some(name) => profile::find(name)?,
+        None       => profile::gc(),
+    };
+    return gcipl::parse(rom, profile);
+}
+
+fn dtk_project(in_file: String, out_dir: String, profile: Option<String>) -> std::io::Result<()> {
+    let detection = check_format(&in_file, &BOOT_IMAGE_KINDS, "dtk-project")?;
+    let file_data = bootstage::map_file(&in_file)?;
+    let mut rom = Vec::new();
+    let image_ref = parse_boot_image_ref(detection.kind, &file_data, &mut rom, &profile)?;
+    let image = image_ref.to_image();
+
+    // Laid out like the decomp-toolkit project template
+    let out_path = std::path::Path::new(&out_dir);
+    let stem = std::path::Path::new(&in_file).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or("bs2".to_string());
+    let dol_file = format!("orig/{}.dol", stem);
+    std::fs::create_dir_all(out_path.join("orig"))?;
+
+    let section_names = [image.profile.text_names.clone(), image.profile.data_names.clone(), image.profile.bss_names.clone()].concat();
+    write_dtk_file(&out_path.join(&dol_file).to_string_lossy().to_string(), false, &image.bs2_data,
+                   &image.text_addr, &image.text_len,
+                   &image.data_addr, &image.data_len,
+                   &image.bss_addr, &image.bss_len,
+                   image.bs2_entry, image.bs2_addr,
+                   &section_names)?;
+    project::write_config(&out_dir, &dol_file, &image_ref)?;
+
+    println!("Wrote {}/{} and {}/config (config.yml, symbols.txt, splits.txt)", out_dir, dol_file, out_dir);
+    Ok(())
+}
+
+fn objdiff_project(in_file: String, splits_file: String, symbols: Option<String>, out_dir: String, build_dir: Option<String>, profile: Option<String>) -> std::io::Result<()> {
+    let detection = check_format(&in_file, &BOOT_IMAGE_KINDS, "objdiff")?;
+    let file_data = bootstage::map_file(&in_file)?;
+    let mut rom = Vec::new();
+    let image_ref = parse_boot_image_ref(detection.kind, &file_data, &mut rom, &profile)?;
+    let splits = splits::read_file(&splits_file)?;
+    let symbols = symbols.map(|s| symbols::read_file(&s)).transpose()?;
+
+    // Same place the dtk project template builds to
+    let stem = std::path::Path::new(&in_file).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or("bs2".to_string());
+    let build_dir = build_dir.unwrap_or(format!("build/{}", stem));
+    let config = objdiff::write_project(&out_dir, &build_dir, &image_ref, &splits, symbols.as_ref())?;
+
+    println!("Wrote {}/objdiff.json and {} target objects to {}/{}/obj", out_dir, config.units.len(), out_dir, build_dir);
+    Ok(())
+}
+
+fn split_units(in_file: String, splits_file: String, symbols: Option<String>, out_dir: String, profile: Option<String>) -> std::io::Result<()> {
+    let detection = check_format(&in_file, &BOOT_IMAGE_KINDS, "split")?;
+    let file_data = bootstage::map_file(&in_file)?;
+    let mut rom = Vec::new();
+    let image_ref = parse_boot_image_ref(detection.kind, &file_data, &mut rom, &profile)?;
+    let splits = splits::read_file(&splits_file)?;
+    let symbols = symbols.map(|s| symbols::read_file(&s)).transpose()?;
+
+    let result = asmsplit::write_split(&out_dir, &image_ref, &splits, symbols.as_ref())?;
+    println!("Wrote {} units ({} of them auto_ units for what the splits don't cover) to {}/asm and {}/ldscript.ld",
+             result.units + result.auto_units, result.auto_units, out_dir, out_dir);
     Ok(())
 }
 
-fn elf_to_bs(base_file: String, in_file: String, out_file: String, image_size: usize, base_addr: u32) -> std::io::Result<()> {
-    let base_image = bootstage::open_file(&base_file);
+fn elf_to_apploader(base_file: String, in_file: String, out_file: String, image_size: usize, base_addr: u32) -> std::io::Result<()> {
+    let mut loader = apploader::open_file(&base_file)?;
+
+    // The disc loader always copies it to the same place
+    if base_addr != 0xFFFFFFFF && base_addr != apploader::LOAD_ADDR {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                                       format!("Apploaders must be linked at {:#010X}", apploader::LOAD_ADDR)));
+    }
+
+    let load_size = if image_size == 0xFFFFFFFF { loader.load_size() as usize } else { image_size };
+    if load_size < loader.trailer_size as usize {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                                       format!("Image size {:#X} is smaller than the trailer ({:#X} bytes)", load_size, loader.trailer_size)));
+    }
+
+    let raw_elf_data = elf::turn_elf_to_raw(&in_file, load_size, apploader::LOAD_ADDR)?;
+    loader.replace_code(raw_elf_data.data, raw_elf_data.entry_point)?;
+
+    return apploader::create_file(&out_file, &loader);
+}
+
+fn elf_to_bs(base_file: String, in_file: String, out_file: String, image_size: usize, base_addr: u32, profile: Option<String>) -> std::io::Result<()> {
+    check_format(&in_file, &[FormatKind::Elf], "convert (input)")?;
+    if check_format(&base_file, &LOADER_KINDS, "convert (base)")?.kind == FormatKind::Apploader {
+        return elf_to_apploader(base_file, in_file, out_file, image_size, base_addr);
+    }
+    let (base_kind, base_image) = open_boot_image(&base_file, &profile, "convert (base)")?;
 
     let bs2_image_size = if image_size == 0xFFFFFFFF { base_image.bs2_len as usize } else { image_size };
     let bs2_base_addr = if base_addr == 0xFFFFFFFF { base_image.bs2_addr } else { base_addr };
@@ -95,16 +804,490 @@ fn elf_to_bs(base_file: String, in_file: String, out_file: String, image_size: u
 
     let mut output_image = base_image;
 
-    let raw_elf_data = elf::turn_elf_to_raw(&in_file, bs2_image_size, bs2_base_addr);
+    let raw_elf_data = elf::turn_elf_to_raw(&in_file, bs2_image_size, bs2_base_addr)?;
 
     output_image.bs2_data   = raw_elf_data.data;
     output_image.bs2_addr   = bs2_base_addr;
     output_image.bs2_len    = bs2_image_size as u32;
     output_image.bs2_entry  = raw_elf_data.entry_point;
 
+    if base_kind == FormatKind::GameCubeIpl {
+        // BS1 always copies BS2 to the same place and jumps to its start
+        if bs2_base_addr != gcipl::BS2_ADDR {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                                           format!("GameCube BS2 must be linked at {:#010X}", gcipl::BS2_ADDR)));
+        }
+        if raw_elf_data.entry_point != gcipl::BS2_ADDR {
+            eprintln!("Warning: ELF entry point {:#010X} is ignored, BS1 jumps to {:#010X}", raw_elf_data.entry_point, gcipl::BS2_ADDR);
+        }
+        let base_file_data = bootstage::map_file(&base_file)?;
+        return gcipl::create_file(&out_file, &base_file_data, &output_image.bs2_data);
+    }
+
     bootstage::create_file(&out_file, &output_image);
 
     Ok(())
 }
 
+fn list_profiles(out_file: Option<String>, profile: Option<String>) -> std::io::Result<()> {
+    if let Some(out_file) = out_file {
+        let template = profile::find(profile.as_deref().unwrap_or("wii"))?;
+        return meta::write_document(&out_file, &template);
+    }
+
+    for profile in profile::builtin() {
+        println!("{:<6} {} (BS2 at {:#010X}, BSS from {:#010X}, bound {:#010X}, pad {:#X})",
+                 profile.name, profile.description,
+                 profile.init_mem_bound_start, profile.uninit_mem_bound_start, profile.mem_bound_end, profile.bs2_pad);
+    }
+    Ok(())
+}
+
+fn ipl_fonts(in_file: String, out_file: String, insert: Option<String>) -> std::io::Result<()> {
+    check_format(&in_file, &[FormatKind::GameCubeIpl], "extract-fonts")?;
+    let file_data = bootstage::map_file(&in_file)?;
+    if file_data.len() != gcipl::IPL_SIZE {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+                                       format!("{} is {:#X} bytes, expected {:#X}", in_file, file_data.len(), gcipl::IPL_SIZE)));
+    }
+
+    match insert {
+        Some(font_dir) => std::fs::write(&out_file, fonts::insert_rom_fonts(&file_data, &font_dir)?)?,
+        None           => fonts::extract_rom_fonts(&file_data, &out_file)?,
+    }
+    Ok(())
+}
+
+fn nand_info(nand_root: String, profile: Option<String>) -> std::io::Result<()> {
+    let nand = nand::NandRoot::new(&nand_root)?;
+    let (tmd, content_path, content_data) = nand.read_boot_content(title::SYSTEM_MENU_TITLE_ID)?;
+
+    info::print_tmd(&tmd);
+    println!("Boot content: {}", content_path.display());
+    println!();
+    info::print_bootstage(&bootstage::open_data(&content_data, &profile)?);
+    Ok(())
+}
+
+fn install_bootstage(in_file: String, nand_root: String) -> std::io::Result<()> {
+    check_format(&in_file, &BOOTSTAGE_KINDS, "install")?;
+    let nand = nand::NandRoot::new(&nand_root)?;
+
+    let content_data = std::fs::read(&in_file)?;
+    let content_path = nand.install_boot_content(title::SYSTEM_MENU_TITLE_ID, &content_data)?;
+    println!("Installed {} as {} ({:#X} bytes, SHA-1 {})", in_file, content_path.display(), content_data.len(), meta::sha1_hex(&content_data));
+    Ok(())
+}
+
+fn nand_dump(in_file: String, keys: Option<String>, out_file: Option<String>, list_titles: bool, profile: Option<String>) -> std::io::Result<()> {
+    let file_data = bootstage::map_file(&in_file)?;
+    let keys = match keys {
+        Some(keys) => Some(std::fs::read(keys)?),
+        None       => None,
+    };
+    let dump = sffs::NandDump::parse(&file_data, keys.as_deref())?;
+
+    if list_titles {
+        for (title_id, tmd) in dump.titles()? {
+            match tmd {
+                Some(tmd) => println!("{} v{} ({} contents)", title::title_string(title_id), tmd.title_version, tmd.contents.len()),
+                None      => println!("{} (no TMD)", title::title_string(title_id)),
+            }
+        }
+        return Ok(());
+    }
+
+    let (tmd, content_data) = dump.boot_content(title::SYSTEM_MENU_TITLE_ID)?;
+    if let Some(out_file) = out_file {
+        return std::fs::write(out_file, content_data);
+    }
+
+    info::print_tmd(&tmd);
+    println!();
+    info::print_bootstage(&bootstage::open_data(&content_data, &profile)?);
+    Ok(())
+}
+
+fn identify_file(in_file: String, db: Option<String>, record: Option<String>, title_version: Option<u16>, profile: Option<String>) -> std::io::Result<()> {
+    check_format(&in_file, &BOOTSTAGE_KINDS, "identify")?;
+    let file_data = bootstage::map_file(&in_file)?;
+    let image = bootstage::BSImageRef::parse_with_profile(&file_data, profile::select(&profile, &file_data)?)?;
+    let hashes = identify::hashes(&file_data, &image);
 
+    if let Some(version_region) = record {
+        let db_file = db.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "--record needs a database (-d)"))?;
+        let mut known_db = identify::read_db(&db_file)?;
+        let (version, region) = identify::split_version(&version_region);
+        known_db.images.push(identify::KnownImage {
+            version,
+            region,
+            title_version:  title_version.or(identify::title_version_of(&version_region)),
+            sha1:           Some(hashes.sha1),
+            bs1_sha1:       Some(hashes.bs1_sha1),
+            bs2_sha1:       Some(hashes.bs2_sha1),
+            sections:       Some(identify::sections(&image)),
+        });
+        meta::write_document(&db_file, &known_db)?;
+        println!("Recorded {} as {} in {}", in_file, version_region, db_file);
+        return Ok(());
+    }
+
+    let mut known = identify::builtin()?;
+    if let Some(db_file) = &db {
+        known.extend(identify::read_db(db_file)?.images);
+    }
+
+    println!("SHA-1:       {}", hashes.sha1);
+    println!("BS1 SHA-1:   {}", hashes.bs1_sha1);
+    println!("BS2 SHA-1:   {}", hashes.bs2_sha1);
+
+    let describe = |known: &identify::KnownImage| -> String {
+        return match known.title_version {
+            Some(title_version) => format!("{}{} (title version {})", known.version, known.region, title_version),
+            None                => format!("{}{}", known.version, known.region),
+        };
+    };
+
+    let matches = identify::find_matches(&known, &hashes);
+    for (known, match_kind) in &matches {
+        let what = match match_kind {
+            identify::MatchKind::Image     => "whole image",
+            identify::MatchKind::BS1AndBS2 => "BS1 and BS2",
+            identify::MatchKind::BS2       => "BS2 only",
+            identify::MatchKind::BS1       => "BS1 only",
+        };
+        println!("Match:       {}, {}", describe(known), what);
+    }
+    if !matches.is_empty() {
+        return Ok(());
+    }
+
+    match identify::closest(&known, &identify::sections(&image)) {
+        Some((known, similarity)) => println!("Match:       none, closest known is {} ({:.0}% alike section layout)", describe(known), similarity * 100.0),
+        None if db.is_none()      => println!("Match:       none, not in the built-in table (record known dumps with -d <db> --record <version>)"),
+        None                      => println!("Match:       none, no known image to compare with"),
+    }
+    Ok(())
+}
+
+fn verify_file(built: String, original: Option<String>, sha1: Option<String>, map: Option<String>, profile: Option<String>) -> std::io::Result<()> {
+    let built_data = bootstage::map_file(&built)?;
+    let built_sha1 = meta::sha1_hex(&built_data);
+    let mismatch = || std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{} does not match", built));
+
+    println!("SHA-1:       {}", built_sha1);
+    if let Some(expected) = sha1 {
+        let expected = verify::read_expected_sha1(&expected)?;
+        println!("Expected:    {}", expected);
+        if built_sha1 == expected {
+            println!("Match:       SHA-1 is the expected one");
+            return Ok(());
+        }
+        if original.is_none() {
+            return Err(mismatch());
+        }
+    }
+
+    let original = original.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "verify needs an original file or --sha1"))?;
+    check_format(&original, &BOOTSTAGE_KINDS, "verify")?;
+    let original_data = bootstage::map_file(&original)?;
+    println!("Original:    {}", meta::sha1_hex(&original_data));
+    if built_data[..] == original_data[..] {
+        println!("Match:       {} is identical to {}", built, original);
+        return Ok(());
+    }
+
+    let image = bootstage::BSImageRef::parse_with_profile(&original_data, profile::select(&profile, &original_data)?)?;
+    let regions = verify::regions(&original_data, &image);
+    let symbols = match map {
+        Some(map_file) => Some(symbols::read_file(&map_file)?),
+        None           => None,
+    };
+
+    let ranges = verify::diff_ranges(&built_data, &original_data, &regions);
+    let describe = |range: &verify::DiffRange| -> String {
+        let region = range.region.map(|r| regions[r].name.as_str()).unwrap_or("past the end");
+        let Some(addr) = range.addr(&regions) else {
+            return format!("{:<10}   {}", "-", region);
+        };
+        let symbol = symbols.as_ref().and_then(|s| s.describe(addr)).unwrap_or_default();
+        return format!("{:#010X}   {:<16} {}", addr, region, symbol).trim_end().to_string();
+    };
+
+    println!("Mismatch:    {} differing ranges, {:#X} bytes", ranges.len(), ranges.iter().map(|r| r.len).sum::<usize>());
+    if built_data.len() != original_data.len() {
+        println!("Size:        {:#X} bytes, original {:#X} bytes", built_data.len(), original_data.len());
+    }
+    println!("First:       file {:#010X}   {}", ranges[0].file_off, describe(&ranges[0]));
+    println!("Ranges:");
+    for range in &ranges {
+        println!("  file {:#010X} {:>8}   {}", range.file_off, format!("{:#X}", range.len), describe(range));
+    }
+    Err(mismatch())
+}
+
+// Smallest section containing addr (name, start, length), since the BSS sections can overlap
+fn image_section_of(image: &bootstage::BSImage, addr: u32) -> Option<(String, u32, u32)> {
+    let mut sections = vec![("BS1".to_string(), image.bs1_addr, image.bs1_len)];
+    for i in 0..image.text_addr.len() {
+        sections.push((image.profile.text_names[i].clone(), image.text_addr[i], image.text_len[i]));
+    }
+    for i in 0..image.data_addr.len() {
+        sections.push((image.profile.data_names[i].clone(), image.data_addr[i], image.data_len[i]));
+    }
+    for i in 0..image.bss_addr.len() {
+        sections.push((image.profile.bss_names[i].clone(), image.bss_addr[i], image.bss_len[i]));
+    }
+    return sections.into_iter()
+                   .filter(|(_, start, len)| addr >= *start && addr - start < *len)
+                   .min_by_key(|(_, _, len)| *len);
+}
+
+fn parse_hex_addr(addr: &str) -> std::io::Result<u32> {
+    return u32::from_str_radix(addr.trim_start_matches("0x").trim_start_matches("0X"), 16)
+               .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} is not a hex address", addr)));
+}
+
+fn lookup_addr(addr: String, symbols: Option<String>, splits: Option<String>, in_file: Option<String>, profile: Option<String>) -> std::io::Result<()> {
+    let addr = parse_hex_addr(&addr)?;
+    if symbols.is_none() && splits.is_none() && in_file.is_none() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "lookup needs -s, --splits or -i"));
+    }
+    let labels = info::Labels::read(&symbols, &splits)?;
+
+    println!("Address:     {:#010X}", addr);
+    if let Some(in_file) = in_file {
+        let (_, image) = open_boot_image(&in_file, &profile, "lookup")?;
+        let section = match image_section_of(&image, addr) {
+            Some((name, start, len)) => format!("{} ({:#010X}-{:#010X})", name, start, start.wrapping_add(len)),
+            None                     => "none".to_string(),
+        };
+        println!("Section:     {}", section);
+    }
+    if let Some(symbols) = &labels.symbols {
+        let symbol = match symbols.find(addr) {
+            Some(symbol) => {
+                let kind = match symbol.kind {
+                    symbols::SymbolKind::Function => "function",
+                    symbols::SymbolKind::Object   => "object",
+                    symbols::SymbolKind::Unknown  => "label",
+                };
+                let size = if symbol.size != 0 { format!(", {:#X} bytes", symbol.size) } else { String::new() };
+                format!("{} ({}{})", symbols.describe(addr).unwrap_or_default(), kind, size)
+            },
+            None => "none".to_string(),
+        };
+        println!("Symbol:      {}", symbol);
+    }
+    if let Some(splits) = &labels.splits {
+        let unit = match splits.unit_of(addr) {
+            Some((unit, range)) => format!("{} ({} {:#010X}-{:#010X})", unit.name, range.section, range.start, range.end),
+            None                => "none".to_string(),
+        };
+        println!("Unit:        {}", unit);
+    }
+    Ok(())
+}
+
+fn disassemble(in_file: String, start: Option<String>, end: Option<String>, symbols: Option<String>, asm: bool, out_file: Option<String>, profile: Option<String>) -> std::io::Result<()> {
+    let detection = check_format(&in_file, &BOOT_IMAGE_KINDS, "disasm")?;
+    let file_data = bootstage::map_file(&in_file)?;
+    let mut rom = Vec::new();
+    let image_ref = parse_boot_image_ref(detection.kind, &file_data, &mut rom, &profile)?;
+    let symbols = symbols.map(|s| symbols::read_file(&s)).transpose()?;
+    let start = start.map(|a| parse_hex_addr(&a)).transpose()?;
+    let end = end.map(|a| parse_hex_addr(&a)).transpose()?;
+
+    let blocks = disasm::code_blocks(&image_ref, start, end);
+    if blocks.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "No code in that range"));
+    }
+    let lines: Vec<Vec<disasm::Line>> = blocks.iter().map(disasm::disassemble).collect();
+    let labels = disasm::labels(&blocks, &lines, symbols.as_ref());
+    let text = if asm { disasm::assembly(&blocks, &lines, &labels, symbols.as_ref()) } else { disasm::listing(&blocks, &lines, &labels, symbols.as_ref()) };
+
+    match out_file {
+        Some(out_file) => std::fs::write(out_file, text)?,
+        None           => print!("{}", text),
+    }
+    Ok(())
+}
+
+fn map_to_symbols(in_file: String, out_file: String, base_file: Option<String>, profile: Option<String>) -> std::io::Result<()> {
+    let map = mwmap::read_file(&in_file)?;
+    let mut symbols = map.symbols();
+    symbols.extend(map.linker_labels());
+    symbols.sort_by_key(|s| s.addr);
+
+    if let Some(base_file) = base_file {
+        let (_, image) = open_boot_image(&base_file, &profile, "map2syms")?;
+        let mut problems = Vec::new();
+        for symbol in &symbols {
+            let map_section = symbol.section.as_deref().unwrap_or("");
+            match image_section_of(&image, symbol.addr) {
+                None => problems.push(format!("{} at {:#010X} is outside every section", symbol.name, symbol.addr)),
+                Some((name, _, _)) if name != map_section => {
+                    problems.push(format!("{} at {:#010X} is in {}, the map says {}", symbol.name, symbol.addr, name, map_section));
+                },
+                // image_section_of puts the address inside the section, so this can't underflow
+                Some((name, start, len)) if symbol.size > len - (symbol.addr - start) => {
+                    problems.push(format!("{} at {:#010X} ({:#X} bytes) runs past the end of {}", symbol.name, symbol.addr, symbol.size, name));
+                },
+                _ => {},
+            }
+        }
+        if problems.is_empty() {
+            println!("All {} symbols fit the sections of {}", symbols.len(), base_file);
+        }
+        else {
+            println!("{} of {} symbols don't fit the sections of {}:", problems.len(), symbols.len(), base_file);
+            for problem in &problems {
+                println!("  {}", problem);
+            }
+        }
+    }
+
+    let text: String = symbols.iter().map(|s| symbols::dtk_line(s) + "\n").collect();
+    std::fs::write(&out_file, text)?;
+    println!("Wrote {} symbols to {}", symbols.len(), out_file);
+    Ok(())
+}
+
+// Returns false (and writes nothing) if the ELF is stripped
+fn export_map(in_file: &String, out_file: &String) -> std::io::Result<bool> {
+    check_format(in_file, &[FormatKind::Elf], "export-map")?;
+    let (sections, symbols) = symbols::read_elf(&std::fs::read(in_file)?)?;
+    if symbols.is_empty() {
+        return Ok(false);
+    }
+    let (map, count) = symbols::dolphin_map(&sections, &symbols);
+    std::fs::write(out_file, map)?;
+    println!("Wrote {} symbols to {}", count, out_file);
+    Ok(true)
+}
+
+fn progress_report(target: String, built: String, symbols: String, splits: Option<String>, out_file: Option<String>, profile: Option<String>) -> std::io::Result<()> {
+    let detection = check_format(&target, &BOOT_IMAGE_KINDS, "progress")?;
+    check_format(&built, &[FormatKind::Elf], "progress")?;
+    let file_data = bootstage::map_file(&target)?;
+    let mut rom = Vec::new();
+    let image_ref = parse_boot_image_ref(detection.kind, &file_data, &mut rom, &profile)?;
+    let symbols = symbols::read_file(&symbols)?;
+    let splits = splits.map(|s| splits::read_file(&s)).transpose()?;
+
+    let matches = progress::match_symbols(&image_ref, &symbols, &std::fs::read(&built)?)?;
+    let report = progress::report(&image_ref, &matches, splits.as_ref());
+    progress::print_report(&report);
+
+    if let Some(out_file) = out_file {
+        meta::write_document(&out_file, &report)?;
+        println!();
+        println!("Wrote {}", out_file);
+    }
+    Ok(())
+}
+
+fn compare_functions(target: String, built: String, symbols: String, all: bool, profile: Option<String>) -> std::io::Result<()> {
+    let detection = check_format(&target, &BOOT_IMAGE_KINDS, "compare")?;
+    check_format(&built, &[FormatKind::Elf], "compare")?;
+    let file_data = bootstage::map_file(&target)?;
+    let mut rom = Vec::new();
+    let image_ref = parse_boot_image_ref(detection.kind, &file_data, &mut rom, &profile)?;
+    let symbols = symbols::read_file(&symbols)?;
+
+    let matches = compare::compare_functions(&image_ref, &symbols, &std::fs::read(&built)?)?;
+    compare::print_matches(&matches, all);
+
+    let bad = matches.iter().filter(|m| m.class == compare::MatchClass::Mismatched || m.class == compare::MatchClass::Missing).count();
+    if bad != 0 {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{} of {} functions don't match", bad, matches.len())));
+    }
+    Ok(())
+}
+
+fn re_import(in_file: String, out_base: String, symbols: Option<String>, profile: Option<String>) -> std::io::Result<()> {
+    let detection = check_format(&in_file, &BOOT_IMAGE_KINDS, "re-import")?;
+    let file_data = bootstage::map_file(&in_file)?;
+    let mut rom = Vec::new();
+    let image_ref = parse_boot_image_ref(detection.kind, &file_data, &mut rom, &profile)?;
+    let symbols = symbols.map(|s| symbols::read_file(&s)).transpose()?;
+
+    let program = std::path::Path::new(&in_file).file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or(in_file.clone());
+    retools::write_files(&out_base, &program, &image_ref, symbols.as_ref())?;
+
+    let blocks = retools::memory_blocks(&image_ref);
+    println!("Wrote {}.xml (Ghidra) and {}.py (IDA) with {} memory blocks and {} symbols, both load {}.bytes",
+             out_base, out_base, blocks.len(), symbols.map(|s| s.symbols.len()).unwrap_or(0), out_base);
+    Ok(())
+}
+
+fn detect_file(in_file: String) -> std::io::Result<()> {
+    let file_data = bootstage::map_file(&in_file)?;
+
+    for detection in detect::detect_all(&file_data) {
+        println!("{:>3}% {}", detection.confidence, detection.kind);
+        for reason in &detection.reasons {
+            println!("       - {}", reason);
+        }
+    }
+    Ok(())
+}
+
+fn info_file(in_file: String, profile: Option<String>, common_key: Option<String>, labels: &info::Labels) -> std::io::Result<()> {
+    let file_data = bootstage::map_file(&in_file)?;
+    let detection = detect::detect(&file_data);
+
+    println!("Format:      {} ({}%)", detection.kind, detection.confidence);
+    match detection.kind {
+        FormatKind::WiiBootStage | FormatKind::VWiiBootStage => {
+            let profile = profile::select(&profile, &file_data)?;
+            let image = bootstage::BSImageRef::parse_with_profile(&file_data, profile)?.to_image();
+            info::print_bootstage_labeled(&image, labels);
+        },
+        FormatKind::GameCubeIpl => {
+            let rom = gcipl::descramble_rom(&file_data)?;
+            println!("Copyright:   {}", gcipl::copyright(&rom));
+            let profile = match profile {
+                Some(name) => profile::find(&name)?,
+                None       => profile::gc(),
+            };
+            let image = gcipl::parse(&rom, profile)?.to_image();
+            info::print_bootstage_labeled(&image, labels);
+        },
+        FormatKind::Wad => {
+            let wad = wad::parse(&file_data)?;
+            info::print_wad(&wad);
+
+            let common_key = title::find_common_key(&common_key)?;
+            wad.verify_contents(&wad.title_key(&common_key)?)?;
+            println!("SHA-1:       all {} contents match the TMD", wad.contents.len());
+
+            let boot_data = wad.boot_content(&common_key)?;
+            let boot_kind = detect::detect(&boot_data).kind;
+            if !BOOTSTAGE_KINDS.contains(&boot_kind) {
+                println!("Boot content is a {}, not a BootStage", boot_kind);
+                return Ok(());
+            }
+            println!();
+            info::print_bootstage_labeled(&bootstage::open_data(&boot_data, &profile)?, labels);
+        },
+        FormatKind::Apploader => {
+            info::print_apploader(&apploader::parse(&file_data)?);
+        },
+        FormatKind::Dol => {
+            if let Some(header) = dol::read_header(&file_data) {
+                info::print_dol(&header, labels);
+            }
+        },
+        FormatKind::Elf => {
+            let header = elf::read_elf32_hdr(&file_data)?;
+            let prg_hdrs = elf::read_elf32_prg_hdrs(&file_data, &header)?;
+            info::print_elf(&header, &prg_hdrs);
+        },
+        FormatKind::Raw => {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+                                           format!("{} is not a recognised format", in_file)));
+        },
+    }
+    Ok(())
+}
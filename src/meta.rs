@@ -0,0 +1,331 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+use crate::bootstage::{self, BSImage, BSImageRef};
+use crate::profile::{self, TargetProfile};
+
+// Text description of a BSImage.
+// The BS1/BS2 payloads are kept out of the document and referenced by path and/or SHA-1.
+#[derive(Serialize, Deserialize)]
+pub struct BSImageMeta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<ProfileMeta>,
+
+    pub header: BSHeaderMeta,
+    pub entry_points: BSEntryMeta,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pad_block: Option<String>,
+
+    pub sections: BSSectionsMeta,
+    pub payloads: BSPayloadsMeta,
+}
+
+// A built-in profile is referenced by name, any other one is written out in full
+// so the document does not depend on the profile file it was described with.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ProfileMeta {
+    Name(String),
+    Embedded(TargetProfile),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BSHeaderMeta {
+    #[serde(with = "hex_u32")]
+    pub bs1_addr: u32,
+    #[serde(with = "hex_u32")]
+    pub bs1_len: u32,
+
+    #[serde(with = "hex_u32")]
+    pub bs2_addr: u32,
+    #[serde(with = "hex_u32")]
+    pub bs2_len: u32,
+
+    #[serde(with = "hex_u32")]
+    pub stub_addr: u32,
+    #[serde(with = "hex_u32")]
+    pub stub_len: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BSEntryMeta {
+    #[serde(with = "hex_u32")]
+    pub bs1: u32,
+    #[serde(with = "hex_u32")]
+    pub bs2: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BSSectionMeta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(with = "hex_u32")]
+    pub addr: u32,
+    #[serde(with = "hex_u32")]
+    pub len: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BSSectionsMeta {
+    pub text: Vec<BSSectionMeta>,
+    pub data: Vec<BSSectionMeta>,
+    pub bss: Vec<BSSectionMeta>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BSPayloadsMeta {
+    pub bs1: BlobRef,
+    pub bs2: BlobRef,
+}
+
+// A binary payload stored next to the document (path) or in a blob directory (by hash).
+#[derive(Serialize, Deserialize)]
+pub struct BlobRef {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub sha1: String,
+    pub size: usize,
+}
+
+enum DocFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+pub mod hex_u32 {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum HexOrInt {
+        Int(u32),
+        Str(String),
+    }
+
+    pub fn serialize<S: Serializer>(value: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+        return serializer.serialize_str(&format!("{:#010X}", value));
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+        return match HexOrInt::deserialize(deserializer)? {
+            HexOrInt::Int(v) => Ok(v),
+            HexOrInt::Str(s) => {
+                let trimmed = s.trim();
+                let parsed = match trimmed.strip_prefix("0x").or_else(|| trimmed.strip_prefix("0X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16),
+                    None      => trimmed.parse::<u32>(),
+                };
+                parsed.map_err(|_| serde::de::Error::custom(format!("invalid number \"{}\"", s)))
+            }
+        };
+    }
+}
+
+pub fn sha1_hex(data: &[u8]) -> String {
+    return Sha1::digest(data).iter().map(|b| format!("{:02x}", b)).collect();
+}
+
+fn bytes_to_hex(data: &[u8]) -> String {
+    return data.iter().map(|b| format!("{:02x}", b)).collect();
+}
+
+pub fn hex_to_bytes(text: &str) -> Option<Vec<u8>> {
+    let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    return (0..text.len()).step_by(2)
+                          .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+                          .collect();
+}
+
+fn doc_format(doc_path: &Path) -> std::io::Result<DocFormat> {
+    let ext = doc_path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    return match ext.as_str() {
+        "json"          => Ok(DocFormat::Json),
+        "yaml" | "yml"  => Ok(DocFormat::Yaml),
+        "toml"          => Ok(DocFormat::Toml),
+        _ => Err(Error::new(ErrorKind::InvalidInput,
+                            format!("Unknown document format \"{}\" (expected .json, .yaml/.yml or .toml)", doc_path.display()))),
+    };
+}
+
+fn to_sections(addr: &[u32], len: &[u32], names: &[String]) -> Vec<BSSectionMeta> {
+    return addr.iter().zip(len).enumerate()
+               .map(|(i, (&addr, &len))| BSSectionMeta { name: names.get(i).cloned(), addr, len })
+               .collect();
+}
+
+// Builds the document for an image; payload blobs are not written here.
+// BS2 is recorded as it is in the file, before open_file reorders the BSS table, so a rebuild gives back the same bytes.
+pub fn image_to_meta(parsed: &BSImageRef) -> BSImageMeta {
+    let image = &parsed.to_image();
+    return BSImageMeta {
+        profile: Some(if profile::builtin().contains(&image.profile) { ProfileMeta::Name(image.profile.name.clone()) }
+                      else { ProfileMeta::Embedded(image.profile.clone()) }),
+        header: BSHeaderMeta {
+            bs1_addr:   image.bs1_addr,
+            bs1_len:    image.bs1_len,
+
+            bs2_addr:   image.bs2_addr,
+            bs2_len:    image.bs2_len,
+
+            stub_addr:  image.stub_addr,
+            stub_len:   image.stub_len,
+        },
+        entry_points: BSEntryMeta {
+            bs1: image.bs1_entry,
+            bs2: image.bs2_entry,
+        },
+        pad_block: if bootstage::verify_unk_data(image) { Some(bytes_to_hex(&image.unk_stuff)) } else { None },
+        sections: BSSectionsMeta {
+            text:   to_sections(&image.text_addr, &image.text_len, &image.profile.text_names),
+            data:   to_sections(&image.data_addr, &image.data_len, &image.profile.data_names),
+            bss:    to_sections(&image.bss_addr, &image.bss_len, &image.profile.bss_names),
+        },
+        payloads: BSPayloadsMeta {
+            bs1: BlobRef { path: None, sha1: sha1_hex(&image.bs1_data), size: image.bs1_data.len() },
+            bs2: BlobRef { path: None, sha1: sha1_hex(parsed.bs2_data), size: parsed.bs2_data.len() },
+        },
+    };
+}
+
+// Writes the document and its payload blobs.
+// Without a blob directory the payloads go next to the document and are referenced by path,
+// otherwise they are stored as <sha1>.bin inside it and referenced by hash only.
+pub fn write_meta(doc_file: &str, image: &BSImageRef, blob_dir: Option<&str>) -> std::io::Result<()> {
+    let doc_path = Path::new(doc_file);
+    // Fail before writing any blob
+    doc_format(doc_path)?;
+    let mut meta = image_to_meta(image);
+
+    match blob_dir {
+        Some(dir) => {
+            fs::create_dir_all(dir)?;
+            fs::write(Path::new(dir).join(format!("{}.bin", meta.payloads.bs1.sha1)), image.bs1_data)?;
+            fs::write(Path::new(dir).join(format!("{}.bin", meta.payloads.bs2.sha1)), image.bs2_data)?;
+        }
+        None => {
+            let stem = doc_path.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+            let parent = doc_path.parent().unwrap_or(Path::new(""));
+            let bs1_name = format!("{}.bs1.bin", stem);
+            let bs2_name = format!("{}.bs2.bin", stem);
+            fs::write(parent.join(&bs1_name), image.bs1_data)?;
+            fs::write(parent.join(&bs2_name), image.bs2_data)?;
+            meta.payloads.bs1.path = Some(bs1_name);
+            meta.payloads.bs2.path = Some(bs2_name);
+        }
+    }
+
+    write_document(doc_file, &meta)?;
+
+    return Ok(());
+}
+
+// Writes any serialisable value as JSON, YAML or TOML depending on the file extension.
+pub fn write_document<T: Serialize>(doc_file: &str, value: &T) -> std::io::Result<()> {
+    let doc_path = Path::new(doc_file);
+
+    let text = match doc_format(doc_path)? {
+        DocFormat::Json => serde_json::to_string_pretty(value).map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))? + "\n",
+        DocFormat::Yaml => serde_yaml::to_string(value).map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?,
+        DocFormat::Toml => toml::to_string_pretty(value).map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?,
+    };
+
+    return fs::write(doc_path, text);
+}
+
+// Reads any deserialisable value from a JSON, YAML or TOML file depending on the extension.
+pub fn read_document<T: DeserializeOwned>(doc_file: &str) -> std::io::Result<T> {
+    let doc_path = Path::new(doc_file);
+    let text = fs::read_to_string(doc_path)?;
+
+    return match doc_format(doc_path)? {
+        DocFormat::Json => serde_json::from_str(&text).map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {}", doc_file, e))),
+        DocFormat::Yaml => serde_yaml::from_str(&text).map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {}", doc_file, e))),
+        DocFormat::Toml => toml::from_str(&text).map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {}", doc_file, e))),
+    };
+}
+
+fn load_blob(blob: &BlobRef, doc_dir: &Path, blob_dir: Option<&str>) -> std::io::Result<Vec<u8>> {
+    let blob_path: PathBuf = match (&blob.path, blob_dir) {
+        (Some(path), _)     => doc_dir.join(path),
+        (None, Some(dir))   => Path::new(dir).join(format!("{}.bin", blob.sha1.to_ascii_lowercase())),
+        (None, None)        => doc_dir.join(format!("{}.bin", blob.sha1.to_ascii_lowercase())),
+    };
+
+    let data = fs::read(&blob_path).map_err(|e| Error::new(e.kind(), format!("{}: {}", blob_path.display(), e)))?;
+
+    if data.len() != blob.size {
+        return Err(Error::new(ErrorKind::InvalidData,
+                              format!("{}: size is {:#X}, document says {:#X}", blob_path.display(), data.len(), blob.size)));
+    }
+    if !sha1_hex(&data).eq_ignore_ascii_case(&blob.sha1) {
+        return Err(Error::new(ErrorKind::InvalidData,
+                              format!("{}: SHA-1 does not match the document", blob_path.display())));
+    }
+
+    return Ok(data);
+}
+
+// Inverse of write_meta: rebuilds the image from a document plus its payload blobs.
+// An explicit profile overrides the one named in the document.
+pub fn meta_to_image(doc_file: &str, blob_dir: Option<&str>, profile: &Option<String>) -> std::io::Result<BSImage> {
+    let meta: BSImageMeta = read_document(doc_file)?;
+    let doc_dir = Path::new(doc_file).parent().unwrap_or(Path::new(""));
+
+    let profile = match (profile, meta.profile) {
+        (Some(name), _)                             => profile::find(name)?,
+        (None, Some(ProfileMeta::Name(name)))       => profile::find(&name)?,
+        (None, Some(ProfileMeta::Embedded(custom))) => { profile::verify_profile(&custom)?; custom },
+        (None, None)                                => profile::retail(),
+    };
+    let mut image = bootstage::default_for(profile);
+
+    image.bs1_addr  = meta.header.bs1_addr;
+    image.bs1_len   = meta.header.bs1_len;
+    image.bs1_data  = load_blob(&meta.payloads.bs1, doc_dir, blob_dir)?;
+
+    image.bs2_addr  = meta.header.bs2_addr;
+    image.bs2_len   = meta.header.bs2_len;
+    image.bs2_data  = load_blob(&meta.payloads.bs2, doc_dir, blob_dir)?;
+
+    image.stub_addr = meta.header.stub_addr;
+    image.stub_len  = meta.header.stub_len;
+
+    if let Some(pad) = &meta.pad_block {
+        image.unk_stuff = hex_to_bytes(pad).ok_or_else(|| Error::new(ErrorKind::InvalidData, "pad_block is not a valid hex string"))?;
+    }
+
+    image.bs1_entry = meta.entry_points.bs1;
+    image.bs2_entry = meta.entry_points.bs2;
+
+    image.text_addr = meta.sections.text.iter().map(|s| s.addr).collect();
+    image.text_len  = meta.sections.text.iter().map(|s| s.len).collect();
+    image.data_addr = meta.sections.data.iter().map(|s| s.addr).collect();
+    image.data_len  = meta.sections.data.iter().map(|s| s.len).collect();
+    image.bss_addr  = meta.sections.bss.iter().map(|s| s.addr).collect();
+    image.bss_len   = meta.sections.bss.iter().map(|s| s.len).collect();
+
+    // create_file writes BS2 as-is, so the sections can only be what its ROM copy and BSS tables say
+    let tables = BSImageRef::from_bs2(image.bs2_addr, &image.bs2_data, image.profile.clone())?.to_image();
+    if tables.text_addr != image.text_addr || tables.text_len != image.text_len ||
+       tables.data_addr != image.data_addr || tables.data_len != image.data_len ||
+       tables.bss_addr != image.bss_addr || tables.bss_len != image.bss_len {
+        return Err(Error::new(ErrorKind::InvalidData,
+                              format!("{}: sections do not match the section tables in the BS2 payload", doc_file)));
+    }
+
+    if image.bs1_data.len() != image.bs1_len as usize {
+        return Err(Error::new(ErrorKind::InvalidData, "BS1 payload size does not match header.bs1_len"));
+    }
+
+    return Ok(image);
+}
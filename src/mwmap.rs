@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+use crate::symbols::{self, Symbol, SymbolKind};
+
+// One line of a section layout
+pub struct MapEntry {
+    pub section: String,
+    pub name: String,
+    pub addr: u32,
+    pub size: u32,
+    pub align: Option<u32>,
+    // "main.o" or "os.a __start.c"
+    pub object: String,
+}
+
+pub struct MapSection {
+    pub name: String,
+    pub addr: u32,
+    pub size: u32,
+}
+
+// CodeWarrior linker (mwld) .MAP file.
+pub struct MapFile {
+    pub entries: Vec<MapEntry>,
+    // From the memory map, if the map has one
+    pub sections: Vec<MapSection>,
+    // "Linker generated symbols" (_f_text, _stack_addr, ...)
+    pub linker_symbols: Vec<(String, u32)>,
+    // Kind and scope from the link tree ("3] OSInit (func,global) found in ...")
+    pub link_tree: HashMap<String, (SymbolKind, bool)>,
+}
+
+enum Block {
+    None,
+    Layout(String),
+    MemoryMap,
+    LinkerSymbols,
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    return u32::from_str_radix(text.trim_start_matches("0x"), 16).ok();
+}
+
+fn is_hex_word(text: &str) -> bool {
+    return text.len() == 8 && parse_hex(text).is_some();
+}
+
+// "  3] OSInit (func,global) found in os.a OS.c"
+fn parse_link_tree(line: &str) -> Option<(String, SymbolKind, bool)> {
+    let (depth, rest) = line.trim_start().split_once("] ")?;
+    depth.parse::<u32>().ok()?;
+    let (name, rest) = rest.split_once(" (")?;
+    let (kind, scope) = rest.split_once(')')?.0.split_once(',')?;
+    let kind = match kind {
+        "func"   => SymbolKind::Function,
+        "object" => SymbolKind::Object,
+        _        => SymbolKind::Unknown,
+    };
+    return Some((name.trim().to_string(), kind, scope == "local"));
+}
+
+// Byte offset right after word number index
+fn word_end(line: &str, index: usize) -> usize {
+    let mut words = 0;
+    let mut in_word = false;
+    for (i, c) in line.char_indices() {
+        if c.is_whitespace() {
+            if in_word && words == index + 1 {
+                return i;
+            }
+            in_word = false;
+        }
+        else if !in_word {
+            in_word = true;
+            words += 1;
+        }
+    }
+    return line.len();
+}
+
+// Section layout lines come in three layouts depending on the linker version:
+//   00000000 000094 80003100 __start 	__start.o                 (no alignment)
+//   00000000 000094 80003100  4 __start 	os.a __start.c         (alignment)
+//   00000000 000094 80003100 00000100  4 __start 	__start.o      (file offset and alignment, mwld 3.0+)
+// UNUSED lines (stripped by the linker) have no address and are skipped.
+fn parse_layout_line(section: &str, line: &str) -> Option<MapEntry> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    if words.len() < 4 || !is_hex_word(words[0]) {
+        return None;
+    }
+    let size = parse_hex(words[1])?;
+    let addr = parse_hex(words[2])?;
+
+    let (align, name_index) = if words.len() >= 6 && is_hex_word(words[3]) && words[4].parse::<u32>().is_ok() {
+        (words[4].parse().ok(), 5)
+    }
+    else if words[3].parse::<u32>().is_ok() && words.len() >= 5 {
+        (words[3].parse().ok(), 4)
+    }
+    else {
+        (None, 3)
+    };
+
+    // The name is followed by the object, usually after a tab
+    let name = words[name_index];
+    let after_name = &line[word_end(line, name_index)..];
+    let object = if let Some((_, object)) = after_name.split_once('\t') { object.trim() } else { after_name.trim() };
+    // "__start (entry of .init)"
+    let object = match object.strip_prefix("(entry of ") {
+        Some(rest) => rest.split_once(')').map(|(_, o)| o.trim()).unwrap_or(""),
+        None       => object,
+    };
+
+    return Some(MapEntry {
+        section:    section.to_string(),
+        name:       name.to_string(),
+        addr,
+        size,
+        align,
+        object:     object.to_string(),
+    });
+}
+
+pub fn parse(text: &str) -> MapFile {
+    let mut map = MapFile { entries: Vec::new(), sections: Vec::new(), linker_symbols: Vec::new(), link_tree: HashMap::new() };
+    let mut block = Block::None;
+
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if let Some(section) = symbols::layout_section(line) {
+            block = Block::Layout(section.to_string());
+            continue;
+        }
+        if trimmed.starts_with("Memory map:") {
+            block = Block::MemoryMap;
+            continue;
+        }
+        if trimmed.starts_with("Linker generated symbols:") {
+            block = Block::LinkerSymbols;
+            continue;
+        }
+        if trimmed.starts_with("Link map of") {
+            block = Block::None;
+            continue;
+        }
+
+        match &block {
+            Block::Layout(section) => {
+                if let Some(entry) = parse_layout_line(section, line) {
+                    map.entries.push(entry);
+                }
+            },
+            Block::MemoryMap => {
+                // "       .init  80003100 00002434 00000100"
+                let words: Vec<&str> = trimmed.split_whitespace().collect();
+                if words.len() >= 3 && is_hex_word(words[1]) {
+                    if let (Some(addr), Some(size)) = (parse_hex(words[1]), parse_hex(words[2])) {
+                        map.sections.push(MapSection { name: words[0].to_string(), addr, size });
+                    }
+                }
+            },
+            Block::LinkerSymbols => {
+                let words: Vec<&str> = trimmed.split_whitespace().collect();
+                if words.len() == 2 {
+                    if let Some(addr) = parse_hex(words[1]) {
+                        map.linker_symbols.push((words[0].to_string(), addr));
+                    }
+                }
+            },
+            Block::None => {
+                if let Some((name, kind, local)) = parse_link_tree(line) {
+                    map.link_tree.entry(name).or_insert((kind, local));
+                }
+            },
+        }
+    }
+    return map;
+}
+
+pub fn read_file(file_name: &str) -> std::io::Result<MapFile> {
+    let text = fs::read(file_name).map_err(|e| Error::new(e.kind(), format!("{}: {}", file_name, e)))?;
+    let map = parse(&String::from_utf8_lossy(&text));
+    if map.entries.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, format!("{} has no section layouts, is it a CodeWarrior map?", file_name)));
+    }
+    return Ok(map);
+}
+
+impl MapFile {
+    // Every named symbol, without the per-object section entries (".text  main.o")
+    pub fn symbols(&self) -> Vec<Symbol> {
+        let mut symbols = Vec::new();
+        for entry in &self.entries {
+            if entry.name == entry.section {
+                continue;
+            }
+            let (kind, local) = match self.link_tree.get(&entry.name) {
+                Some(&(SymbolKind::Unknown, local)) => (symbols::section_kind(&entry.section), local),
+                Some(&(kind, local))                => (kind, local),
+                None                                => (symbols::section_kind(&entry.section), false),
+            };
+            symbols.push(Symbol {
+                name:       entry.name.clone(),
+                addr:       entry.addr,
+                size:       entry.size,
+                kind,
+                section:    Some(entry.section.clone()),
+                local,
+            });
+        }
+        return symbols;
+    }
+
+    // Linker generated symbols that land inside a section of the layout, as labels
+    pub fn linker_labels(&self) -> Vec<Symbol> {
+        let mut ranges: HashMap<&str, (u32, u32)> = HashMap::new();
+        for entry in &self.entries {
+            let range = ranges.entry(&entry.section).or_insert((entry.addr, entry.addr));
+            range.0 = range.0.min(entry.addr);
+            range.1 = range.1.max(entry.addr.saturating_add(entry.size));
+        }
+
+        let mut labels = Vec::new();
+        for (name, addr) in &self.linker_symbols {
+            if let Some((section, _)) = ranges.iter().find(|(_, (start, end))| addr >= start && addr < end) {
+                labels.push(Symbol { name: name.clone(), addr: *addr, size: 0, kind: SymbolKind::Unknown, section: Some(section.to_string()), local: false });
+            }
+        }
+        return labels;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Older mwld: no alignment column, memory map without ROM addresses
+    const MAP_NO_ALIGN : &str = "Link map of __start
+ 1] __start (func,weak) found in os.a __start.c
+  2] __init_registers (func,local) found in os.a __start.c
+  2] _stack_addr found as linker generated symbol
+  2] __OSUnused (object,global) found in os.a OS.c
+
+.init section layout
+  Starting        Virtual
+  address  Size   address
+  -----------------------
+  00000000 000240 81300000 .init 	os.a __start.c
+  00000000 00004c 81300000 __init_registers 	os.a __start.c
+  0000004c 000094 8130004c __start (entry of .init) 	os.a __start.c
+  UNUSED   000004 ........ __OSUnused os.a OS.c
+
+Memory map:
+                   Starting Size     File
+                   address           Offset
+       .init  81300000 00000240 00000100
+       .text  81300240 00000400 00000340
+
+Linker generated symbols:
+              _stack_addr 81300100
+               _SDA_BASE_ 81380000
+";
+
+    // With the alignment column
+    const MAP_ALIGN : &str = ".text section layout
+  Starting        Virtual
+  address  Size   address
+  -----------------------
+  00000000 000200 81330440  4 .text 	OS.o
+  00000000 000200 81330440  4 OSInit 	os.a OS.c
+  UNUSED   000034 ........ OSUnusedFunc os.a OS.c
+";
+
+    // mwld 3.0 and later: file offset and alignment, memory map with ROM and RAM buffer addresses
+    const MAP_FILE_OFFSET : &str = ".data section layout
+  Starting        Virtual  File
+  address  Size   address  offset
+  ---------------------------------
+  00000000 000010 81380000 00080000  8 .data 	OS.o
+  00000000 000010 81380000 00080000  4 @123 	OS.o
+
+Memory map:
+                       Starting Size     File     ROM      RAM Buffer
+                       address           Offset   Address  Address
+                 .data 81380000 00000010 00080000 81380000 00000000
+";
+
+    // Section, name, address, size, alignment and object
+    type LayoutLine<'a> = (&'a str, &'a str, u32, u32, Option<u32>, &'a str);
+
+    fn layout(map: &MapFile) -> Vec<LayoutLine<'_>> {
+        return map.entries.iter().map(|e| (e.section.as_str(), e.name.as_str(), e.addr, e.size, e.align, e.object.as_str())).collect();
+    }
+
+    #[test]
+    fn parses_layouts_without_alignment() {
+        let map = parse(MAP_NO_ALIGN);
+        assert_eq!(layout(&map), [(".init", ".init",            0x81300000, 0x240, None, "os.a __start.c"),
+                                  (".init", "__init_registers", 0x81300000, 0x4C,  None, "os.a __start.c"),
+                                  (".init", "__start",          0x8130004C, 0x94,  None, "os.a __start.c")]);
+
+        let sections: Vec<(&str, u32, u32)> = map.sections.iter().map(|s| (s.name.as_str(), s.addr, s.size)).collect();
+        assert_eq!(sections, [(".init", 0x81300000, 0x240), (".text", 0x81300240, 0x400)]);
+        assert_eq!(map.linker_symbols, [("_stack_addr".to_string(), 0x81300100), ("_SDA_BASE_".to_string(), 0x81380000)]);
+
+        // Kind and scope come from the link tree, the section entry is left out
+        assert_eq!(map.link_tree.get("__start"), Some(&(SymbolKind::Function, false)));
+        assert_eq!(map.link_tree.get("__init_registers"), Some(&(SymbolKind::Function, true)));
+        assert_eq!(map.link_tree.get("__OSUnused"), Some(&(SymbolKind::Object, false)));
+        assert!(!map.link_tree.contains_key("_stack_addr"));
+        let symbols: Vec<(String, SymbolKind, bool)> = map.symbols().into_iter().map(|s| (s.name, s.kind, s.local)).collect();
+        assert_eq!(symbols, [("__init_registers".to_string(), SymbolKind::Function, true), ("__start".to_string(), SymbolKind::Function, false)]);
+
+        // Only the linker symbols inside a laid out section become labels
+        let labels: Vec<(String, Option<String>)> = map.linker_labels().into_iter().map(|s| (s.name, s.section)).collect();
+        assert_eq!(labels, [("_stack_addr".to_string(), Some(".init".to_string()))]);
+    }
+
+    #[test]
+    fn parses_layouts_with_alignment() {
+        let map = parse(MAP_ALIGN);
+        assert_eq!(layout(&map), [(".text", ".text",  0x81330440, 0x200, Some(4), "OS.o"),
+                                  (".text", "OSInit", 0x81330440, 0x200, Some(4), "os.a OS.c")]);
+        assert!(map.sections.is_empty());
+        assert_eq!(map.symbols()[0].kind, SymbolKind::Function);
+    }
+
+    #[test]
+    fn parses_layouts_with_file_offsets() {
+        let map = parse(MAP_FILE_OFFSET);
+        assert_eq!(layout(&map), [(".data", ".data", 0x81380000, 0x10, Some(8), "OS.o"),
+                                  (".data", "@123",  0x81380000, 0x10, Some(4), "OS.o")]);
+        let sections: Vec<(&str, u32, u32)> = map.sections.iter().map(|s| (s.name.as_str(), s.addr, s.size)).collect();
+        assert_eq!(sections, [(".data", 0x81380000, 0x10)]);
+        assert_eq!(map.symbols()[0].kind, SymbolKind::Object);
+    }
+
+    #[test]
+    fn entries_past_4gib_do_not_overflow() {
+        let mut map = parse(MAP_ALIGN);
+        map.entries[1].addr = 0xFFFFFF00;
+        map.linker_symbols.push(("_end".to_string(), 0xFFFFFFF0));
+        assert_eq!(map.linker_labels().len(), 1);
+    }
+}
@@ -0,0 +1,154 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use sha1::{Digest, Sha1};
+
+use crate::title::{self, Tmd, TmdContent, CONTENT_SHARED};
+
+// Decrypted NAND as Dolphin keeps it (the "Wii" folder of the user directory).
+pub struct NandRoot {
+    pub root: PathBuf,
+}
+
+fn not_found(message: String) -> Error {
+    return Error::new(ErrorKind::NotFound, message);
+}
+
+impl NandRoot {
+    pub fn new(root: &str) -> std::io::Result<NandRoot> {
+        let root = PathBuf::from(root);
+        if !root.join("title").is_dir() {
+            return Err(not_found(format!("{} is not a NAND root (no title directory)", root.display())));
+        }
+        return Ok(NandRoot { root });
+    }
+
+    pub fn content_dir(&self, title_id: u64) -> PathBuf {
+        return self.root.join("title")
+                        .join(format!("{:08x}", title_id >> 32))
+                        .join(format!("{:08x}", title_id & 0xFFFFFFFF))
+                        .join("content");
+    }
+
+    pub fn tmd_path(&self, title_id: u64) -> PathBuf {
+        return self.content_dir(title_id).join("title.tmd");
+    }
+
+    pub fn read_tmd(&self, title_id: u64) -> std::io::Result<(Vec<u8>, Tmd)> {
+        let tmd_path = self.tmd_path(title_id);
+        let tmd_data = fs::read(&tmd_path).map_err(|e| Error::new(e.kind(), format!("{}: {}", tmd_path.display(), e)))?;
+        let tmd = title::parse_tmd(&tmd_data)?;
+        return Ok((tmd_data, tmd));
+    }
+
+    // Shared contents are looked up by hash in shared1/content.map.
+    pub fn content_path(&self, title_id: u64, content: &TmdContent) -> std::io::Result<PathBuf> {
+        if content.kind & CONTENT_SHARED == 0 {
+            return Ok(self.content_dir(title_id).join(format!("{:08x}.app", content.content_id)));
+        }
+
+        let map_path = self.root.join("shared1").join("content.map");
+        if let Some(name) = title::find_shared_content(&fs::read(&map_path)?, &content.sha1) {
+            return Ok(self.root.join("shared1").join(format!("{}.app", name)));
+        }
+        return Err(not_found(format!("Shared content {:08x} is not in {}", content.content_id, map_path.display())));
+    }
+
+    // Reads the content the title's TMD boots and checks it against the TMD.
+    pub fn read_boot_content(&self, title_id: u64) -> std::io::Result<(Tmd, PathBuf, Vec<u8>)> {
+        let (_, tmd) = self.read_tmd(title_id)?;
+        let content = tmd.boot_content()?.clone();
+        let content_path = self.content_path(title_id, &content)?;
+
+        let content_data = fs::read(&content_path).map_err(|e| Error::new(e.kind(), format!("{}: {}", content_path.display(), e)))?;
+        title::verify_content(&content, &content_data)?;
+
+        return Ok((tmd, content_path, content_data));
+    }
+
+    // Replaces the boot content and fixes its size and SHA-1 in the TMD.
+    // The old TMD and content are kept as .bak, the TMD signature is not redone (Dolphin doesn't check it).
+    pub fn install_boot_content(&self, title_id: u64, content_data: &[u8]) -> std::io::Result<PathBuf> {
+        let (mut tmd_data, tmd) = self.read_tmd(title_id)?;
+        let content = tmd.boot_content()?.clone();
+        if content.kind & CONTENT_SHARED != 0 {
+            return Err(Error::new(ErrorKind::Unsupported, format!("Boot content {:08x} is shared, not replacing it", content.content_id)));
+        }
+
+        let content_path = self.content_path(title_id, &content)?;
+        let tmd_path = self.tmd_path(title_id);
+        backup(&tmd_path)?;
+        if content_path.is_file() {
+            backup(&content_path)?;
+        }
+
+        let i = tmd.contents.iter().position(|c| c.index == content.index).unwrap();
+        let record = title::TMD_CONTENT_OFFSET + i * title::TMD_CONTENT_SIZE;
+        tmd_data[record + 0x08..record + 0x10].copy_from_slice(&(content_data.len() as u64).to_be_bytes());
+        tmd_data[record + 0x10..record + 0x24].copy_from_slice(&Sha1::digest(content_data));
+
+        fs::write(&content_path, content_data)?;
+        fs::write(&tmd_path, &tmd_data)?;
+
+        return Ok(content_path);
+    }
+}
+
+// file -> file.bak, never overwriting an older backup so the original stays around
+fn backup(path: &Path) -> std::io::Result<()> {
+    let mut backup_name = path.as_os_str().to_owned();
+    backup_name.push(".bak");
+    if !Path::new(&backup_name).exists() {
+        fs::copy(path, &backup_name)?;
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gcipl::tests::temp_file;
+    use crate::title::tests::synthetic_tmd;
+    use crate::title::SYSTEM_MENU_TITLE_ID;
+
+    #[test]
+    fn install_patches_tmd_and_keeps_backups() {
+        let root = PathBuf::from(temp_file("nand"));
+        let nand = NandRoot { root: root.clone() };
+        let content_dir = nand.content_dir(SYSTEM_MENU_TITLE_ID);
+        assert!(content_dir.ends_with("title/00000001/00000002/content"));
+        fs::create_dir_all(&content_dir).unwrap();
+
+        let old_content = b"old boot content".to_vec();
+        let old_tmd = synthetic_tmd(SYSTEM_MENU_TITLE_ID, &[(0x2A, 0, b"banner"), (0x97, 1, &old_content)]);
+        fs::write(nand.tmd_path(SYSTEM_MENU_TITLE_ID), &old_tmd).unwrap();
+        fs::write(content_dir.join("0000002a.app"), b"banner").unwrap();
+        fs::write(content_dir.join("00000097.app"), &old_content).unwrap();
+
+        let nand = NandRoot::new(&root.to_string_lossy()).unwrap();
+        let (_, path, data) = nand.read_boot_content(SYSTEM_MENU_TITLE_ID).unwrap();
+        assert_eq!((path.file_name().unwrap().to_str().unwrap(), data), ("00000097.app", old_content.clone()));
+
+        let new_content = b"a new, longer boot content".to_vec();
+        let path = nand.install_boot_content(SYSTEM_MENU_TITLE_ID, &new_content).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), new_content);
+        assert_eq!(fs::read(content_dir.join("00000097.app.bak")).unwrap(), old_content);
+        assert_eq!(fs::read(content_dir.join("title.tmd.bak")).unwrap(), old_tmd);
+
+        // Only the boot record's size and hash change, and the content verifies against the new TMD
+        let (tmd_data, tmd) = nand.read_tmd(SYSTEM_MENU_TITLE_ID).unwrap();
+        let boot = tmd.boot_content().unwrap();
+        assert_eq!(boot.size, new_content.len() as u64);
+        assert_eq!(boot.sha1[..], Sha1::digest(&new_content)[..]);
+        assert_eq!(tmd_data, synthetic_tmd(SYSTEM_MENU_TITLE_ID, &[(0x2A, 0, b"banner"), (0x97, 1, &new_content)]));
+        assert_eq!(nand.read_boot_content(SYSTEM_MENU_TITLE_ID).unwrap().2, new_content);
+
+        // A second install doesn't overwrite the original backup
+        nand.install_boot_content(SYSTEM_MENU_TITLE_ID, b"third").unwrap();
+        assert_eq!(fs::read(content_dir.join("00000097.app.bak")).unwrap(), old_content);
+        assert_eq!(fs::read(content_dir.join("title.tmd.bak")).unwrap(), old_tmd);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
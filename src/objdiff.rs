@@ -0,0 +1,236 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::asmsplit::{self, Link};
+use crate::bootstage::BSImageRef;
+use crate::elf::{self, ObjReloc, ObjSection, ObjSymbol, SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE, STT_FUNC, STT_NOTYPE, STT_OBJECT};
+use crate::meta;
+use crate::project;
+use crate::splits::{Splits, Unit};
+use crate::symbols::{SymbolKind, SymbolMap};
+
+// objdiff.json, paths are relative to the project root
+#[derive(Serialize)]
+pub struct ObjdiffConfig {
+    // The targets are cut out of the image, there's nothing to build
+    pub build_target: bool,
+    pub units: Vec<ObjdiffUnit>,
+}
+
+#[derive(Serialize)]
+pub struct ObjdiffUnit {
+    pub name: String,
+    pub target_path: String,
+    pub base_path: String,
+    pub metadata: ObjdiffMetadata,
+}
+
+#[derive(Serialize)]
+pub struct ObjdiffMetadata {
+    pub source_path: String,
+}
+
+// "OS/OS.c" -> "OS/OS"
+pub fn unit_stem(unit: &Unit) -> &str {
+    return match unit.name.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() && !stem.ends_with('/') => stem,
+        _                                                            => &unit.name,
+    };
+}
+
+// Section flags from the section list of the image, the splits' own type as a fallback
+fn section_flags(sections: &[project::ProjectSection], splits: &Splits, name: &str) -> (u32, bool) {
+    let kind = sections.iter().find(|s| s.name == name).map(|s| s.kind.to_string())
+                       .or_else(|| splits.sections.iter().find(|(n, _)| n == name)
+                                         .and_then(|(_, attributes)| attributes.split_whitespace().find_map(|a| a.strip_prefix("type:")).map(|k| k.to_string())))
+                       .unwrap_or("data".to_string());
+    return match kind.as_str() {
+        "code"   => (SHF_ALLOC | SHF_EXECINSTR, false),
+        "rodata" => (SHF_ALLOC, false),
+        "bss"    => (SHF_ALLOC | SHF_WRITE, true),
+        _        => (SHF_ALLOC | SHF_WRITE, false),
+    };
+}
+
+// The unit's part of the image as a relocatable object. Every range becomes a section, the symbols
+// inside it are kept with their offset into it. Unnamed stretches get fn_/lbl_ symbols like dtk
+// would make, and symbols without a size run up to the next one, so objdiff has something to pair.
+// The references split finds become relocations against the labels split gives their targets
+// (referenced is everything any unit points at), so targets compare by symbol the way dtk's do.
+pub fn unit_object(image: &BSImageRef, splits: &Splits, link: &Link, referenced: &HashSet<u32>, unit: &Unit, symbols: Option<&SymbolMap>) -> std::io::Result<Vec<u8>> {
+    let image_sections = project::sections(image);
+    let mut sections = Vec::new();
+    let mut obj_symbols = Vec::new();
+    let mut defined: HashMap<u32, usize> = HashMap::new();
+
+    for range in &unit.ranges {
+        let (flags, bss) = section_flags(&image_sections, splits, &range.section);
+        let size = range.end - range.start;
+        let data = if bss {
+            None
+        }
+        else {
+            Some(image.section_data(range.start, size).ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData,
+                     format!("{} {} {:#010X}-{:#010X} is outside the image", unit.name, range.section, range.start, range.end)))?.to_vec())
+        };
+        let index = sections.len();
+        sections.push(ObjSection { name: range.section.clone(), flags, align: project::alignment(range.start), size, data, relocs: Vec::new() });
+
+        // Symbols, then the labels of referenced addresses. Referenced code that isn't a function start is
+        // a bare label, which doesn't cut the function it's in short.
+        let code = flags & SHF_EXECINSTR != 0;
+        let mut inside: Vec<(u32, String, u32, SymbolKind, bool, bool)> = symbols.map(|s| s.symbols.as_slice()).unwrap_or(&[]).iter()
+            .filter(|s| s.addr >= range.start && s.addr < range.end)
+            .map(|s| (s.addr, s.name.clone(), s.size, s.kind, s.local, true))
+            .collect();
+        // Where a label shares its address with a typed symbol, the typed one stays
+        inside.sort_by_key(|s| (s.0, s.3 == SymbolKind::Unknown));
+        inside.dedup_by_key(|s| s.0);
+        for symbol in inside.iter_mut() {
+            if let Some(label) = link.label(symbol.0) {
+                symbol.1 = label.name.clone();
+                symbol.4 = !label.global;
+            }
+        }
+        for (addr, label) in link.labels_in(&range.section, range.start, range.end) {
+            if referenced.contains(&addr) && !inside.iter().any(|s| s.0 == addr) {
+                let sized = !code || link.is_function(addr);
+                inside.push((addr, label.name.clone(), 0, SymbolKind::Unknown, !label.global, sized));
+            }
+        }
+        inside.sort_by_key(|s| s.0);
+        if inside.first().map(|s| s.0) != Some(range.start) {
+            let (prefix, kind) = if code { ("fn", SymbolKind::Function) } else { ("lbl", SymbolKind::Object) };
+            let name = link.label(range.start).map(|l| l.name.clone()).unwrap_or(format!("{}_{:08X}", prefix, range.start));
+            inside.insert(0, (range.start, name, 0, kind, false, true));
+        }
+
+        for i in 0..inside.len() {
+            let (addr, ref name, size, kind, local, sized) = inside[i];
+            let next = inside[i + 1..].iter().find(|s| s.5).map(|s| s.0).unwrap_or(range.end);
+            let kind = match kind {
+                _ if !sized          => STT_NOTYPE,
+                SymbolKind::Function => STT_FUNC,
+                SymbolKind::Object   => STT_OBJECT,
+                SymbolKind::Unknown  => if code { STT_FUNC } else { STT_OBJECT },
+            };
+            defined.insert(addr, obj_symbols.len());
+            obj_symbols.push(ObjSymbol {
+                name:       name.clone(),
+                section:    Some(index),
+                value:      addr - range.start,
+                size:       if !sized { 0 } else if size == 0 { next - addr } else { size.min(range.end - addr) },
+                kind,
+                local,
+            });
+        }
+    }
+
+    // Targets outside the unit, the small data bases and whatever is outside BS2 are undefined symbols
+    for (range, section) in unit.ranges.iter().zip(sections.iter_mut()) {
+        for reloc in link.relocations(&range.section, range.start, range.end) {
+            let symbol = match reloc.target.and_then(|t| defined.get(&t)) {
+                Some(symbol) => *symbol,
+                None         => match obj_symbols.iter().position(|s| s.section.is_none() && s.name == reloc.symbol) {
+                    Some(symbol) => symbol,
+                    None         => {
+                        obj_symbols.push(ObjSymbol { name: reloc.symbol, section: None, value: 0, size: 0, kind: STT_NOTYPE, local: false });
+                        obj_symbols.len() - 1
+                    },
+                },
+            };
+            section.relocs.push(ObjReloc { offset: reloc.addr - range.start, kind: reloc.kind, symbol, addend: 0 });
+        }
+    }
+    return Ok(elf::relocatable_elf(&sections, &obj_symbols));
+}
+
+// objdiff.json in out_dir, and a target object per unit under <build_dir>/obj.
+// The base objects are expected under <build_dir>/src, where the project's build puts them.
+pub fn write_project(out_dir: &str, build_dir: &str, image: &BSImageRef, splits: &Splits, symbols: Option<&SymbolMap>) -> std::io::Result<ObjdiffConfig> {
+    let root = Path::new(out_dir);
+    let mut config = ObjdiffConfig { build_target: false, units: Vec::new() };
+    let link = asmsplit::analyse(image, splits, symbols)?;
+    let referenced = link.referenced();
+
+    for unit in &splits.units {
+        let stem = unit_stem(unit);
+        let target_path = format!("{}/obj/{}.o", build_dir, stem);
+        let target_file = root.join(&target_path);
+        if let Some(parent) = target_file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&target_file, unit_object(image, splits, &link, &referenced, unit, symbols)?)?;
+
+        config.units.push(ObjdiffUnit {
+            name:           stem.to_string(),
+            target_path,
+            base_path:      format!("{}/src/{}.o", build_dir, stem),
+            metadata:       ObjdiffMetadata { source_path: format!("src/{}", unit.name) },
+        });
+    }
+
+    meta::write_document(&root.join("objdiff.json").to_string_lossy(), &config)?;
+    return Ok(config);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elf::{R_PPC_ADDR16_HA, R_PPC_ADDR16_LO, R_PPC_ADDR32, R_PPC_REL24, SHN_UNDEF};
+    use crate::gcipl::{self, tests::{put, synthetic_bs2}};
+    use crate::profile;
+    use crate::splits::SplitRange;
+
+    // (offset, type, symbol name, symbol defined) of every entry in a .rela section
+    fn relocations(object: &[u8], name: &str) -> Vec<(u32, u32, String, bool)> {
+        let header = elf::read_elf32_hdr(object).unwrap();
+        let sections = elf::read_elf32_sec_hdrs(object, &header).unwrap();
+        let symbols = elf::read_elf32_symbols(object, &sections).unwrap();
+        let rela = sections.iter().find(|s| s.name == name).unwrap();
+        let data = &object[rela.sh_offset as usize..(rela.sh_offset + rela.sh_size) as usize];
+        return data.chunks_exact(12).map(|entry| {
+            let info = u32::from_be_bytes([entry[4], entry[5], entry[6], entry[7]]);
+            let symbol = &symbols[(info >> 8) as usize];
+            (u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]), info & 0xFF, symbol.name.clone(), symbol.st_shndx != SHN_UNDEF)
+        }).collect();
+    }
+
+    #[test]
+    fn unit_object_has_relocations() {
+        // .text is 0x81300100-0x81300500, .data 0x813005A0-0x813005C0
+        let mut bs2_data = synthetic_bs2();
+        bs2_data[0x100..0x500].copy_from_slice(&[0x60, 0x00, 0x00, 0x00].repeat(0x100));
+        bs2_data[0x500..0x600].fill(0);
+        put(&mut bs2_data, 0x81300100, &[
+            0x3C608130,     // lis r3, 0x8130
+            0x386305A4,     // addi r3, r3, 0x05A4
+            0x480000F9,     // bl 0x81300200
+            0x48000008,     // b 0x81300114
+            0x60000000,     // nop
+            0x4E800020,     // blr
+        ]);
+        put(&mut bs2_data, 0x81300200, &[0x4E800020]);
+        put(&mut bs2_data, 0x813005A0, &[0x81300100]);
+        let image = BSImageRef::from_bs2(gcipl::BS2_ADDR, &bs2_data, profile::gc()).unwrap();
+
+        let unit = Unit { name: "a.c".to_string(), ranges: vec![
+            SplitRange { section: ".text".to_string(), start: 0x81300100, end: 0x81300200 },
+            SplitRange { section: ".data".to_string(), start: 0x813005A0, end: 0x813005C0 },
+        ] };
+        let splits = Splits { sections: Vec::new(), units: vec![unit] };
+        let link = asmsplit::analyse(&image, &splits, None).unwrap();
+        let object = unit_object(&image, &splits, &link, &link.referenced(), &splits.units[0], None).unwrap();
+
+        // The call leaves the unit, the branch to 0x81300114 stays inside the function and keeps its bits
+        assert_eq!(relocations(&object, ".rela.text"), vec![
+            (0x02, R_PPC_ADDR16_HA, "lbl_813005A4".to_string(), true),
+            (0x06, R_PPC_ADDR16_LO, "lbl_813005A4".to_string(), true),
+            (0x08, R_PPC_REL24, "fn_81300200".to_string(), false),
+        ]);
+        assert_eq!(relocations(&object, ".rela.data"), vec![(0x00, R_PPC_ADDR32, "lbl_81300100".to_string(), true)]);
+    }
+}
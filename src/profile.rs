@@ -0,0 +1,210 @@
+use std::io::{Error, ErrorKind};
+
+use serde::{Deserialize, Serialize};
+
+use crate::bootstage::{BSImageRef, BSS_COUNT, DATA_COUNT, HEADER_LENGTH, TEXT_COUNT};
+use crate::meta;
+
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SectionKind {
+    Text,
+    Data,
+}
+
+// Everything bstool needs to know about one family of BootStage images.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct TargetProfile {
+    pub name: String,
+    pub description: String,
+
+    // Start of the initialized BS2 sections (first entry of the ROM copy table)
+    #[serde(with = "meta::hex_u32")]
+    pub init_mem_bound_start: u32,
+    // Lowest address a BSS section may start at
+    #[serde(with = "meta::hex_u32")]
+    pub uninit_mem_bound_start: u32,
+    // Highest address any section may reach
+    #[serde(with = "meta::hex_u32")]
+    pub mem_bound_end: u32,
+
+    #[serde(with = "meta::hex_u32")]
+    pub stub_default_addr: u32,
+    #[serde(with = "meta::hex_u32")]
+    pub stub_default_size: u32,
+
+    // Size of the pad block in front of BS2 (0 = never present)
+    #[serde(with = "meta::hex_u32")]
+    pub bs2_pad: u32,
+
+    // Order of the text/data entries in the ROM copy table
+    pub link_order: Vec<SectionKind>,
+
+    pub text_names: Vec<String>,
+    pub data_names: Vec<String>,
+    pub bss_names: Vec<String>,
+}
+
+fn names(list: &[&str]) -> Vec<String> {
+    return list.iter().map(|s| s.to_string()).collect();
+}
+
+fn retail_link_order() -> Vec<SectionKind> {
+    use SectionKind::*;
+    return vec![Text, Data, Data, Text, Data, Data, Data, Data, Data, Data];
+}
+
+pub fn retail() -> TargetProfile {
+    return TargetProfile {
+        name:                   "wii".to_string(),
+        description:            "Retail Wii IPL BootStage".to_string(),
+
+        init_mem_bound_start:   0x81330000,
+        uninit_mem_bound_start: 0x81080000,
+        mem_bound_end:          0x816D0000, // AFAIK no existing retail boot stage exceeds that boundary.
+
+        stub_default_addr:      0x81340000,
+        stub_default_size:      0x00010000,
+
+        bs2_pad:                0x20,
+
+        link_order:             retail_link_order(),
+
+        text_names:             names(&[".init", ".text"]),
+        data_names:             names(&["extab", "extabindex", ".ctors", ".dtors", ".rodata", ".data", ".sdata", ".sdata2"]),
+        bss_names:              names(&[".bss", ".sbss", ".sbss2"]),
+    };
+}
+
+pub fn vwii() -> TargetProfile {
+    let mut profile = retail();
+    profile.name        = "vwii".to_string();
+    profile.description = "Wii U vWii System Menu BootStage".to_string();
+    // Same layout as retail, but BS2 may grow up to the end of MEM1
+    profile.mem_bound_end = 0x81800000;
+    return profile;
+}
+
+pub fn ndev() -> TargetProfile {
+    let mut profile = retail();
+    profile.name        = "ndev".to_string();
+    profile.description = "Development (NDEV) IPL BootStage".to_string();
+    // Development builds are not tied to the retail memory map, so accept anything in MEM1
+    profile.uninit_mem_bound_start = 0x80004000;
+    profile.mem_bound_end          = 0x81800000;
+    return profile;
+}
+
+pub fn gc() -> TargetProfile {
+    let mut profile = retail();
+    profile.name        = "gc".to_string();
+    profile.description = "GameCube IPL ROM BS2".to_string();
+    // BS1 copies BS2 to 0x81300000, there is no stub and no pad block
+    profile.init_mem_bound_start   = 0x81300000;
+    profile.uninit_mem_bound_start = 0x80000000;
+    profile.mem_bound_end          = 0x81800000;
+    profile.stub_default_addr      = 0;
+    profile.stub_default_size      = 0;
+    profile.bs2_pad                = 0;
+    return profile;
+}
+
+impl Default for TargetProfile {
+    fn default() -> TargetProfile {
+        let mut profile = retail();
+        profile.name        = "custom".to_string();
+        profile.description = "Custom profile".to_string();
+        return profile;
+    }
+}
+
+// Built-in profiles, narrowest bounds first (that's also the auto-detection order).
+pub fn builtin() -> Vec<TargetProfile> {
+    return vec![retail(), vwii(), ndev(), gc()];
+}
+
+pub fn verify_profile(profile: &TargetProfile) -> std::io::Result<()> {
+    let texts = profile.link_order.iter().filter(|&&k| k == SectionKind::Text).count();
+    let datas = profile.link_order.iter().filter(|&&k| k == SectionKind::Data).count();
+
+    if texts != TEXT_COUNT || datas != DATA_COUNT {
+        return Err(Error::new(ErrorKind::InvalidData,
+                              format!("Profile \"{}\": link_order needs {} text and {} data entries", profile.name, TEXT_COUNT, DATA_COUNT)));
+    }
+    if profile.text_names.len() != TEXT_COUNT || profile.data_names.len() != DATA_COUNT || profile.bss_names.len() != BSS_COUNT {
+        return Err(Error::new(ErrorKind::InvalidData,
+                              format!("Profile \"{}\": needs {} text, {} data and {} bss names", profile.name, TEXT_COUNT, DATA_COUNT, BSS_COUNT)));
+    }
+    if profile.uninit_mem_bound_start > profile.mem_bound_end || profile.init_mem_bound_start > profile.mem_bound_end {
+        return Err(Error::new(ErrorKind::InvalidData,
+                              format!("Profile \"{}\": memory bounds are out of order", profile.name)));
+    }
+    // The pad block is checked for two non-zero words
+    if !profile.bs2_pad.is_multiple_of(0x20) {
+        return Err(Error::new(ErrorKind::InvalidData,
+                              format!("Profile \"{}\": bs2_pad has to be 0 or a multiple of 0x20", profile.name)));
+    }
+
+    return Ok(());
+}
+
+// Looks up a built-in profile by name, otherwise loads a custom one from a JSON/YAML/TOML file.
+// Fields missing from a custom profile keep their retail values.
+pub fn find(name_or_file: &str) -> std::io::Result<TargetProfile> {
+    if let Some(profile) = builtin().into_iter().find(|p| p.name.eq_ignore_ascii_case(name_or_file)) {
+        return Ok(profile);
+    }
+
+    if !std::path::Path::new(name_or_file).is_file() {
+        let known: Vec<String> = builtin().into_iter().map(|p| p.name).collect();
+        return Err(Error::new(ErrorKind::NotFound,
+                              format!("Unknown profile \"{}\" (built-in: {}, or a profile file)", name_or_file, known.join(", "))));
+    }
+
+    let profile: TargetProfile = meta::read_document(name_or_file)?;
+    verify_profile(&profile)?;
+    return Ok(profile);
+}
+
+fn read_u32(file_data: &[u8], offset: usize) -> Option<u32> {
+    let temp = file_data.get(offset..offset + 4)?;
+    return Some(u32::from_be_bytes([temp[0], temp[1], temp[2], temp[3]]));
+}
+
+// Whether the header's BS2 placement fits inside a profile's bounds.
+pub fn header_fits(file_data: &[u8], profile: &TargetProfile) -> bool {
+    if file_data.len() < HEADER_LENGTH {
+        return false;
+    }
+
+    let (Some(bs2_addr), Some(bs2_len)) = (read_u32(file_data, 0x64), read_u32(file_data, 0xAC)) else {
+        return false;
+    };
+
+    let starts_ok = bs2_addr == profile.init_mem_bound_start ||
+                    (profile.bs2_pad != 0 && bs2_addr.checked_add(profile.bs2_pad) == Some(profile.init_mem_bound_start));
+    let ends_ok = (bs2_addr as u64 + bs2_len as u64) <= profile.mem_bound_end as u64;
+
+    return starts_ok && ends_ok;
+}
+
+// Picks the narrowest built-in profile the image parses with. Several can fit the header (vwii and ndev
+// only differ in where BSS may go), so each is tried on the section tables. If none parses, the narrowest
+// one fitting the header, falling back to retail.
+pub fn detect(file_data: &[u8]) -> TargetProfile {
+    let fitting: Vec<TargetProfile> = builtin().into_iter().filter(|p| header_fits(file_data, p)).collect();
+    return fitting.iter()
+                  .find(|p| BSImageRef::parse_with_profile(file_data, (*p).clone()).is_ok())
+                  .or(fitting.first())
+                  .cloned()
+                  .unwrap_or_else(retail);
+}
+
+// Explicit profile if one was given, otherwise auto-detection.
+pub fn select(profile: &Option<String>, file_data: &[u8]) -> std::io::Result<TargetProfile> {
+    return match profile {
+        Some(name) => find(name),
+        None       => Ok(detect(file_data)),
+    };
+}
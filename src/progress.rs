@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::bootstage::BSImageRef;
+use crate::elf;
+use crate::project;
+use crate::splits::Splits;
+use crate::symbols::{self, Symbol, SymbolKind, SymbolMap};
+
+// Same measure names as objdiff's progress report, which the decomp dashboards read
+#[derive(Serialize, Default, Clone)]
+pub struct Measures {
+    pub total_code: u64,
+    pub matched_code: u64,
+    pub matched_code_percent: f32,
+    pub total_data: u64,
+    pub matched_data: u64,
+    pub matched_data_percent: f32,
+    pub total_functions: u32,
+    pub matched_functions: u32,
+    pub matched_functions_percent: f32,
+}
+
+#[derive(Serialize)]
+pub struct SectionProgress {
+    pub name: String,
+    // code, data or rodata
+    pub kind: String,
+    pub total: u64,
+    pub matched: u64,
+    pub matched_percent: f32,
+}
+
+#[derive(Serialize)]
+pub struct UnitProgress {
+    pub name: String,
+    pub measures: Measures,
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    pub measures: Measures,
+    pub sections: Vec<SectionProgress>,
+    pub units: Vec<UnitProgress>,
+}
+
+// A sized symbol of the original and whether the built ELF has the same bytes for it
+pub struct SymbolMatch<'a> {
+    pub symbol: &'a Symbol,
+    pub code: bool,
+    pub matched: bool,
+}
+
+fn percent(matched: u64, total: u64) -> f32 {
+    return if total == 0 { 0.0 } else { matched as f32 * 100.0 / total as f32 };
+}
+
+impl Measures {
+    fn add(&mut self, symbol: &SymbolMatch) {
+        let size = symbol.symbol.size as u64;
+        let function = symbol.code && symbol.symbol.kind == SymbolKind::Function;
+        if symbol.matched {
+            if symbol.code { self.matched_code += size; } else { self.matched_data += size; }
+            if function { self.matched_functions += 1; }
+        }
+        if function {
+            self.total_functions += 1;
+        }
+    }
+
+    fn finish(&mut self) {
+        self.matched_code_percent = percent(self.matched_code, self.total_code);
+        self.matched_data_percent = percent(self.matched_data, self.total_data);
+        self.matched_functions_percent = percent(self.matched_functions as u64, self.total_functions as u64);
+    }
+}
+
+// Built symbols by name. A global wins over a local of the same name.
+pub fn by_name(built: &[Symbol]) -> HashMap<&str, &Symbol> {
+    let mut names: HashMap<&str, &Symbol> = HashMap::new();
+    for symbol in built {
+        let entry = names.entry(&symbol.name).or_insert(symbol);
+        if entry.local && !symbol.local {
+            *entry = symbol;
+        }
+    }
+    return names;
+}
+
+// Every sized symbol in a loaded, non-BSS section, compared by name against the built ELF.
+// Symbols the ELF doesn't have, or has with a different size, don't match.
+pub fn match_symbols<'a>(image: &BSImageRef, symbols: &'a SymbolMap, built_data: &[u8]) -> std::io::Result<Vec<SymbolMatch<'a>>> {
+    let header = elf::read_elf32_hdr(built_data)?;
+    let built_sections = elf::read_elf32_sec_hdrs(built_data, &header)?;
+    let (_, built) = symbols::read_elf(built_data)?;
+    let built = by_name(&built);
+    let sections = project::sections(image);
+
+    let mut matches = Vec::new();
+    for symbol in symbols.symbols.iter().filter(|s| s.size != 0) {
+        let Some(section) = sections.iter().find(|s| s.kind != "bss" && symbol.addr >= s.addr && symbol.addr - s.addr < s.len) else {
+            continue;
+        };
+        let original = image.section_data(symbol.addr, symbol.size);
+        let matched = match (original, built.get(symbol.name.as_str())) {
+            (Some(original), Some(other)) if other.size == symbol.size => {
+                elf::data_at(built_data, &built_sections, other.addr, other.size) == Some(original)
+            },
+            _ => false,
+        };
+        matches.push(SymbolMatch { symbol, code: section.kind == "code", matched });
+    }
+    return Ok(matches);
+}
+
+pub fn report(image: &BSImageRef, matches: &[SymbolMatch], splits: Option<&Splits>) -> Report {
+    let mut total = Measures::default();
+    let mut sections = Vec::new();
+    for section in project::sections(image).into_iter().filter(|s| s.kind != "bss") {
+        let matched: u64 = matches.iter().filter(|m| m.matched && m.symbol.addr >= section.addr && m.symbol.addr - section.addr < section.len)
+                                  .map(|m| m.symbol.size as u64).sum();
+        if section.kind == "code" { total.total_code += section.len as u64; } else { total.total_data += section.len as u64; }
+        sections.push(SectionProgress {
+            name:               section.name,
+            kind:               section.kind.to_string(),
+            total:              section.len as u64,
+            matched,
+            matched_percent:    percent(matched, section.len as u64),
+        });
+    }
+    for symbol in matches {
+        total.add(symbol);
+    }
+    total.finish();
+
+    let mut units = Vec::new();
+    let code_sections: Vec<String> = project::sections(image).into_iter().filter(|s| s.kind == "code").map(|s| s.name).collect();
+    let bss_sections: Vec<String> = project::sections(image).into_iter().filter(|s| s.kind == "bss").map(|s| s.name).collect();
+    for unit in splits.map(|s| s.units.as_slice()).unwrap_or(&[]) {
+        let mut measures = Measures::default();
+        for range in unit.ranges.iter().filter(|r| !bss_sections.contains(&r.section)) {
+            let size = (range.end - range.start) as u64;
+            if code_sections.contains(&range.section) { measures.total_code += size; } else { measures.total_data += size; }
+            for symbol in matches.iter().filter(|m| m.symbol.addr >= range.start && m.symbol.addr < range.end) {
+                measures.add(symbol);
+            }
+        }
+        measures.finish();
+        units.push(UnitProgress { name: unit.name.clone(), measures });
+    }
+
+    return Report { measures: total, sections, units };
+}
+
+pub fn print_report(report: &Report) {
+    let measures = &report.measures;
+    println!("Code:       {} / {} bytes ({:.2}%)", measures.matched_code, measures.total_code, measures.matched_code_percent);
+    println!("Data:       {} / {} bytes ({:.2}%)", measures.matched_data, measures.total_data, measures.matched_data_percent);
+    println!("Functions:  {} / {} ({:.2}%)", measures.matched_functions, measures.total_functions, measures.matched_functions_percent);
+
+    println!();
+    println!("Sections:");
+    for section in &report.sections {
+        println!("  {:<12} {:>8} / {:<8} bytes ({:.2}%)", section.name, section.matched, section.total, section.matched_percent);
+    }
+
+    if !report.units.is_empty() {
+        println!();
+        println!("Units:");
+        for unit in &report.units {
+            let measures = &unit.measures;
+            println!("  {:<32} code {:.2}%, data {:.2}%, {} / {} functions",
+                     unit.name, measures.matched_code_percent, measures.matched_data_percent, measures.matched_functions, measures.total_functions);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elf::{ObjSection, ObjSymbol, SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE, STT_FUNC, STT_OBJECT};
+    use crate::gcipl::{self, tests::synthetic_bs2};
+    use crate::profile;
+    use crate::splits::{SplitRange, Unit};
+
+    fn symbol(name: &str, addr: u32, size: u32, kind: SymbolKind) -> Symbol {
+        return Symbol { name: name.to_string(), addr, size, kind, section: None, local: false };
+    }
+
+    fn range(section: &str, start: u32, end: u32) -> SplitRange {
+        return SplitRange { section: section.to_string(), start, end };
+    }
+
+    // An executable-like ELF: .text, .rodata and .data at the given addresses with the given contents
+    fn built_elf(sections: &[(&str, u32, u32, Vec<u8>)], symbols: &[(&str, usize, u32, u32, u8)]) -> Vec<u8> {
+        let obj_sections: Vec<ObjSection> = sections.iter().map(|(name, _, flags, data)| {
+            ObjSection { name: name.to_string(), flags: *flags, align: 4, size: data.len() as u32, data: Some(data.clone()), relocs: Vec::new() }
+        }).collect();
+        let obj_symbols: Vec<ObjSymbol> = symbols.iter().map(|(name, section, value, size, kind)| {
+            ObjSymbol { name: name.to_string(), section: Some(*section), value: *value, size: *size, kind: *kind, local: false }
+        }).collect();
+        let mut elf_data = elf::relocatable_elf(&obj_sections, &obj_symbols);
+
+        // Move the sections to their load addresses, as the linker would
+        let header = elf::read_elf32_hdr(&elf_data).unwrap();
+        for (i, (_, addr, _, _)) in sections.iter().enumerate() {
+            let sh_addr = header.e_shoff as usize + (i + 1) * header.e_shentsize as usize + 0x0C;
+            elf_data[sh_addr..sh_addr + 4].copy_from_slice(&addr.to_be_bytes());
+        }
+        return elf_data;
+    }
+
+    #[test]
+    fn counts_matched_bytes_and_functions() {
+        // .init 0x81300000-0x81300100, .text 0x81300100-0x81300500, .rodata 0x81300580, .data 0x813005A0, eight 0x20 data sections
+        let bs2_data = synthetic_bs2();
+        let image = BSImageRef::from_bs2(gcipl::BS2_ADDR, &bs2_data, profile::gc()).unwrap();
+        let original = |addr: u32, len: usize| image.section_data(addr, len as u32).unwrap().to_vec();
+
+        let symbols = SymbolMap::new(vec![
+            symbol("fn_same",      0x81300100, 0x40, SymbolKind::Function),
+            symbol("fn_changed",   0x81300140, 0x40, SymbolKind::Function),
+            symbol("fn_resized",   0x81300180, 0x20, SymbolKind::Function),
+            symbol("fn_missing",   0x813001A0, 0x10, SymbolKind::Function),
+            symbol("lbl_813001B0", 0x813001B0, 0,    SymbolKind::Unknown),
+            symbol("obj_rodata",   0x81300580, 0x08, SymbolKind::Object),
+            symbol("obj_data",     0x813005A0, 0x10, SymbolKind::Object),
+            symbol("obj_bss",      0x81300600, 0x04, SymbolKind::Object),
+        ]);
+
+        let mut text = original(0x81300100, 0xC0);
+        text[0x44] ^= 0xFF;
+        let mut rodata = original(0x81300580, 0x08);
+        rodata[0] ^= 0xFF;
+        let built = built_elf(&[(".text",   0x81300100, SHF_ALLOC | SHF_EXECINSTR, text),
+                                (".rodata", 0x81300580, SHF_ALLOC,                 rodata),
+                                (".data",   0x813005A0, SHF_ALLOC | SHF_WRITE,     original(0x813005A0, 0x10))],
+                              &[("fn_same",    0, 0x00, 0x40, STT_FUNC),
+                                ("fn_changed", 0, 0x40, 0x40, STT_FUNC),
+                                ("fn_resized", 0, 0x80, 0x24, STT_FUNC),
+                                ("obj_rodata", 1, 0x00, 0x08, STT_OBJECT),
+                                ("obj_data",   2, 0x00, 0x10, STT_OBJECT)]);
+
+        let matches = match_symbols(&image, &symbols, &built).unwrap();
+        let matched: Vec<(&str, bool, bool)> = matches.iter().map(|m| (m.symbol.name.as_str(), m.code, m.matched)).collect();
+        assert_eq!(matched, [("fn_same", true, true), ("fn_changed", true, false), ("fn_resized", true, false), ("fn_missing", true, false),
+                             ("obj_rodata", false, false), ("obj_data", false, true)]);
+
+        let splits = Splits { sections: Vec::new(), units: vec![
+            Unit { name: "a.c".to_string(), ranges: vec![range(".text", 0x81300100, 0x81300180), range(".data", 0x813005A0, 0x813005C0), range(".bss", 0x81300600, 0x81300700)] },
+            Unit { name: "b.c".to_string(), ranges: vec![range(".text", 0x81300180, 0x813001C0), range(".rodata", 0x81300580, 0x813005A0)] },
+        ] };
+        let report = report(&image, &matches, Some(&splits));
+
+        let measures = |m: &Measures| (m.total_code, m.matched_code, m.total_data, m.matched_data, m.total_functions, m.matched_functions);
+        assert_eq!(measures(&report.measures), (0x500, 0x40, 0x100, 0x10, 4, 1));
+        assert_eq!(report.measures.matched_functions_percent, 25.0);
+        assert_eq!(measures(&report.units[0].measures), (0x80, 0x40, 0x20, 0x10, 2, 1));
+        assert_eq!(measures(&report.units[1].measures), (0x40, 0, 0x20, 0, 2, 0));
+        assert_eq!(report.units[0].measures.matched_code_percent, 50.0);
+
+        let sections: Vec<(&str, &str, u64, u64)> = report.sections.iter().map(|s| (s.name.as_str(), s.kind.as_str(), s.total, s.matched)).collect();
+        assert_eq!(sections, [(".init", "code", 0x100, 0), (".text", "code", 0x400, 0x40),
+                              ("extab", "rodata", 0x20, 0), ("extabindex", "rodata", 0x20, 0), (".ctors", "rodata", 0x20, 0), (".dtors", "rodata", 0x20, 0),
+                              (".rodata", "rodata", 0x20, 0), (".data", "data", 0x20, 0x10), (".sdata", "data", 0x20, 0), (".sdata2", "rodata", 0x20, 0)]);
+
+        // The names the dashboards read
+        let json = serde_json::to_value(&report).unwrap();
+        let keys = |value: &serde_json::Value| value.as_object().unwrap().keys().cloned().collect::<Vec<String>>();
+        assert_eq!(keys(&json), ["measures", "sections", "units"]);
+        assert_eq!(keys(&json["measures"]), ["matched_code", "matched_code_percent", "matched_data", "matched_data_percent",
+                                             "matched_functions", "matched_functions_percent", "total_code", "total_data", "total_functions"]);
+        assert_eq!(keys(&json["sections"][0]), ["kind", "matched", "matched_percent", "name", "total"]);
+        assert_eq!(keys(&json["units"][0]), ["measures", "name"]);
+        assert_eq!(json["units"][1]["name"], "b.c");
+        assert_eq!(json["measures"]["total_code"], 0x500);
+    }
+}
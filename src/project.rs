@@ -0,0 +1,107 @@
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::bootstage::{BSImageRef, BSS_COUNT, DATA_COUNT, TEXT_COUNT};
+use crate::meta;
+
+// The decomp-toolkit config.yml, paths are relative to the project root
+#[derive(Serialize)]
+pub struct DtkConfig {
+    pub object: String,
+    pub hash: String,
+    pub symbols: String,
+    pub splits: String,
+}
+
+pub struct ProjectSection {
+    pub name: String,
+    pub addr: u32,
+    pub len: u32,
+    // decomp-toolkit section type (code, data, rodata or bss)
+    pub kind: &'static str,
+}
+
+// Loaded sections in DOL order (text, data, then bss), empty ones left out
+pub fn sections(image: &BSImageRef) -> Vec<ProjectSection> {
+    let mut sections = Vec::new();
+    for i in 0..TEXT_COUNT {
+        sections.push(ProjectSection { name: image.profile.text_names[i].clone(), addr: image.text_addr[i], len: image.text_len[i], kind: "code" });
+    }
+    for i in 0..DATA_COUNT {
+        let name = &image.profile.data_names[i];
+        let kind = if name == ".data" || name == ".sdata" { "data" } else { "rodata" };
+        sections.push(ProjectSection { name: name.clone(), addr: image.data_addr[i], len: image.data_len[i], kind });
+    }
+    for i in 0..BSS_COUNT {
+        sections.push(ProjectSection { name: image.profile.bss_names[i].clone(), addr: image.bss[i].addr, len: image.bss[i].size, kind: "bss" });
+    }
+    sections.retain(|s| s.len != 0);
+    return sections;
+}
+
+// A section starting right at addr wins over one it's inside of (the BSS sections overlap)
+fn section_of(sections: &[ProjectSection], addr: u32) -> Option<&ProjectSection> {
+    return sections.iter().find(|s| s.addr == addr)
+                   .or_else(|| sections.iter().find(|s| addr >= s.addr && addr - s.addr < s.len));
+}
+
+// Best guess from where the section starts, capped at 32
+pub fn alignment(addr: u32) -> u32 {
+    return 1 << addr.trailing_zeros().min(5);
+}
+
+// Start labels for every section, the BS2 entry point and the tables BS1 copies BS2 with.
+// MWLD names the tables _rom_copy_info and _bss_init_info, both end with an empty entry.
+pub fn symbols_txt(image: &BSImageRef) -> String {
+    let sections = sections(image);
+    let mut lines: Vec<(u32, String)> = Vec::new();
+    let mut symbol = |name: &str, addr: u32, attributes: String| {
+        if let Some(section) = section_of(&sections, addr) {
+            lines.push((addr, format!("{} = {}:{:#010X}; // {} scope:global\n", name, section.name, addr, attributes)));
+        }
+    };
+
+    symbol("__start", image.bs2_entry, "type:function".to_string());
+    symbol("_rom_copy_info", image.bs2_addr + image.rom_table_off,
+           format!("type:object size:{:#X}", (TEXT_COUNT + DATA_COUNT + 1) * 0x0C));
+    symbol("_bss_init_info", image.bs2_addr + image.bss_table_off,
+           format!("type:object size:{:#X}", (BSS_COUNT + 1) * 0x08));
+    for section in &sections {
+        symbol(&format!("_f_{}", section.name.trim_start_matches('.')), section.addr, "type:label".to_string());
+    }
+
+    lines.sort_by_key(|(addr, _)| *addr);
+    return lines.into_iter().map(|(_, line)| line).collect();
+}
+
+// Only the section list, the ranges are left as comments to split from.
+pub fn splits_txt(image: &BSImageRef) -> String {
+    let sections = sections(image);
+    let mut text = String::from("// Section ranges:\n");
+    for section in &sections {
+        text += &format!("//   {:<12} start:{:#010X} end:{:#010X}\n", section.name, section.addr, section.addr + section.len);
+    }
+    text += "\nSections:\n";
+    for section in &sections {
+        text += &format!("\t{:<12} type:{} align:{}\n", section.name, section.kind, alignment(section.addr));
+    }
+    return text;
+}
+
+// config/config.yml, config/symbols.txt and config/splits.txt next to an already written DOL.
+pub fn write_config(out_dir: &str, dol_file: &str, image: &BSImageRef) -> std::io::Result<()> {
+    let root = Path::new(out_dir);
+    fs::create_dir_all(root.join("config"))?;
+
+    let config = DtkConfig {
+        object:     dol_file.to_string(),
+        hash:       meta::sha1_hex(&fs::read(root.join(dol_file))?),
+        symbols:    "config/symbols.txt".to_string(),
+        splits:     "config/splits.txt".to_string(),
+    };
+    fs::write(root.join("config").join("symbols.txt"), symbols_txt(image))?;
+    fs::write(root.join("config").join("splits.txt"), splits_txt(image))?;
+    return meta::write_document(&root.join("config").join("config.yml").to_string_lossy(), &config);
+}
@@ -0,0 +1,238 @@
+use std::fs;
+
+use crate::bootstage::BSImageRef;
+use crate::project;
+use crate::symbols::{SymbolKind, SymbolMap};
+
+// Import files for reverse-engineering tools: a Ghidra XML program and an IDA Python script.
+// Both load the block contents from one .bytes file written next to them.
+
+pub struct MemoryBlock<'a> {
+    pub name: String,
+    pub addr: u32,
+    pub len: u32,
+    // "rx", "r" or "rw"
+    pub perms: &'static str,
+    // None for BSS
+    pub data: Option<&'a [u8]>,
+}
+
+// Parts of [start, end) not covered by any of taken
+fn uncovered(start: u32, end: u32, taken: &[(u32, u32)]) -> Vec<(u32, u32)> {
+    let mut pieces = vec![(start, end)];
+    for &(taken_start, taken_end) in taken {
+        pieces = pieces.into_iter().flat_map(|(start, end)| {
+            if taken_end <= start || taken_start >= end {
+                return vec![(start, end)];
+            }
+            return [(start, taken_start), (taken_end, end)].into_iter().filter(|(s, e)| s < e).collect();
+        }).collect();
+    }
+    return pieces;
+}
+
+// BS1 and the BS2 sections, plus "BS2" blocks for whatever part of BS2 no section covers.
+// The BSS sections can overlap each other and BS1, so they only get what's left over, split into numbered blocks if needed.
+pub fn memory_blocks<'a>(image: &BSImageRef<'a>) -> Vec<MemoryBlock<'a>> {
+    let mut blocks = vec![MemoryBlock { name: "BS1".to_string(), addr: image.bs1_addr, len: image.bs1_len, perms: "rx", data: Some(image.bs1_data) }];
+
+    let sections = project::sections(image);
+    for section in sections.iter().filter(|s| s.kind != "bss") {
+        let perms = match section.kind {
+            "code" => "rx",
+            "data" => "rw",
+            _      => "r",
+        };
+        if let Some(data) = image.section_data(section.addr, section.len) {
+            blocks.push(MemoryBlock { name: section.name.clone(), addr: section.addr, len: section.len, perms, data: Some(data) });
+        }
+    }
+
+    let taken: Vec<(u32, u32)> = blocks.iter().map(|b| (b.addr, b.addr + b.len)).collect();
+    let bs2_end = image.bs2_addr + image.bs2_data.len() as u32;
+    for (i, (start, end)) in uncovered(image.bs2_addr, bs2_end, &taken).into_iter().enumerate() {
+        let name = if i == 0 { "BS2".to_string() } else { format!("BS2.{}", i + 1) };
+        blocks.push(MemoryBlock { name, addr: start, len: end - start, perms: "rx", data: image.section_data(start, end - start) });
+    }
+
+    for section in sections.iter().filter(|s| s.kind == "bss") {
+        let taken: Vec<(u32, u32)> = blocks.iter().map(|b| (b.addr, b.addr + b.len)).collect();
+        for (i, (start, end)) in uncovered(section.addr, section.addr + section.len, &taken).into_iter().enumerate() {
+            let name = if i == 0 { section.name.clone() } else { format!("{}.{}", section.name, i + 1) };
+            blocks.push(MemoryBlock { name, addr: start, len: end - start, perms: "rw", data: None });
+        }
+    }
+
+    blocks.sort_by_key(|b| b.addr);
+    return blocks;
+}
+
+fn escape_xml(text: &str) -> String {
+    return text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;");
+}
+
+fn escape_python(text: &str) -> String {
+    return text.replace('\\', "\\\\").replace('"', "\\\"");
+}
+
+// Ghidra's XML importer format (what its XML exporter writes)
+pub fn ghidra_xml(program: &str, bytes_file: &str, blocks: &[MemoryBlock], offsets: &[Option<usize>], entries: &[(u32, String)], symbols: Option<&SymbolMap>) -> String {
+    let mut xml = String::new();
+    xml += "<?xml version=\"1.0\" standalone=\"yes\"?>\n";
+    xml += "<?program_dtd version=\"1\"?>\n";
+    xml += &format!("<PROGRAM NAME=\"{}\" EXE_FORMAT=\"BootStage\" IMAGE_BASE=\"{:08X}\">\n", escape_xml(program), blocks[0].addr);
+    xml += "    <INFO_SOURCE TOOL=\"bstool\" />\n";
+    xml += "    <PROCESSOR NAME=\"PowerPC\" LANGUAGE_PROVIDER=\"PowerPC:BE:32:Gekko_Broadway:default\" ENDIAN=\"big\" ADDRESS_MODEL=\"32-bit\" />\n";
+
+    xml += "    <MEMORY_MAP>\n";
+    for (block, offset) in blocks.iter().zip(offsets) {
+        let head = format!("        <MEMORY_SECTION NAME=\"{}\" START_ADDR=\"{:08X}\" LENGTH=\"{:#X}\" PERMISSIONS=\"{}\"",
+                           escape_xml(&block.name), block.addr, block.len, block.perms);
+        match offset {
+            Some(offset) => {
+                xml += &format!("{}>\n", head);
+                xml += &format!("            <MEMORY_CONTENTS START_ADDR=\"{:08X}\" FILE_NAME=\"{}\" FILE_OFFSET=\"{:#X}\" LENGTH=\"{:#X}\" />\n",
+                                block.addr, escape_xml(bytes_file), offset, block.len);
+                xml += "        </MEMORY_SECTION>\n";
+            },
+            None => xml += &format!("{} />\n", head),
+        }
+    }
+    xml += "    </MEMORY_MAP>\n";
+
+    xml += "    <PROGRAM_ENTRY_POINTS>\n";
+    for (addr, _) in entries {
+        xml += &format!("        <PROGRAM_ENTRY_POINT ADDRESS=\"{:08X}\" />\n", addr);
+    }
+    xml += "    </PROGRAM_ENTRY_POINTS>\n";
+
+    xml += "    <SYMBOL_TABLE>\n";
+    for (addr, name) in entries {
+        xml += &format!("        <SYMBOL ADDRESS=\"{:08X}\" NAME=\"{}\" TYPE=\"global\" SOURCE_TYPE=\"IMPORTED\" PRIMARY=\"y\" />\n", addr, escape_xml(name));
+    }
+    for symbol in symbols.map(|s| s.symbols.as_slice()).unwrap_or(&[]) {
+        xml += &format!("        <SYMBOL ADDRESS=\"{:08X}\" NAME=\"{}\" TYPE=\"{}\" SOURCE_TYPE=\"IMPORTED\" PRIMARY=\"y\" />\n",
+                        symbol.addr, escape_xml(&symbol.name), if symbol.local { "local" } else { "global" });
+    }
+    xml += "    </SYMBOL_TABLE>\n";
+
+    xml += "    <FUNCTIONS>\n";
+    for symbol in symbols.map(|s| s.symbols.as_slice()).unwrap_or(&[]).iter().filter(|s| s.kind == SymbolKind::Function && s.size != 0) {
+        xml += &format!("        <FUNCTION ENTRY_POINT=\"{:08X}\" NAME=\"{}\" LIBRARY_FUNCTION=\"n\">\n", symbol.addr, escape_xml(&symbol.name));
+        xml += &format!("            <ADDRESS_RANGE START=\"{:08X}\" END=\"{:08X}\" />\n", symbol.addr, symbol.addr + symbol.size - 1);
+        xml += "        </FUNCTION>\n";
+    }
+    xml += "    </FUNCTIONS>\n";
+    xml += "</PROGRAM>\n";
+    return xml;
+}
+
+// IDAPython script that builds the segments in an empty database and fills them from the .bytes file
+pub fn ida_script(bytes_file: &str, blocks: &[MemoryBlock], offsets: &[Option<usize>], entries: &[(u32, String)], symbols: Option<&SymbolMap>) -> String {
+    let mut py = String::new();
+    py += "# Generated by bstool. In IDA: open an empty PowerPC (big endian) database, then File > Script file.\n";
+    py += "import os\n";
+    py += "import ida_bytes, ida_entry, ida_funcs, ida_ida, ida_idp, ida_name, ida_segment\n\n";
+    py += &format!("BYTES_FILE = os.path.join(os.path.dirname(os.path.abspath(__file__)), \"{}\")\n\n", escape_python(bytes_file));
+
+    py += "# (name, start, length, permissions, class, offset in BYTES_FILE or None for BSS)\n";
+    py += "SEGMENTS = [\n";
+    for (block, offset) in blocks.iter().zip(offsets) {
+        let mut perms = Vec::new();
+        if block.perms.contains('r') { perms.push("ida_segment.SEGPERM_READ"); }
+        if block.perms.contains('w') { perms.push("ida_segment.SEGPERM_WRITE"); }
+        if block.perms.contains('x') { perms.push("ida_segment.SEGPERM_EXEC"); }
+        let class = match (block.perms.contains('x'), offset.is_some()) {
+            (true, _)      => "CODE",
+            (false, true)  => "DATA",
+            (false, false) => "BSS",
+        };
+        let offset = offset.map(|o| format!("{:#X}", o)).unwrap_or("None".to_string());
+        py += &format!("    (\"{}\", {:#010X}, {:#X}, {}, \"{}\", {}),\n", escape_python(&block.name), block.addr, block.len, perms.join(" | "), class, offset);
+    }
+    py += "]\n\n";
+
+    py += "ENTRIES = [\n";
+    for (addr, name) in entries {
+        py += &format!("    ({:#010X}, \"{}\"),\n", addr, escape_python(name));
+    }
+    py += "]\n\n";
+
+    py += "# (address, name, size, is_function)\n";
+    py += "SYMBOLS = [\n";
+    for symbol in symbols.map(|s| s.symbols.as_slice()).unwrap_or(&[]) {
+        py += &format!("    ({:#010X}, \"{}\", {:#X}, {}),\n", symbol.addr, escape_python(&symbol.name), symbol.size,
+                       if symbol.kind == SymbolKind::Function { "True" } else { "False" });
+    }
+    py += "]\n\n";
+
+    py += "ida_idp.set_processor_type(\"ppc\", ida_idp.SETPROC_USER)\n";
+    py += "ida_ida.inf_set_be(True)\n";
+    py += "with open(BYTES_FILE, \"rb\") as f:\n";
+    py += "    data = f.read()\n\n";
+    py += "for name, start, length, perm, sclass, offset in SEGMENTS:\n";
+    py += "    seg = ida_segment.segment_t()\n";
+    py += "    seg.start_ea = start\n";
+    py += "    seg.end_ea = start + length\n";
+    py += "    seg.bitness = 1\n";
+    py += "    seg.perm = perm\n";
+    py += "    ida_segment.add_segm_ex(seg, name, sclass, ida_segment.ADDSEG_NOSREG)\n";
+    py += "    if offset is not None:\n";
+    py += "        ida_bytes.put_bytes(start, data[offset:offset + length])\n\n";
+    py += "for addr, name in ENTRIES:\n";
+    py += "    ida_entry.add_entry(addr, addr, name, True)\n\n";
+    py += "for addr, name, size, is_function in SYMBOLS:\n";
+    py += "    ida_name.set_name(addr, name, ida_name.SN_NOWARN | ida_name.SN_NOCHECK)\n";
+    py += "    if is_function:\n";
+    py += "        ida_funcs.add_func(addr, addr + size if size else ida_idp.BADADDR)\n";
+    return py;
+}
+
+// <out_base>.xml, <out_base>.py and the <out_base>.bytes both of them load from
+pub fn write_files(out_base: &str, program: &str, image: &BSImageRef, symbols: Option<&SymbolMap>) -> std::io::Result<()> {
+    let blocks = memory_blocks(image);
+
+    let mut bytes = Vec::new();
+    let mut offsets = Vec::new();
+    for block in &blocks {
+        offsets.push(block.data.map(|data| {
+            bytes.extend_from_slice(data);
+            bytes.len() - data.len()
+        }));
+    }
+
+    // Entry points are named after the symbol there if there is one
+    let entry_name = |addr: u32, default: &str| -> String {
+        return symbols.and_then(|s| s.find(addr)).filter(|s| s.addr == addr).map(|s| s.name.clone()).unwrap_or(default.to_string());
+    };
+    let entries = vec![(image.bs1_entry, entry_name(image.bs1_entry, "bs1_entry")),
+                       (image.bs2_entry, entry_name(image.bs2_entry, "bs2_entry"))];
+
+    let bytes_file = format!("{}.bytes", out_base);
+    let bytes_name = std::path::Path::new(&bytes_file).file_name().unwrap().to_string_lossy().to_string();
+    fs::write(&bytes_file, &bytes)?;
+    fs::write(format!("{}.xml", out_base), ghidra_xml(program, &bytes_name, &blocks, &offsets, &entries, symbols))?;
+    fs::write(format!("{}.py", out_base), ida_script(&bytes_name, &blocks, &offsets, &entries, symbols))?;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gcipl::{self, tests::synthetic_bs2};
+    use crate::profile;
+
+    #[test]
+    fn bs2_leftovers_get_blocks() {
+        // Sections cover the first 0x600 bytes
+        let mut bs2_data = synthetic_bs2();
+        bs2_data.extend_from_slice(&[0x4E; 0x40]);
+        let image = BSImageRef::from_bs2(gcipl::BS2_ADDR, &bs2_data, profile::gc()).unwrap();
+
+        let blocks = memory_blocks(&image);
+        let leftover = blocks.iter().find(|b| b.name == "BS2").unwrap();
+        assert_eq!((leftover.addr, leftover.len), (gcipl::BS2_ADDR + 0x600, 0x40));
+        assert_eq!(leftover.data, Some(&bs2_data[0x600..]));
+        assert!(!blocks.iter().any(|b| b.name == "BS2.2"));
+    }
+}
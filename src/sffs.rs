@@ -0,0 +1,371 @@
+use std::io::{Error, ErrorKind};
+
+use crate::title::{self, Tmd, CONTENT_SHARED, KEYS_BIN_COMMON_KEY, KEYS_BIN_SIZE};
+
+pub const PAGE_SIZE : usize = 0x800;
+pub const SPARE_SIZE : usize = 0x40;
+pub const PAGES_PER_CLUSTER : usize = 8;
+pub const CLUSTER_SIZE : usize = PAGE_SIZE * PAGES_PER_CLUSTER;
+pub const CLUSTER_COUNT : usize = 0x8000;
+
+// The last 256 clusters hold 16 copies of the superblock, the newest one wins
+pub const SUPERBLOCK_START : usize = 0x7F00;
+pub const SUPERBLOCK_CLUSTERS : usize = 16;
+pub const SUPERBLOCK_COUNT : usize = 16;
+
+const FAT_OFFSET : usize = 0x0C;
+const FST_OFFSET : usize = FAT_OFFSET + CLUSTER_COUNT * 2;
+const FST_ENTRY_SIZE : usize = 0x20;
+const FST_COUNT : usize = 0x17FF;
+
+// FAT values that end a chain
+const FAT_LAST : u16 = 0xFFFB;
+
+pub const KEYS_BIN_NAND_KEY : usize = 0x158;
+
+const MODE_FILE : u8 = 1;
+const MODE_DIR : u8 = 2;
+const NO_ENTRY : u16 = 0xFFFF;
+
+pub struct FstEntry {
+    pub name: String,
+    pub mode: u8,
+    pub attr: u8,
+    // First child of a directory, first cluster of a file
+    pub sub: u16,
+    pub sib: u16,
+    pub size: u32,
+    pub uid: u32,
+    pub gid: u16,
+}
+
+// Console NAND dump (BootMii nand.bin), with or without the spare/ECC bytes of every page.
+pub struct NandDump<'a> {
+    pub data: &'a [u8],
+    pub has_ecc: bool,
+    pub superblock_version: u32,
+    pub fat: Vec<u16>,
+    pub fst: Vec<FstEntry>,
+    pub nand_key: [u8; 16],
+    pub common_key: [u8; 16],
+}
+
+fn invalid_nand(message: &str) -> Error {
+    return Error::new(ErrorKind::InvalidData, format!("Invalid NAND dump: {}", message));
+}
+
+fn read_u16(buffer: &[u8], offset: usize) -> u16 {
+    return u16::from_be_bytes([buffer[offset], buffer[offset + 1]]);
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    return u32::from_be_bytes([buffer[offset], buffer[offset + 1], buffer[offset + 2], buffer[offset + 3]]);
+}
+
+fn key_at(keys: &[u8], offset: usize) -> [u8; 16] {
+    let mut key = [0u8; 16];
+    key.copy_from_slice(&keys[offset..offset + 16]);
+    return key;
+}
+
+// Whether the dump has spare data, and the keys.bin BootMii appends to newer dumps
+pub fn dump_layout(file_data: &[u8]) -> std::io::Result<(bool, Option<&[u8]>)> {
+    let ecc_size = CLUSTER_COUNT * PAGES_PER_CLUSTER * (PAGE_SIZE + SPARE_SIZE);
+    let plain_size = CLUSTER_COUNT * CLUSTER_SIZE;
+
+    return match file_data.len() {
+        len if len == ecc_size                  => Ok((true, None)),
+        len if len == ecc_size + KEYS_BIN_SIZE   => Ok((true, Some(&file_data[ecc_size..]))),
+        len if len == plain_size                => Ok((false, None)),
+        len if len == plain_size + KEYS_BIN_SIZE => Ok((false, Some(&file_data[plain_size..]))),
+        len => Err(invalid_nand(&format!("size {:#X} is not a 512 MiB NAND with or without ECC", len))),
+    };
+}
+
+impl<'a> NandDump<'a> {
+    // keys is a BootMii keys.bin, if None the one appended to the dump is used.
+    pub fn parse(file_data: &'a [u8], keys: Option<&[u8]>) -> std::io::Result<NandDump<'a>> {
+        let (has_ecc, appended_keys) = dump_layout(file_data)?;
+        let keys = keys.or(appended_keys)
+                       .ok_or_else(|| Error::new(ErrorKind::NotFound, "This NAND dump has no keys appended, pass a keys.bin with -k"))?;
+        if keys.len() != KEYS_BIN_SIZE {
+            return Err(invalid_nand(&format!("keys file is {:#X} bytes, expected {:#X}", keys.len(), KEYS_BIN_SIZE)));
+        }
+
+        let mut dump = NandDump {
+            data: file_data,
+            has_ecc,
+            superblock_version: 0,
+            fat: Vec::new(),
+            fst: Vec::new(),
+            nand_key: key_at(keys, KEYS_BIN_NAND_KEY),
+            common_key: key_at(keys, KEYS_BIN_COMMON_KEY),
+        };
+
+        // Newest superblock
+        let mut superblock = None;
+        for i in 0..SUPERBLOCK_COUNT {
+            let first = SUPERBLOCK_START + i * SUPERBLOCK_CLUSTERS;
+            let head = dump.cluster(first);
+            if &head[0..4] == b"SFFS" && (superblock.is_none() || read_u32(&head, 4) > dump.superblock_version) {
+                dump.superblock_version = read_u32(&head, 4);
+                superblock = Some(first);
+            }
+        }
+        let first = superblock.ok_or_else(|| invalid_nand("no SFFS superblock"))?;
+        let superblock : Vec<u8> = (first..first + SUPERBLOCK_CLUSTERS).flat_map(|c| dump.cluster(c)).collect();
+
+        dump.fat = (0..CLUSTER_COUNT).map(|i| read_u16(&superblock, FAT_OFFSET + i * 2)).collect();
+        dump.fst = (0..FST_COUNT).map(|i| {
+            let entry = &superblock[FST_OFFSET + i * FST_ENTRY_SIZE..FST_OFFSET + (i + 1) * FST_ENTRY_SIZE];
+            let name_end = entry[..12].iter().position(|&c| c == 0).unwrap_or(12);
+            FstEntry {
+                name:   String::from_utf8_lossy(&entry[..name_end]).to_string(),
+                mode:   entry[0x0C],
+                attr:   entry[0x0D],
+                sub:    read_u16(entry, 0x0E),
+                sib:    read_u16(entry, 0x10),
+                size:   read_u32(entry, 0x12),
+                uid:    read_u32(entry, 0x16),
+                gid:    read_u16(entry, 0x1A),
+            }
+        }).collect();
+
+        return Ok(dump);
+    }
+
+    // Raw cluster with the spare bytes stripped
+    pub fn cluster(&self, index: usize) -> Vec<u8> {
+        if !self.has_ecc {
+            return self.data[index * CLUSTER_SIZE..(index + 1) * CLUSTER_SIZE].to_vec();
+        }
+
+        let raw_page = PAGE_SIZE + SPARE_SIZE;
+        let start = index * PAGES_PER_CLUSTER * raw_page;
+        return (0..PAGES_PER_CLUSTER).flat_map(|page| &self.data[start + page * raw_page..start + page * raw_page + PAGE_SIZE])
+                                     .copied()
+                                     .collect();
+    }
+
+    // File clusters are encrypted with the console's NAND key
+    pub fn decrypted_cluster(&self, index: usize) -> std::io::Result<Vec<u8>> {
+        let mut data = self.cluster(index);
+        title::aes_cbc_decrypt(&self.nand_key, &[0u8; 16], &mut data)?;
+        return Ok(data);
+    }
+
+    fn children(&self, dir: usize) -> Vec<usize> {
+        let mut children = Vec::new();
+        let mut child = self.fst[dir].sub;
+        while child != NO_ENTRY && (child as usize) < self.fst.len() && children.len() < self.fst.len() {
+            children.push(child as usize);
+            child = self.fst[child as usize].sib;
+        }
+        return children;
+    }
+
+    pub fn lookup(&self, path: &str) -> Option<usize> {
+        let mut entry = 0;
+        for name in path.split('/').filter(|n| !n.is_empty()) {
+            if self.fst[entry].mode & 3 != MODE_DIR {
+                return None;
+            }
+            entry = self.children(entry).into_iter().find(|&c| self.fst[c].name == name)?;
+        }
+        return Some(entry);
+    }
+
+    // Names in a directory
+    pub fn list_dir(&self, path: &str) -> std::io::Result<Vec<String>> {
+        let dir = self.lookup(path).filter(|&d| self.fst[d].mode & 3 == MODE_DIR)
+                      .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("NAND has no directory {}", path)))?;
+        return Ok(self.children(dir).into_iter().map(|c| self.fst[c].name.clone()).collect());
+    }
+
+    pub fn read_file(&self, path: &str) -> std::io::Result<Vec<u8>> {
+        let entry = self.lookup(path).map(|e| &self.fst[e]).filter(|e| e.mode & 3 == MODE_FILE)
+                        .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("NAND has no file {}", path)))?;
+
+        let mut data = Vec::with_capacity(entry.size as usize);
+        let mut cluster = entry.sub;
+        while data.len() < entry.size as usize {
+            if cluster as usize >= CLUSTER_COUNT || cluster >= FAT_LAST {
+                return Err(invalid_nand(&format!("cluster chain of {} ends early", path)));
+            }
+            data.extend_from_slice(&self.decrypted_cluster(cluster as usize)?);
+            cluster = self.fat[cluster as usize];
+        }
+        data.truncate(entry.size as usize);
+        return Ok(data);
+    }
+
+    // Every installed title with its TMD, if it has one
+    pub fn titles(&self) -> std::io::Result<Vec<(u64, Option<Tmd>)>> {
+        let mut titles = Vec::new();
+        for upper in self.list_dir("/title")? {
+            for lower in self.list_dir(&format!("/title/{}", upper))? {
+                let (Ok(hi), Ok(lo)) = (u32::from_str_radix(&upper, 16), u32::from_str_radix(&lower, 16)) else {
+                    continue;
+                };
+                let tmd = self.read_file(&format!("/title/{}/{}/content/title.tmd", upper, lower)).ok()
+                              .and_then(|tmd_data| title::parse_tmd(&tmd_data).ok());
+                titles.push(((hi as u64) << 32 | lo as u64, tmd));
+            }
+        }
+        return Ok(titles);
+    }
+
+    // The content a title boots. Installed contents are normally stored decrypted,
+    // if the TMD hash doesn't match it is decrypted with the title key from the ticket.
+    pub fn boot_content(&self, title_id: u64) -> std::io::Result<(Tmd, Vec<u8>)> {
+        let title_dir = format!("/title/{:08x}/{:08x}", title_id >> 32, title_id & 0xFFFFFFFF);
+        let tmd = title::parse_tmd(&self.read_file(&format!("{}/content/title.tmd", title_dir))?)?;
+        let content = tmd.boot_content()?.clone();
+
+        let content_path = if content.kind & CONTENT_SHARED != 0 {
+            let name = title::find_shared_content(&self.read_file("/shared1/content.map")?, &content.sha1)
+                             .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("Shared content {:08x} is not in content.map", content.content_id)))?;
+            format!("/shared1/{}.app", name)
+        }
+        else {
+            format!("{}/content/{:08x}.app", title_dir, content.content_id)
+        };
+        let content_data = self.read_file(&content_path)?;
+
+        if title::verify_content(&content, &content_data).is_ok() {
+            return Ok((tmd, content_data));
+        }
+
+        let ticket = title::parse_ticket(&self.read_file(&format!("/ticket/{:08x}/{:08x}.tik", title_id >> 32, title_id & 0xFFFFFFFF))?)?;
+        let title_key = title::decrypt_title_key(&ticket, &self.common_key)?;
+        let content_data = title::decrypt_content(&content, &content_data, &title_key)?;
+        return Ok((tmd, content_data));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::title::tests::{aes_cbc_encrypt, encrypt_content, synthetic_ticket, synthetic_tmd, COMMON_KEY};
+
+    const NAND_KEY : [u8; 16] = [0x33; 16];
+
+    struct SyntheticNand {
+        data: Vec<u8>,
+        superblock: Vec<u8>,
+        entries: usize,
+    }
+
+    impl SyntheticNand {
+        // Plain 512 MiB dump with keys.bin appended (zeroed pages are never touched)
+        fn new() -> SyntheticNand {
+            let mut data = vec![0u8; CLUSTER_COUNT * CLUSTER_SIZE + KEYS_BIN_SIZE];
+            let keys = CLUSTER_COUNT * CLUSTER_SIZE;
+            data[keys + KEYS_BIN_COMMON_KEY..keys + KEYS_BIN_COMMON_KEY + 16].copy_from_slice(&COMMON_KEY);
+            data[keys + KEYS_BIN_NAND_KEY..keys + KEYS_BIN_NAND_KEY + 16].copy_from_slice(&NAND_KEY);
+
+            let mut superblock = vec![0u8; SUPERBLOCK_CLUSTERS * CLUSTER_SIZE];
+            superblock[0..4].copy_from_slice(b"SFFS");
+            return SyntheticNand { data, superblock, entries: 0 };
+        }
+
+        fn add_entry(&mut self, name: &str, mode: u8, sub: u16, sib: u16, size: u32) {
+            let off = FST_OFFSET + self.entries * FST_ENTRY_SIZE;
+            let entry = &mut self.superblock[off..off + FST_ENTRY_SIZE];
+            entry[..name.len()].copy_from_slice(name.as_bytes());
+            entry[0x0C] = mode;
+            entry[0x0E..0x10].copy_from_slice(&sub.to_be_bytes());
+            entry[0x10..0x12].copy_from_slice(&sib.to_be_bytes());
+            entry[0x12..0x16].copy_from_slice(&size.to_be_bytes());
+            self.entries += 1;
+        }
+
+        fn add_dir(&mut self, name: &str, sub: u16, sib: u16) {
+            self.add_entry(name, MODE_DIR, sub, sib, 0);
+        }
+
+        // Stores a file over the given clusters, chained in that order
+        fn add_file(&mut self, name: &str, sib: u16, clusters: &[u16], file_data: &[u8]) {
+            for (i, (&cluster, chunk)) in clusters.iter().zip(file_data.chunks(CLUSTER_SIZE)).enumerate() {
+                let next = clusters.get(i + 1).copied().unwrap_or(FAT_LAST);
+                self.superblock[FAT_OFFSET + cluster as usize * 2..FAT_OFFSET + cluster as usize * 2 + 2].copy_from_slice(&next.to_be_bytes());
+
+                let mut encrypted = chunk.to_vec();
+                encrypted.resize(CLUSTER_SIZE, 0);
+                aes_cbc_encrypt(&NAND_KEY, &[0u8; 16], &mut encrypted);
+                self.data[cluster as usize * CLUSTER_SIZE..(cluster as usize + 1) * CLUSTER_SIZE].copy_from_slice(&encrypted);
+            }
+            self.add_entry(name, MODE_FILE, clusters[0], sib, file_data.len() as u32);
+        }
+
+        fn write_superblock(&mut self, slot: usize, version: u32) {
+            self.superblock[4..8].copy_from_slice(&version.to_be_bytes());
+            let start = (SUPERBLOCK_START + slot * SUPERBLOCK_CLUSTERS) * CLUSTER_SIZE;
+            self.data[start..start + self.superblock.len()].copy_from_slice(&self.superblock);
+        }
+    }
+
+    // /title/00000001/00000002/content with the TMD and an encrypted boot content spread over two clusters,
+    // and the ticket under /ticket/00000001
+    fn system_menu_nand(content_data: &[u8]) -> SyntheticNand {
+        let title_id = title::SYSTEM_MENU_TITLE_ID;
+        let tmd_data = synthetic_tmd(title_id, &[(0x97, 1, content_data)]);
+
+        let mut nand = SyntheticNand::new();
+        // An older superblock that must not be used
+        nand.add_dir("", NO_ENTRY, NO_ENTRY);
+        nand.write_superblock(0, 1);
+        nand.entries = 0;
+
+        nand.add_dir("", 1, NO_ENTRY);
+        nand.add_dir("title", 3, 2);
+        nand.add_dir("ticket", 8, NO_ENTRY);
+        nand.add_dir("00000001", 4, NO_ENTRY);
+        nand.add_dir("00000002", 5, NO_ENTRY);
+        nand.add_dir("content", 6, NO_ENTRY);
+        nand.add_file("title.tmd", 7, &[0x10], &tmd_data);
+        nand.add_file("00000097.app", NO_ENTRY, &[0x20, 0x12], &encrypt_content(1, content_data));
+        nand.add_dir("00000001", 9, NO_ENTRY);
+        nand.add_file("00000002.tik", NO_ENTRY, &[0x30], &synthetic_ticket(title_id, 0));
+        nand.write_superblock(3, 5);
+        return nand;
+    }
+
+    #[test]
+    fn walks_fst_and_fat() {
+        let content_data: Vec<u8> = (0..CLUSTER_SIZE + 0x100).map(|i| (i * 13) as u8).collect();
+        let nand = system_menu_nand(&content_data);
+        let dump = NandDump::parse(&nand.data, None).unwrap();
+
+        assert!(!dump.has_ecc);
+        assert_eq!(dump.superblock_version, 5);
+        assert_eq!(dump.common_key, COMMON_KEY);
+        assert_eq!(dump.list_dir("/title/00000001").unwrap(), ["00000002"]);
+        assert_eq!(dump.list_dir("/").unwrap(), ["title", "ticket"]);
+        assert!(dump.lookup("/title/00000001/00000003").is_none());
+
+        // Two clusters, the second one before the first on the NAND
+        let stored = dump.read_file("/title/00000001/00000002/content/00000097.app").unwrap();
+        assert_eq!(stored, encrypt_content(1, &content_data));
+
+        let titles = dump.titles().unwrap();
+        assert_eq!(titles.len(), 1);
+        assert_eq!(titles[0].0, title::SYSTEM_MENU_TITLE_ID);
+
+        // Stored encrypted, so it goes through the ticket
+        let (tmd, boot) = dump.boot_content(title::SYSTEM_MENU_TITLE_ID).unwrap();
+        assert_eq!(tmd.boot_index, 1);
+        assert_eq!(boot, content_data);
+    }
+
+    #[test]
+    fn broken_cluster_chain() {
+        let mut nand = system_menu_nand(&[0x60; CLUSTER_SIZE + 0x100]);
+        // End the chain of the boot content after its first cluster
+        nand.superblock[FAT_OFFSET + 0x20 * 2..FAT_OFFSET + 0x20 * 2 + 2].copy_from_slice(&FAT_LAST.to_be_bytes());
+        nand.write_superblock(3, 5);
+
+        let dump = NandDump::parse(&nand.data, None).unwrap();
+        assert!(dump.read_file("/title/00000001/00000002/content/00000097.app").is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+pub struct SplitRange {
+    pub section: String,
+    pub start: u32,
+    pub end: u32,
+}
+
+// One translation unit and the parts of each section it covers
+pub struct Unit {
+    pub name: String,
+    pub ranges: Vec<SplitRange>,
+}
+
+// decomp-toolkit splits.txt:
+//   Sections:
+//   	.text       type:code align:32
+//
+//   OS/OS.c:
+//   	.text       start:0x81330440 end:0x81330640
+pub struct Splits {
+    // Names and attributes as listed under "Sections:"
+    pub sections: Vec<(String, String)>,
+    pub units: Vec<Unit>,
+}
+
+impl Splits {
+    pub fn unit_of(&self, addr: u32) -> Option<(&Unit, &SplitRange)> {
+        for unit in &self.units {
+            if let Some(range) = unit.ranges.iter().find(|r| addr >= r.start && addr < r.end) {
+                return Some((unit, range));
+            }
+        }
+        return None;
+    }
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    return u32::from_str_radix(text.trim_start_matches("0x"), 16).ok();
+}
+
+pub fn parse(text: &str) -> std::io::Result<Splits> {
+    let mut splits = Splits { sections: Vec::new(), units: Vec::new() };
+    let mut in_sections = false;
+
+    for (number, line) in text.lines().enumerate() {
+        let content = line.split("//").next().unwrap_or("");
+        if content.trim().is_empty() {
+            continue;
+        }
+
+        // Units and the "Sections:" block start at the beginning of the line, their contents are indented
+        if !content.starts_with([' ', '\t']) {
+            let name = content.trim_end();
+            let Some(name) = name.strip_suffix(':').or_else(|| name.split_once(": ").map(|(n, _)| n)) else {
+                return Err(Error::new(ErrorKind::InvalidData, format!("splits line {}: expected a unit name", number + 1)));
+            };
+            in_sections = name == "Sections";
+            if !in_sections {
+                splits.units.push(Unit { name: name.to_string(), ranges: Vec::new() });
+            }
+            continue;
+        }
+
+        let mut words = content.split_whitespace();
+        let section = words.next().unwrap_or("").to_string();
+        if in_sections {
+            splits.sections.push((section, words.collect::<Vec<&str>>().join(" ")));
+            continue;
+        }
+
+        let (mut start, mut end) = (None, None);
+        for attribute in words {
+            match attribute.split_once(':') {
+                Some(("start", value)) => start = parse_hex(value),
+                Some(("end", value))   => end = parse_hex(value),
+                _                      => {},
+            }
+        }
+        let (Some(unit), Some(start), Some(end)) = (splits.units.last_mut(), start, end) else {
+            return Err(Error::new(ErrorKind::InvalidData, format!("splits line {}: expected a section with start and end", number + 1)));
+        };
+        unit.ranges.push(SplitRange { section, start, end });
+    }
+    return Ok(splits);
+}
+
+pub fn read_file(file_name: &str) -> std::io::Result<Splits> {
+    let text = fs::read_to_string(file_name).map_err(|e| Error::new(e.kind(), format!("{}: {}", file_name, e)))?;
+    return parse(&text).map_err(|e| Error::new(e.kind(), format!("{}: {}", file_name, e)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // As decomp-toolkit writes it, with unit attributes, renames and comments
+    const SPLITS_TXT : &str = "Sections:
+	.init       type:code align:4
+	extab       type:rodata align:32
+	.text       type:code align:32
+	.ctors      type:rodata align:32
+	.sbss       type:bss align:32
+
+Runtime.PPCEABI.H/__init_cpp_exceptions.cpp: comment:0
+	.text       start:0x81330440 end:0x813304B8
+	.ctors      start:0x81380000 end:0x81380004 rename:.ctors$10
+
+// Hand-split, see the notes
+OS/OS.c:
+	.init       start:0x81300000 end:0x81300100 // just the tables
+	.text       start:0x813304B8 end:0x81330640
+	.sbss       start:0x813A0000 end:0x813A0010
+";
+
+    #[test]
+    fn parses_dtk_splits() {
+        let splits = parse(SPLITS_TXT).unwrap();
+        let sections: Vec<(&str, &str)> = splits.sections.iter().map(|(n, a)| (n.as_str(), a.as_str())).collect();
+        assert_eq!(sections, [(".init",  "type:code align:4"),
+                              ("extab",  "type:rodata align:32"),
+                              (".text",  "type:code align:32"),
+                              (".ctors", "type:rodata align:32"),
+                              (".sbss",  "type:bss align:32")]);
+
+        let names: Vec<&str> = splits.units.iter().map(|u| u.name.as_str()).collect();
+        assert_eq!(names, ["Runtime.PPCEABI.H/__init_cpp_exceptions.cpp", "OS/OS.c"]);
+        let ranges: Vec<(&str, u32, u32)> = splits.units[1].ranges.iter().map(|r| (r.section.as_str(), r.start, r.end)).collect();
+        assert_eq!(ranges, [(".init", 0x81300000, 0x81300100), (".text", 0x813304B8, 0x81330640), (".sbss", 0x813A0000, 0x813A0010)]);
+        assert_eq!(splits.units[0].ranges[1].section, ".ctors");
+
+        // Ends are exclusive
+        let unit_name = |addr: u32| splits.unit_of(addr).map(|(u, r)| format!("{} {}", u.name, r.section));
+        assert_eq!(unit_name(0x813304B4).as_deref(), Some("Runtime.PPCEABI.H/__init_cpp_exceptions.cpp .text"));
+        assert_eq!(unit_name(0x813304B8).as_deref(), Some("OS/OS.c .text"));
+        assert_eq!(unit_name(0x81330640), None);
+    }
+
+    #[test]
+    fn rejects_broken_splits() {
+        let line_of = |text: &str| parse(text).err().unwrap().to_string();
+        assert_eq!(line_of("OS/OS.c:\n\t.text       start:0x81330440\n"), "splits line 2: expected a section with start and end");
+        assert_eq!(line_of("\t.text       start:0x81330440 end:0x81330640\n"), "splits line 1: expected a section with start and end");
+        assert_eq!(line_of("Sections:\n\t.text type:code\nOS/OS.c\n"), "splits line 3: expected a unit name");
+    }
+}
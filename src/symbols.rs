@@ -0,0 +1,298 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+use crate::elf::{self, SHN_LORESERVE, SHN_UNDEF, STB_LOCAL, STT_FUNC, STT_NOTYPE, STT_OBJECT};
+use crate::mwmap::MapSection;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SymbolKind {
+    Function,
+    Object,
+    Unknown,
+}
+
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub addr: u32,
+    // 0 if the map doesn't say
+    pub size: u32,
+    pub kind: SymbolKind,
+    pub section: Option<String>,
+    pub local: bool,
+}
+
+// Symbols sorted by address, for naming addresses in reports.
+pub struct SymbolMap {
+    pub symbols: Vec<Symbol>,
+}
+
+impl SymbolMap {
+    pub fn new(mut symbols: Vec<Symbol>) -> SymbolMap {
+        symbols.sort_by_key(|s| s.addr);
+        return SymbolMap { symbols };
+    }
+
+    // The symbol containing addr, or the closest one before it if sizes are unknown
+    pub fn find(&self, addr: u32) -> Option<&Symbol> {
+        let before = &self.symbols[..self.symbols.partition_point(|s| s.addr <= addr)];
+        if let Some(symbol) = before.iter().rev().find(|s| s.size != 0 && addr - s.addr < s.size) {
+            return Some(symbol);
+        }
+        return before.last().filter(|s| s.size == 0);
+    }
+
+    // "OSInit+0x14"
+    pub fn describe(&self, addr: u32) -> Option<String> {
+        let symbol = self.find(addr)?;
+        if symbol.addr == addr {
+            return Some(symbol.name.clone());
+        }
+        return Some(format!("{}+{:#X}", symbol.name, addr - symbol.addr));
+    }
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    return u32::from_str_radix(text.trim_start_matches("0x"), 16).ok();
+}
+
+// Code sections hold functions, everything else objects
+pub fn section_kind(section: &str) -> SymbolKind {
+    return if section == ".init" || section == ".text" { SymbolKind::Function } else { SymbolKind::Object };
+}
+
+// ".text" out of "  .text section layout"
+pub fn layout_section(line: &str) -> Option<&str> {
+    return Some(line.trim().strip_suffix("section layout")?.trim());
+}
+
+// Plain text symbol map, one symbol per line:
+//   80003100 00003c 80003100  4 __start    (Dolphin/linker map: start, size, address, alignment, name)
+//   80003100 00003c __start
+//   80003100 __start
+// Anything else (headers, comments, UNUSED lines) is skipped.
+pub fn parse_text_map(text: &str) -> SymbolMap {
+    let mut symbols = Vec::new();
+    let mut kind = SymbolKind::Unknown;
+    let mut section: Option<String> = None;
+
+    for line in text.lines() {
+        if let Some(name) = layout_section(line) {
+            kind = section_kind(name);
+            section = Some(name.to_string());
+            continue;
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() < 2 || words[0].len() != 8 {
+            continue;
+        }
+        let Some(start) = parse_hex(words[0]) else {
+            continue;
+        };
+
+        let size = parse_hex(words[1]);
+        let (name, addr, size) = match (size, words.get(2).and_then(|w| parse_hex(w)), words.get(3).map(|w| w.parse::<u32>())) {
+            (Some(size), Some(addr), Some(Ok(_))) if words.len() >= 5 => (words[4], addr, size),
+            (Some(size), _, _) if words.len() == 3                     => (words[2], start, size),
+            _                                                          => (words[1], start, 0),
+        };
+        symbols.push(Symbol { name: name.to_string(), addr, size, kind, section: section.clone(), local: false });
+    }
+    return SymbolMap::new(symbols);
+}
+
+// decomp-toolkit symbols.txt:
+//   OSInit = .text:0x81330440; // type:function size:0x200 scope:global
+pub fn parse_dtk_symbols(text: &str) -> SymbolMap {
+    let mut symbols = Vec::new();
+    for line in text.lines() {
+        let (definition, attributes) = line.split_once("//").unwrap_or((line, ""));
+        let Some((name, location)) = definition.trim().trim_end_matches(';').split_once('=') else {
+            continue;
+        };
+        let Some(addr) = location.rsplit(':').next().and_then(|a| parse_hex(a.trim())) else {
+            continue;
+        };
+
+        let section = location.rsplit_once(':').map(|(section, _)| section.trim().to_string());
+        let mut symbol = Symbol { name: name.trim().to_string(), addr, size: 0, kind: SymbolKind::Unknown, section, local: false };
+        for attribute in attributes.split_whitespace() {
+            match attribute.split_once(':') {
+                Some(("type", "function")) => symbol.kind = SymbolKind::Function,
+                Some(("type", "object"))   => symbol.kind = SymbolKind::Object,
+                Some(("size", size))       => symbol.size = parse_hex(size).unwrap_or(0),
+                Some(("scope", "local"))   => symbol.local = true,
+                _                          => {},
+            }
+        }
+        symbols.push(symbol);
+    }
+    return SymbolMap::new(symbols);
+}
+
+// One symbols.txt line, the symbol needs a section
+pub fn dtk_line(symbol: &Symbol) -> String {
+    let kind = match symbol.kind {
+        SymbolKind::Function => "function",
+        SymbolKind::Object   => "object",
+        SymbolKind::Unknown  => "label",
+    };
+    let size = if symbol.size != 0 { format!(" size:{:#X}", symbol.size) } else { String::new() };
+    return format!("{} = {}:{:#010X}; // type:{}{} scope:{}",
+                   symbol.name, symbol.section.as_deref().unwrap_or(""), symbol.addr, kind, size,
+                   if symbol.local { "local" } else { "global" });
+}
+
+// Loaded sections and the named symbols in them from an ELF's .symtab.
+// Relocatable objects get their section address added, which is normally 0.
+pub fn read_elf(file_data: &[u8]) -> std::io::Result<(Vec<MapSection>, Vec<Symbol>)> {
+    let header = elf::read_elf32_hdr(file_data)?;
+    let sections = elf::read_elf32_sec_hdrs(file_data, &header)?;
+    let relocatable = header.e_type == 1;
+
+    let mut map_sections: Vec<MapSection> = sections.iter()
+        .filter(|s| s.sh_flags & 2 != 0 && s.sh_size != 0)
+        .map(|s| MapSection { name: s.name.clone(), addr: s.sh_addr, size: s.sh_size })
+        .collect();
+    map_sections.sort_by_key(|s| s.addr);
+
+    let mut symbols = Vec::new();
+    for symbol in elf::read_elf32_symbols(file_data, &sections)? {
+        if symbol.name.is_empty() || symbol.st_shndx == SHN_UNDEF || symbol.st_shndx >= SHN_LORESERVE {
+            continue;
+        }
+        let Some(section) = sections.get(symbol.st_shndx as usize).filter(|s| s.sh_flags & 2 != 0) else {
+            continue;
+        };
+        let kind = match symbol.kind() {
+            STT_FUNC    => SymbolKind::Function,
+            STT_OBJECT  => SymbolKind::Object,
+            STT_NOTYPE  => SymbolKind::Unknown,
+            _           => continue,
+        };
+        symbols.push(Symbol {
+            name:       symbol.name.clone(),
+            addr:       if relocatable { section.sh_addr + symbol.st_value } else { symbol.st_value },
+            size:       symbol.st_size,
+            kind,
+            section:    Some(section.name.clone()),
+            local:      symbol.bind() == STB_LOCAL,
+        });
+    }
+    symbols.sort_by_key(|s| s.addr);
+    return Ok((map_sections, symbols));
+}
+
+// Dolphin symbol map: a section layout per section, lines as Dolphin writes them
+// (address, size, address, alignment, name), then the memory map like mwld has it.
+// Labels without a size are left out, Dolphin would make empty functions of them.
+// Returns the map and how many symbols went in.
+pub fn dolphin_map(sections: &[MapSection], symbols: &[Symbol]) -> (String, usize) {
+    let mut text = String::new();
+    let mut count = 0;
+    for section in sections {
+        let inside: Vec<&Symbol> = symbols.iter()
+            .filter(|s| s.section.as_deref() == Some(section.name.as_str()) && (s.size != 0 || s.kind != SymbolKind::Unknown))
+            .collect();
+        if inside.is_empty() {
+            continue;
+        }
+        text += &format!("{} section layout\n", section.name);
+        count += inside.len();
+        for symbol in inside {
+            text += &format!("{:08x} {:08x} {:08x} 0 {}\n", symbol.addr, symbol.size, symbol.addr, symbol.name);
+        }
+        text += "\n";
+    }
+
+    text += "Memory map:\n";
+    for section in sections {
+        text += &format!("{:>19}  {:08x} {:08x}\n", section.name, section.addr, section.size);
+    }
+    return (text, count);
+}
+
+fn is_dtk_symbols(text: &str) -> bool {
+    return text.lines().any(|line| line.contains(" = ") && line.contains(":0x") && line.contains(';'));
+}
+
+// A decomp-toolkit symbols.txt or a plain text/Dolphin map, whichever it looks like.
+pub fn read_file(file_name: &str) -> std::io::Result<SymbolMap> {
+    let text = fs::read_to_string(file_name).map_err(|e| Error::new(e.kind(), format!("{}: {}", file_name, e)))?;
+    let map = if is_dtk_symbols(&text) { parse_dtk_symbols(&text) } else { parse_text_map(&text) };
+    if map.symbols.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, format!("{} has no symbols", file_name)));
+    }
+    return Ok(map);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // As decomp-toolkit writes it, plus a comment line
+    const SYMBOLS_TXT : &str = "// From the 4.3 map
+__check_pad3 = .init:0x81300000; // type:function size:0x40 scope:local
+__start = .init:0x81300040; // type:function size:0xC0 scope:weak
+lbl_81300100 = .text:0x81300100; // type:label scope:local
+OSInit = .text:0x81330440; // type:function size:0x200 scope:global align:4
+@123 = .data:0x81380000; // type:object size:0x10 scope:local align:4 data:string
+__OSCurrHeap = .sbss:0x813A0000; // type:object size:0x4 scope:global data:4byte
+";
+
+    #[test]
+    fn parses_dtk_symbols() {
+        let map = parse_dtk_symbols(SYMBOLS_TXT);
+        let symbols: Vec<(&str, &str, u32, u32, SymbolKind, bool)> = map.symbols.iter()
+            .map(|s| (s.name.as_str(), s.section.as_deref().unwrap(), s.addr, s.size, s.kind, s.local))
+            .collect();
+        assert_eq!(symbols, [("__check_pad3", ".init",  0x81300000, 0x40,  SymbolKind::Function, true),
+                             ("__start",      ".init",  0x81300040, 0xC0,  SymbolKind::Function, false),
+                             ("lbl_81300100", ".text",  0x81300100, 0,     SymbolKind::Unknown,  true),
+                             ("OSInit",       ".text",  0x81330440, 0x200, SymbolKind::Function, false),
+                             ("@123",         ".data",  0x81380000, 0x10,  SymbolKind::Object,   true),
+                             ("__OSCurrHeap", ".sbss",  0x813A0000, 0x4,   SymbolKind::Object,   false)]);
+
+        // And written back the same way, apart from the attributes bstool doesn't keep
+        assert_eq!(dtk_line(&map.symbols[3]), "OSInit = .text:0x81330440; // type:function size:0x200 scope:global");
+        assert_eq!(dtk_line(&map.symbols[2]), "lbl_81300100 = .text:0x81300100; // type:label scope:local");
+        assert!(is_dtk_symbols(SYMBOLS_TXT));
+    }
+
+    #[test]
+    fn parses_text_maps() {
+        let map = parse_text_map(".init section layout
+  Starting        Virtual
+  address  Size   address
+  -----------------------
+  00000000 000040 81300000  4 __check_pad3 \tRuntime.PPCEABI.H/__start.o
+  UNUSED   000034 ........ __OSUnused OS.o
+.text section layout
+81330440 00000200 81330440 0 OSInit
+81330640 000010 OSFoo
+81330650 OSBar
+");
+        let symbols: Vec<(&str, Option<&str>, u32, u32, SymbolKind)> = map.symbols.iter()
+            .map(|s| (s.name.as_str(), s.section.as_deref(), s.addr, s.size, s.kind))
+            .collect();
+        assert_eq!(symbols, [("__check_pad3", Some(".init"), 0x81300000, 0x40,  SymbolKind::Function),
+                             ("OSInit",       Some(".text"), 0x81330440, 0x200, SymbolKind::Function),
+                             ("OSFoo",        Some(".text"), 0x81330640, 0x10,  SymbolKind::Function),
+                             ("OSBar",        Some(".text"), 0x81330650, 0,     SymbolKind::Function)]);
+    }
+
+    #[test]
+    fn find_and_describe() {
+        let map = parse_dtk_symbols(SYMBOLS_TXT);
+        let describe = |addr: u32| map.describe(addr);
+        assert_eq!(describe(0x81300000).as_deref(), Some("__check_pad3"));
+        assert_eq!(describe(0x8130003C).as_deref(), Some("__check_pad3+0x3C"));
+        assert_eq!(describe(0x81300040).as_deref(), Some("__start"));
+        // Past __start, the closest label without a size takes over
+        assert_eq!(describe(0x81300200).as_deref(), Some("lbl_81300100+0x100"));
+        assert_eq!(describe(0x81330644).as_deref(), None);
+        assert_eq!(describe(0x812FFFFC).as_deref(), None);
+        assert_eq!(map.find(0x81380008).map(|s| s.name.as_str()), Some("@123"));
+    }
+}
@@ -0,0 +1,320 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+use aes::cipher::{block_padding::NoPadding, BlockDecryptMut, KeyIvInit};
+use sha1::{Digest, Sha1};
+
+use crate::meta;
+
+pub const SYSTEM_MENU_TITLE_ID : u64 = 0x0000000100000002;
+
+// BootMii keys.bin dump, the common key sits at 0x114
+pub const KEYS_BIN_SIZE : usize = 0x400;
+pub const KEYS_BIN_COMMON_KEY : usize = 0x114;
+
+pub const TMD_CONTENT_OFFSET : usize = 0x1E4;
+pub const TMD_CONTENT_SIZE : usize = 0x24;
+
+type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
+
+pub struct Ticket {
+    pub title_key: [u8; 16],
+    pub title_id: u64,
+    pub common_key_index: u8,
+}
+
+#[derive(Clone)]
+pub struct TmdContent {
+    pub content_id: u32,
+    pub index: u16,
+    pub kind: u16,
+    pub size: u64,
+    pub sha1: [u8; 20],
+}
+
+pub struct Tmd {
+    pub title_id: u64,
+    pub title_version: u16,
+    pub boot_index: u16,
+    pub contents: Vec<TmdContent>,
+}
+
+fn invalid_title(message: &str) -> Error {
+    return Error::new(ErrorKind::InvalidData, message.to_string());
+}
+
+fn read_u16(buffer: &[u8], offset: usize) -> u16 {
+    return u16::from_be_bytes([buffer[offset], buffer[offset + 1]]);
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    return u32::from_be_bytes([buffer[offset], buffer[offset + 1], buffer[offset + 2], buffer[offset + 3]]);
+}
+
+fn read_u64(buffer: &[u8], offset: usize) -> u64 {
+    return (read_u32(buffer, offset) as u64) << 32 | read_u32(buffer, offset + 4) as u64;
+}
+
+// Size of the signature block in front of a ticket or TMD body (type, signature, padding to 0x40).
+pub fn signature_size(signed_data: &[u8]) -> std::io::Result<usize> {
+    if signed_data.len() < 4 {
+        return Err(invalid_title("Signed blob is too small"));
+    }
+    return match read_u32(signed_data, 0) {
+        0x00010000 => Ok(0x240),  // RSA-4096
+        0x00010001 => Ok(0x140),  // RSA-2048
+        0x00010002 => Ok(0x80),   // ECDSA
+        sig_type   => Err(invalid_title(&format!("Unknown signature type {:#010X}", sig_type))),
+    };
+}
+
+pub fn parse_ticket(ticket_data: &[u8]) -> std::io::Result<Ticket> {
+    let body = signature_size(ticket_data)?;
+    if ticket_data.len() < body + 0xB2 {
+        return Err(invalid_title("Ticket is truncated"));
+    }
+
+    let mut title_key = [0u8; 16];
+    title_key.copy_from_slice(&ticket_data[body + 0x7F..body + 0x8F]);
+
+    return Ok(Ticket {
+        title_key,
+        title_id:           read_u64(ticket_data, body + 0x9C),
+        common_key_index:   ticket_data[body + 0xB1],
+    });
+}
+
+pub fn parse_tmd(tmd_data: &[u8]) -> std::io::Result<Tmd> {
+    let body = signature_size(tmd_data)?;
+    if body != 0x140 || tmd_data.len() < TMD_CONTENT_OFFSET {
+        return Err(invalid_title("TMD is truncated or not RSA-2048 signed"));
+    }
+
+    let num_contents = read_u16(tmd_data, 0x1DE) as usize;
+    if tmd_data.len() < TMD_CONTENT_OFFSET + num_contents * TMD_CONTENT_SIZE {
+        return Err(invalid_title(&format!("TMD is too small for {} contents", num_contents)));
+    }
+
+    let contents = (0..num_contents).map(|i| {
+        let off = TMD_CONTENT_OFFSET + i * TMD_CONTENT_SIZE;
+        let mut sha1 = [0u8; 20];
+        sha1.copy_from_slice(&tmd_data[off + 0x10..off + 0x24]);
+        TmdContent {
+            content_id: read_u32(tmd_data, off),
+            index:      read_u16(tmd_data, off + 0x04),
+            kind:       read_u16(tmd_data, off + 0x06),
+            size:       read_u64(tmd_data, off + 0x08),
+            sha1,
+        }
+    }).collect();
+
+    return Ok(Tmd {
+        title_id:       read_u64(tmd_data, 0x18C),
+        title_version:  read_u16(tmd_data, 0x1DC),
+        boot_index:     read_u16(tmd_data, 0x1E0),
+        contents,
+    });
+}
+
+impl Tmd {
+    pub fn boot_content(&self) -> std::io::Result<&TmdContent> {
+        return self.contents.iter().find(|c| c.index == self.boot_index)
+                   .ok_or_else(|| invalid_title(&format!("TMD has no content with the boot index {}", self.boot_index)));
+    }
+}
+
+// shared1/content.map: 8 character file name and SHA-1 per shared content
+pub const CONTENT_MAP_ENTRY_SIZE : usize = 0x1C;
+
+// Content type bit of contents kept in shared1/ instead of the title's own directory
+pub const CONTENT_SHARED : u16 = 0x8000;
+
+// Name of the shared1/ file holding the content with this hash
+pub fn find_shared_content(content_map: &[u8], sha1: &[u8; 20]) -> Option<String> {
+    return content_map.chunks_exact(CONTENT_MAP_ENTRY_SIZE)
+                      .find(|entry| &entry[8..] == sha1)
+                      .map(|entry| String::from_utf8_lossy(&entry[..8]).to_string());
+}
+
+// 00000001-00000002 style title ID
+pub fn title_string(title_id: u64) -> String {
+    return format!("{:08X}-{:08X}", title_id >> 32, title_id & 0xFFFFFFFF);
+}
+
+// The common key is never bundled. Takes a raw 16 byte key, a BootMii keys.bin or the key as hex text.
+pub fn read_common_key(file_name: &str) -> std::io::Result<[u8; 16]> {
+    let key_data = fs::read(file_name)?;
+    let mut key = [0u8; 16];
+
+    if key_data.len() == 16 {
+        key.copy_from_slice(&key_data);
+    }
+    else if key_data.len() == KEYS_BIN_SIZE {
+        key.copy_from_slice(&key_data[KEYS_BIN_COMMON_KEY..KEYS_BIN_COMMON_KEY + 16]);
+    }
+    else {
+        let text = String::from_utf8_lossy(&key_data);
+        let bytes = meta::hex_to_bytes(text.trim()).filter(|b| b.len() == 16)
+                        .ok_or_else(|| invalid_title(&format!("{} is not a common key (16 bytes, keys.bin or 32 hex digits)", file_name)))?;
+        key.copy_from_slice(&bytes);
+    }
+
+    return Ok(key);
+}
+
+// Key from -k, otherwise a common-key.bin or keys.bin in the working directory.
+pub fn find_common_key(key_file: &Option<String>) -> std::io::Result<[u8; 16]> {
+    if let Some(key_file) = key_file {
+        return read_common_key(key_file);
+    }
+    for key_file in ["common-key.bin", "keys.bin"] {
+        if std::path::Path::new(key_file).is_file() {
+            return read_common_key(key_file);
+        }
+    }
+    return Err(Error::new(ErrorKind::NotFound,
+                          "Decrypting needs the Wii common key: pass it with -k (16 byte key, BootMii keys.bin or hex text)"));
+}
+
+pub fn aes_cbc_decrypt(key: &[u8; 16], iv: &[u8; 16], data: &mut [u8]) -> std::io::Result<()> {
+    if !data.len().is_multiple_of(16) {
+        return Err(invalid_title("Encrypted data is not a multiple of the AES block size"));
+    }
+    Aes128CbcDec::new(key.into(), iv.into()).decrypt_padded_mut::<NoPadding>(data)
+                                            .map_err(|_| invalid_title("AES decryption failed"))?;
+    return Ok(());
+}
+
+// Only the Wii common key (index 0) can be passed in, tickets for the Korean or vWii keys are refused.
+pub fn decrypt_title_key(ticket: &Ticket, common_key: &[u8; 16]) -> std::io::Result<[u8; 16]> {
+    if ticket.common_key_index != 0 {
+        return Err(Error::new(ErrorKind::Unsupported,
+                              format!("Ticket uses common key index {}, only the Wii common key (index 0) is supported", ticket.common_key_index)));
+    }
+
+    let mut iv = [0u8; 16];
+    iv[..8].copy_from_slice(&ticket.title_id.to_be_bytes());
+
+    let mut title_key = ticket.title_key;
+    aes_cbc_decrypt(common_key, &iv, &mut title_key)?;
+    return Ok(title_key);
+}
+
+// Decrypts a content (padded to 16 bytes) and checks it against its TMD record.
+pub fn decrypt_content(content: &TmdContent, encrypted: &[u8], title_key: &[u8; 16]) -> std::io::Result<Vec<u8>> {
+    let padded_size = (content.size as usize).next_multiple_of(16);
+    let mut data = encrypted.get(..padded_size)
+                            .ok_or_else(|| invalid_title(&format!("Content {:08x} is truncated", content.content_id)))?
+                            .to_vec();
+
+    let mut iv = [0u8; 16];
+    iv[..2].copy_from_slice(&content.index.to_be_bytes());
+    aes_cbc_decrypt(title_key, &iv, &mut data)?;
+    data.truncate(content.size as usize);
+
+    verify_content(content, &data)?;
+    return Ok(data);
+}
+
+pub fn verify_content(content: &TmdContent, data: &[u8]) -> std::io::Result<()> {
+    if data.len() as u64 != content.size || Sha1::digest(data).as_slice() != content.sha1 {
+        return Err(invalid_title(&format!("Content {:08x} does not match the SHA-1 in the TMD (wrong common key?)", content.content_id)));
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    use aes::cipher::BlockEncryptMut;
+
+    type Aes128CbcEnc = cbc::Encryptor<aes::Aes128>;
+
+    pub const COMMON_KEY : [u8; 16] = [0x11; 16];
+    pub const TITLE_KEY : [u8; 16] = [0x22; 16];
+
+    pub fn aes_cbc_encrypt(key: &[u8; 16], iv: &[u8; 16], data: &mut [u8]) {
+        let len = data.len();
+        Aes128CbcEnc::new(key.into(), iv.into()).encrypt_padded_mut::<NoPadding>(data, len).unwrap();
+    }
+
+    // RSA-2048 signed ticket with the title key encrypted under COMMON_KEY
+    pub fn synthetic_ticket(title_id: u64, common_key_index: u8) -> Vec<u8> {
+        let body = 0x140;
+        let mut ticket_data = vec![0u8; body + 0x164];
+        ticket_data[0..4].copy_from_slice(&0x00010001u32.to_be_bytes());
+
+        let mut iv = [0u8; 16];
+        iv[..8].copy_from_slice(&title_id.to_be_bytes());
+        let mut title_key = TITLE_KEY;
+        aes_cbc_encrypt(&COMMON_KEY, &iv, &mut title_key);
+
+        ticket_data[body + 0x7F..body + 0x8F].copy_from_slice(&title_key);
+        ticket_data[body + 0x9C..body + 0xA4].copy_from_slice(&title_id.to_be_bytes());
+        ticket_data[body + 0xB1] = common_key_index;
+        return ticket_data;
+    }
+
+    // TMD for the given contents (id, index, data), booting the last one
+    pub fn synthetic_tmd(title_id: u64, contents: &[(u32, u16, &[u8])]) -> Vec<u8> {
+        let mut tmd_data = vec![0u8; TMD_CONTENT_OFFSET + contents.len() * TMD_CONTENT_SIZE];
+        tmd_data[0..4].copy_from_slice(&0x00010001u32.to_be_bytes());
+        tmd_data[0x18C..0x194].copy_from_slice(&title_id.to_be_bytes());
+        tmd_data[0x1DC..0x1DE].copy_from_slice(&0x0241u16.to_be_bytes());
+        tmd_data[0x1DE..0x1E0].copy_from_slice(&(contents.len() as u16).to_be_bytes());
+        tmd_data[0x1E0..0x1E2].copy_from_slice(&contents.last().unwrap().1.to_be_bytes());
+
+        for (i, (content_id, index, data)) in contents.iter().enumerate() {
+            let off = TMD_CONTENT_OFFSET + i * TMD_CONTENT_SIZE;
+            tmd_data[off..off + 4].copy_from_slice(&content_id.to_be_bytes());
+            tmd_data[off + 0x04..off + 0x06].copy_from_slice(&index.to_be_bytes());
+            tmd_data[off + 0x06..off + 0x08].copy_from_slice(&1u16.to_be_bytes());
+            tmd_data[off + 0x08..off + 0x10].copy_from_slice(&(data.len() as u64).to_be_bytes());
+            tmd_data[off + 0x10..off + 0x24].copy_from_slice(&Sha1::digest(data));
+        }
+        return tmd_data;
+    }
+
+    // Content encrypted under TITLE_KEY and padded to the AES block size
+    pub fn encrypt_content(index: u16, data: &[u8]) -> Vec<u8> {
+        let mut encrypted = data.to_vec();
+        encrypted.resize(data.len().next_multiple_of(16), 0);
+
+        let mut iv = [0u8; 16];
+        iv[..2].copy_from_slice(&index.to_be_bytes());
+        aes_cbc_encrypt(&TITLE_KEY, &iv, &mut encrypted);
+        return encrypted;
+    }
+
+    #[test]
+    fn ticket_offsets_and_title_key() {
+        let ticket = parse_ticket(&synthetic_ticket(SYSTEM_MENU_TITLE_ID, 0)).unwrap();
+        assert_eq!(ticket.title_id, SYSTEM_MENU_TITLE_ID);
+        assert_eq!(ticket.common_key_index, 0);
+        assert_eq!(decrypt_title_key(&ticket, &COMMON_KEY).unwrap(), TITLE_KEY);
+    }
+
+    #[test]
+    fn other_common_keys_are_refused() {
+        let ticket = parse_ticket(&synthetic_ticket(SYSTEM_MENU_TITLE_ID, 1)).unwrap();
+        assert_eq!(ticket.common_key_index, 1);
+        assert_eq!(decrypt_title_key(&ticket, &COMMON_KEY).err().unwrap().kind(), ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn tmd_offsets_and_content() {
+        let data = b"not a multiple of the block size";
+        let tmd = parse_tmd(&synthetic_tmd(SYSTEM_MENU_TITLE_ID, &[(0x2A, 0, b"banner"), (0x97, 1, data)])).unwrap();
+        assert_eq!(tmd.title_id, SYSTEM_MENU_TITLE_ID);
+        assert_eq!(tmd.title_version, 0x0241);
+        assert_eq!(tmd.contents.len(), 2);
+
+        let boot = tmd.boot_content().unwrap();
+        assert_eq!((boot.content_id, boot.index, boot.size), (0x97, 1, data.len() as u64));
+        assert_eq!(decrypt_content(boot, &encrypt_content(1, data), &TITLE_KEY).unwrap(), data);
+
+        // The IV is the content index, so the other index doesn't decrypt
+        assert!(decrypt_content(boot, &encrypt_content(0, data), &TITLE_KEY).is_err());
+    }
+}
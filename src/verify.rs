@@ -0,0 +1,171 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+use crate::bootstage::{BSImageRef, HEADER_LENGTH};
+
+// A stretch of the file that maps to one thing
+pub struct Region {
+    pub name: String,
+    pub file_off: usize,
+    pub len: usize,
+    // None for parts that are never loaded (the header)
+    pub addr: Option<u32>,
+}
+
+pub struct DiffRange {
+    pub file_off: usize,
+    pub len: usize,
+    pub region: Option<usize>,
+}
+
+impl DiffRange {
+    pub fn addr(&self, regions: &[Region]) -> Option<u32> {
+        let region = &regions[self.region?];
+        return region.addr?.checked_add((self.file_off - region.file_off) as u32);
+    }
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    return u32::from_be_bytes([buffer[offset], buffer[offset + 1], buffer[offset + 2], buffer[offset + 3]]);
+}
+
+// File layout of a BootStage, BS2 split up by its sections.
+pub fn regions(file_data: &[u8], image: &BSImageRef) -> Vec<Region> {
+    let bs1_off = read_u32(file_data, 0x00) as usize;
+    let pad_off = read_u32(file_data, 0x1C) as usize;
+    let bs2_off = pad_off + image.unk_stuff.len();
+
+    let mut regions = vec![
+        Region { name: "header".to_string(),    file_off: 0,                                len: HEADER_LENGTH,             addr: None },
+        Region { name: "BS1".to_string(),       file_off: bs1_off,                          len: image.bs1_len as usize,    addr: Some(image.bs1_addr) },
+        Region { name: "BS2 entry".to_string(), file_off: bs1_off + image.bs1_len as usize, len: 4,                         addr: Some(image.bs1_addr + image.bs1_len) },
+    ];
+    if !image.unk_stuff.is_empty() {
+        regions.push(Region { name: "pad block".to_string(), file_off: pad_off, len: image.unk_stuff.len(), addr: Some(image.bs2_addr - image.unk_stuff.len() as u32) });
+    }
+
+    let bs2_end = image.bs2_addr + image.bs2_data.len() as u32;
+    let mut sections: Vec<(String, u32, u32)> = Vec::new();
+    for i in 0..image.text_addr.len() {
+        sections.push((image.profile.text_names[i].clone(), image.text_addr[i], image.text_len[i]));
+    }
+    for i in 0..image.data_addr.len() {
+        sections.push((image.profile.data_names[i].clone(), image.data_addr[i], image.data_len[i]));
+    }
+    sections.retain(|(_, addr, len)| *len != 0 && *addr >= image.bs2_addr && addr.checked_add(*len).is_some_and(|end| end <= bs2_end));
+    sections.sort_by_key(|(_, addr, _)| *addr);
+
+    // Whatever no section covers is still BS2
+    let mut addr = image.bs2_addr;
+    let mut push_bs2 = |name: String, start: u32, end: u32| {
+        if end > start {
+            regions.push(Region { name, file_off: bs2_off + (start - image.bs2_addr) as usize, len: (end - start) as usize, addr: Some(start) });
+        }
+    };
+    for (name, start, len) in sections {
+        if start < addr {
+            continue;
+        }
+        push_bs2("BS2".to_string(), addr, start);
+        push_bs2(format!("BS2 {}", name), start, start + len);
+        addr = start + len;
+    }
+    push_bs2("BS2".to_string(), addr, bs2_end);
+
+    return regions;
+}
+
+fn region_of(regions: &[Region], file_off: usize) -> Option<usize> {
+    return regions.iter().position(|r| (r.file_off..r.file_off + r.len).contains(&file_off));
+}
+
+// Differing bytes, grouped into ranges that don't cross regions.
+// Bytes only one of the files has count as differing.
+pub fn diff_ranges(built: &[u8], original: &[u8], regions: &[Region]) -> Vec<DiffRange> {
+    let mut ranges: Vec<DiffRange> = Vec::new();
+    for i in 0..built.len().max(original.len()) {
+        if built.get(i) == original.get(i) {
+            continue;
+        }
+        let region = region_of(regions, i);
+        match ranges.last_mut() {
+            Some(last) if last.file_off + last.len == i && last.region == region => last.len += 1,
+            _ => ranges.push(DiffRange { file_off: i, len: 1, region }),
+        }
+    }
+    return ranges;
+}
+
+// A SHA-1 in hex, or a file starting with one (sha1sum output)
+pub fn read_expected_sha1(expected: &str) -> std::io::Result<String> {
+    let text = if expected.len() == 40 && expected.chars().all(|c| c.is_ascii_hexdigit()) {
+        expected.to_string()
+    }
+    else {
+        fs::read_to_string(expected).map_err(|e| Error::new(e.kind(), format!("{}: {}", expected, e)))?
+    };
+
+    let sha1 = text.split_whitespace().next().unwrap_or("");
+    if sha1.len() != 40 || !sha1.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::new(ErrorKind::InvalidData, format!("{} is not a SHA-1", expected)));
+    }
+    return Ok(sha1.to_ascii_lowercase());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bootstage::tests::{synthetic_image, write_and_read};
+    use crate::profile;
+
+    #[test]
+    fn regions_cover_the_file() {
+        let image = synthetic_image(profile::retail(), 0x4FC, 0x1000, true);
+        let file_data = write_and_read(&image, "regions.img");
+        let parsed = BSImageRef::parse(&file_data).unwrap();
+        let regions = regions(&file_data, &parsed);
+
+        let names: Vec<&str> = regions.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["header", "BS1", "BS2 entry", "pad block",
+                           "BS2 .init", "BS2 .text", "BS2 extab", "BS2 extabindex", "BS2 .ctors", "BS2 .dtors",
+                           "BS2 .rodata", "BS2 .data", "BS2 .sdata", "BS2 .sdata2", "BS2"]);
+
+        // Back to back from the header to the end of the file, each at the address it loads to
+        for pair in regions.windows(2) {
+            assert_eq!(pair[0].file_off + pair[0].len, pair[1].file_off);
+        }
+        let last = regions.last().unwrap();
+        assert_eq!(last.file_off + last.len, file_data.len());
+        assert_eq!((regions[2].addr, regions[3].addr), (Some(0x81200000 + 0x4FC), Some(parsed.bs2_addr - 0x20)));
+        assert_eq!((regions[4].addr, regions[14].addr), (Some(parsed.bs2_addr), Some(parsed.bs2_addr + 0x600)));
+    }
+
+    #[test]
+    fn diff_ranges_split_at_regions() {
+        let image = synthetic_image(profile::retail(), 0x4FC, 0x1000, true);
+        let original = write_and_read(&image, "diff.img");
+        let parsed = BSImageRef::parse(&original).unwrap();
+        let regions = regions(&original, &parsed);
+        let region = |name: &str| regions.iter().find(|r| r.name == name).unwrap();
+
+        // A run across the end of .text into extab, a byte in .sdata2, a byte in the pad block and a missing tail
+        let mut built = original.clone();
+        let text = region("BS2 .text");
+        for byte in &mut built[text.file_off + text.len - 2..text.file_off + text.len + 3] {
+            *byte ^= 0xFF;
+        }
+        built[region("BS2 .sdata2").file_off + 0x10] ^= 1;
+        built[region("pad block").file_off + 0x08] ^= 1;
+        built.truncate(original.len() - 4);
+
+        let ranges = diff_ranges(&built, &original, &regions);
+        let found: Vec<(&str, usize, Option<u32>)> = ranges.iter().map(|r| (regions[r.region.unwrap()].name.as_str(), r.len, r.addr(&regions))).collect();
+        let bs2 = parsed.bs2_addr;
+        assert_eq!(found, [("pad block",   1, Some(bs2 - 0x20 + 0x08)),
+                           ("BS2 .text",   2, Some(bs2 + 0x4FE)),
+                           ("BS2 extab",   3, Some(bs2 + 0x500)),
+                           ("BS2 .sdata2", 1, Some(bs2 + 0x5F0)),
+                           ("BS2",         4, Some(bs2 + 0xFFC))]);
+        assert!(diff_ranges(&original, &original, &regions).is_empty());
+    }
+}
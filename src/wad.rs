@@ -0,0 +1,183 @@
+use std::io::{Error, ErrorKind};
+
+use crate::title::{self, Ticket, Tmd};
+
+pub const HEADER_LENGTH : usize = 0x20;
+pub const ALIGNMENT : usize = 0x40;
+
+// Installable WAD ('Is') and boot2 WAD ('ib')
+pub const TYPE_INSTALLABLE : u16 = 0x4973;
+pub const TYPE_BOOT2 : u16 = 0x6962;
+
+pub struct WadHeader {
+    pub header_size: u32,
+    pub wad_type: u16,
+    pub version: u16,
+    pub cert_size: u32,
+    pub crl_size: u32,
+    pub ticket_size: u32,
+    pub tmd_size: u32,
+    pub data_size: u32,
+    pub footer_size: u32,
+}
+
+// Borrowed view of a WAD, every part still encrypted/signed as stored.
+pub struct Wad<'a> {
+    pub header: WadHeader,
+    pub certs: &'a [u8],
+    pub ticket_data: &'a [u8],
+    pub tmd_data: &'a [u8],
+    pub ticket: Ticket,
+    pub tmd: Tmd,
+
+    // Encrypted contents in TMD order
+    pub contents: Vec<&'a [u8]>,
+}
+
+fn invalid_wad(message: &str) -> Error {
+    return Error::new(ErrorKind::InvalidData, format!("Invalid WAD: {}", message));
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    return u32::from_be_bytes([buffer[offset], buffer[offset + 1], buffer[offset + 2], buffer[offset + 3]]);
+}
+
+fn read_u16(buffer: &[u8], offset: usize) -> u16 {
+    return u16::from_be_bytes([buffer[offset], buffer[offset + 1]]);
+}
+
+fn align(offset: usize) -> usize {
+    return offset.next_multiple_of(ALIGNMENT);
+}
+
+pub fn read_header(file_data: &[u8]) -> std::io::Result<WadHeader> {
+    if file_data.len() < HEADER_LENGTH {
+        return Err(invalid_wad("file is smaller than the header"));
+    }
+
+    let header = WadHeader {
+        header_size:    read_u32(file_data, 0x00),
+        wad_type:       read_u16(file_data, 0x04),
+        version:        read_u16(file_data, 0x06),
+        cert_size:      read_u32(file_data, 0x08),
+        crl_size:       read_u32(file_data, 0x0C),
+        ticket_size:    read_u32(file_data, 0x10),
+        tmd_size:       read_u32(file_data, 0x14),
+        data_size:      read_u32(file_data, 0x18),
+        footer_size:    read_u32(file_data, 0x1C),
+    };
+
+    if header.header_size as usize != HEADER_LENGTH || (header.wad_type != TYPE_INSTALLABLE && header.wad_type != TYPE_BOOT2) {
+        return Err(invalid_wad("not a WAD header"));
+    }
+
+    return Ok(header);
+}
+
+pub fn parse(file_data: &[u8]) -> std::io::Result<Wad<'_>> {
+    let header = read_header(file_data)?;
+
+    // Every part starts on a 0x40 boundary
+    let mut offset = align(HEADER_LENGTH);
+    let mut next_part = |size: u32, name: &str| -> std::io::Result<&[u8]> {
+        let part = file_data.get(offset..offset + size as usize)
+                            .ok_or_else(|| invalid_wad(&format!("{} ({:#X} bytes at {:#X}) exceeds the file size", name, size, offset)))?;
+        offset = align(offset + size as usize);
+        return Ok(part);
+    };
+
+    let certs = next_part(header.cert_size, "certificate chain")?;
+    next_part(header.crl_size, "CRL")?;
+    let ticket_data = next_part(header.ticket_size, "ticket")?;
+    let tmd_data = next_part(header.tmd_size, "TMD")?;
+    let data = next_part(header.data_size, "content data")?;
+
+    let ticket = title::parse_ticket(ticket_data)?;
+    let tmd = title::parse_tmd(tmd_data)?;
+
+    let mut contents = Vec::new();
+    let mut content_off = 0;
+    for content in &tmd.contents {
+        let padded_size = (content.size as usize).next_multiple_of(16);
+        let encrypted = data.get(content_off..content_off + padded_size)
+                            .ok_or_else(|| invalid_wad(&format!("content {:08x} exceeds the content data", content.content_id)))?;
+        contents.push(encrypted);
+        content_off = align(content_off + padded_size);
+    }
+
+    return Ok(Wad { header, certs, ticket_data, tmd_data, ticket, tmd, contents });
+}
+
+impl Wad<'_> {
+    pub fn title_key(&self, common_key: &[u8; 16]) -> std::io::Result<[u8; 16]> {
+        return title::decrypt_title_key(&self.ticket, common_key);
+    }
+
+    // Decrypted and SHA-1 checked content at position i of the TMD.
+    pub fn content(&self, i: usize, title_key: &[u8; 16]) -> std::io::Result<Vec<u8>> {
+        return title::decrypt_content(&self.tmd.contents[i], self.contents[i], title_key);
+    }
+
+    // Decrypts every content once to check it against the TMD.
+    pub fn verify_contents(&self, title_key: &[u8; 16]) -> std::io::Result<()> {
+        for i in 0..self.contents.len() {
+            self.content(i, title_key)?;
+        }
+        return Ok(());
+    }
+
+    // The content the TMD boots, which for the System Menu is the BootStage.
+    pub fn boot_content(&self, common_key: &[u8; 16]) -> std::io::Result<Vec<u8>> {
+        let boot = self.tmd.boot_content()?;
+        let i = self.tmd.contents.iter().position(|c| c.index == boot.index).unwrap();
+        return self.content(i, &self.title_key(common_key)?);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::title::tests::{encrypt_content, synthetic_ticket, synthetic_tmd, COMMON_KEY};
+
+    fn push_part(wad_data: &mut Vec<u8>, part: &[u8]) {
+        wad_data.extend_from_slice(part);
+        wad_data.resize(align(wad_data.len()), 0);
+    }
+
+    #[test]
+    fn parse_and_decrypt() {
+        let contents : [(u32, u16, &[u8]); 2] = [(0x2A, 0, &[0x5A; 0x50]), (0x97, 1, b"boot content")];
+        let ticket_data = synthetic_ticket(title::SYSTEM_MENU_TITLE_ID, 0);
+        let tmd_data = synthetic_tmd(title::SYSTEM_MENU_TITLE_ID, &contents);
+        let certs = vec![0xCE; 0x30];
+
+        let mut data = Vec::new();
+        for (_, index, content) in &contents {
+            push_part(&mut data, &encrypt_content(*index, content));
+        }
+
+        let mut wad_data = Vec::new();
+        for value in [HEADER_LENGTH as u32, (TYPE_INSTALLABLE as u32) << 16, certs.len() as u32, 0,
+                      ticket_data.len() as u32, tmd_data.len() as u32, data.len() as u32, 0] {
+            wad_data.extend_from_slice(&value.to_be_bytes());
+        }
+        wad_data.resize(align(HEADER_LENGTH), 0);
+        for part in [&certs, &ticket_data, &tmd_data, &data] {
+            push_part(&mut wad_data, part);
+        }
+
+        let wad = parse(&wad_data).unwrap();
+        assert_eq!(wad.certs, &certs[..]);
+        assert_eq!(wad.ticket_data, &ticket_data[..]);
+        assert_eq!(wad.tmd_data, &tmd_data[..]);
+        assert_eq!(wad.contents.len(), 2);
+
+        let title_key = wad.title_key(&COMMON_KEY).unwrap();
+        wad.verify_contents(&title_key).unwrap();
+        assert_eq!(wad.content(0, &title_key).unwrap(), contents[0].2);
+        assert_eq!(wad.boot_content(&COMMON_KEY).unwrap(), contents[1].2);
+
+        // Cut into the content data
+        assert!(parse(&wad_data[..wad_data.len() - 0x40]).is_err());
+    }
+}
@@ -0,0 +1,232 @@
+use std::io::{Error, ErrorKind};
+
+pub const MAGIC : &[u8; 4] = b"Yay0";
+pub const HEADER_LENGTH : usize = 0x10;
+
+const WINDOW_SIZE : usize = 0x1000;
+const MIN_MATCH : usize = 3;
+const MAX_MATCH : usize = 0xFF + 0x12;
+
+fn invalid_yay0(message: &str) -> Error {
+    return Error::new(ErrorKind::InvalidData, format!("Invalid Yay0 data: {}", message));
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> std::io::Result<u32> {
+    return match buffer.get(offset..offset + 4) {
+        Some(temp) => Ok(u32::from_be_bytes([temp[0], temp[1], temp[2], temp[3]])),
+        None       => Err(invalid_yay0("truncated mask data")),
+    };
+}
+
+fn read_u16(buffer: &[u8], offset: usize) -> std::io::Result<u16> {
+    return match buffer.get(offset..offset + 2) {
+        Some(temp) => Ok(u16::from_be_bytes([temp[0], temp[1]])),
+        None       => Err(invalid_yay0("truncated link table")),
+    };
+}
+
+fn read_u8(buffer: &[u8], offset: usize) -> std::io::Result<u8> {
+    return buffer.get(offset).copied().ok_or_else(|| invalid_yay0("truncated chunk data"));
+}
+
+pub fn is_yay0(data: &[u8]) -> bool {
+    return data.len() >= HEADER_LENGTH && &data[0..4] == MAGIC;
+}
+
+pub fn decompressed_size(data: &[u8]) -> std::io::Result<usize> {
+    if !is_yay0(data) {
+        return Err(invalid_yay0("no Yay0 magic"));
+    }
+    return Ok(read_u32(data, 0x04)? as usize);
+}
+
+pub fn decompress(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let size = decompressed_size(data)?;
+    let mut link_off = read_u32(data, 0x08)? as usize;
+    let mut chunk_off = read_u32(data, 0x0C)? as usize;
+    let mut mask_off = HEADER_LENGTH;
+
+    let mut output = Vec::with_capacity(size);
+    let mut mask = 0u32;
+    let mut mask_bits = 0;
+
+    while output.len() < size {
+        if mask_bits == 0 {
+            mask = read_u32(data, mask_off)?;
+            mask_off += 4;
+            mask_bits = 32;
+        }
+
+        if mask & 0x80000000 != 0 {
+            output.push(read_u8(data, chunk_off)?);
+            chunk_off += 1;
+        }
+        else {
+            let link = read_u16(data, link_off)? as usize;
+            link_off += 2;
+
+            let dist = (link & 0xFFF) + 1;
+            let mut count = link >> 12;
+            if count == 0 {
+                count = read_u8(data, chunk_off)? as usize + 0x12;
+                chunk_off += 1;
+            }
+            else {
+                count += 2;
+            }
+
+            if dist > output.len() {
+                return Err(invalid_yay0("back reference before the start"));
+            }
+
+            // Copies may overlap what they are producing, so go byte by byte
+            let start = output.len() - dist;
+            for i in 0..count.min(size - output.len()) {
+                output.push(output[start + i]);
+            }
+        }
+
+        mask <<= 1;
+        mask_bits -= 1;
+    }
+
+    return Ok(output);
+}
+
+fn match_length(data: &[u8], a: usize, b: usize) -> usize {
+    let max = MAX_MATCH.min(data.len() - b);
+    let mut len = 0;
+    while len < max && data[a + len] == data[b + len] {
+        len += 1;
+    }
+    return len;
+}
+
+// Greedy LZ compression, with hash chains over 3-byte prefixes to find matches quickly.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    const HASH_SIZE : usize = 1 << 15;
+    let hash = |pos: usize| -> usize {
+        ((data[pos] as usize) << 10 ^ (data[pos + 1] as usize) << 5 ^ data[pos + 2] as usize) & (HASH_SIZE - 1)
+    };
+
+    let mut head = vec![usize::MAX; HASH_SIZE];
+    let mut prev = vec![usize::MAX; data.len()];
+
+    let mut masks : Vec<u32> = Vec::new();
+    let mut links : Vec<u8> = Vec::new();
+    let mut chunks : Vec<u8> = Vec::new();
+
+    let mut mask = 0u32;
+    let mut mask_bits = 0;
+
+    let insert = |pos: usize, head: &mut [usize], prev: &mut [usize]| {
+        if pos + MIN_MATCH <= data.len() {
+            let h = hash(pos);
+            prev[pos] = head[h];
+            head[h] = pos;
+        }
+    };
+
+    let mut pos = 0;
+    while pos < data.len() {
+        // Find the longest match in the window
+        let mut best_len = 0;
+        let mut best_dist = 0;
+        if pos + MIN_MATCH <= data.len() {
+            let mut candidate = head[hash(pos)];
+            while candidate != usize::MAX && pos - candidate <= WINDOW_SIZE {
+                let len = match_length(data, candidate, pos);
+                if len > best_len {
+                    best_len = len;
+                    best_dist = pos - candidate;
+                    if len == MAX_MATCH {
+                        break;
+                    }
+                }
+                candidate = prev[candidate];
+            }
+        }
+
+        mask <<= 1;
+        if best_len >= MIN_MATCH {
+            let dist = (best_dist - 1) as u16;
+            if best_len >= 0x12 {
+                links.extend_from_slice(&dist.to_be_bytes());
+                chunks.push((best_len - 0x12) as u8);
+            }
+            else {
+                links.extend_from_slice(&((((best_len - 2) as u16) << 12) | dist).to_be_bytes());
+            }
+            for i in 0..best_len {
+                insert(pos + i, &mut head, &mut prev);
+            }
+            pos += best_len;
+        }
+        else {
+            mask |= 1;
+            chunks.push(data[pos]);
+            insert(pos, &mut head, &mut prev);
+            pos += 1;
+        }
+
+        mask_bits += 1;
+        if mask_bits == 32 {
+            masks.push(mask);
+            mask = 0;
+            mask_bits = 0;
+        }
+    }
+    if mask_bits != 0 {
+        masks.push(mask << (32 - mask_bits));
+    }
+
+    let link_off = HEADER_LENGTH + masks.len() * 4;
+    let chunk_off = link_off + links.len();
+
+    let mut output = Vec::with_capacity(chunk_off + chunks.len());
+    output.extend_from_slice(MAGIC);
+    output.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    output.extend_from_slice(&(link_off as u32).to_be_bytes());
+    output.extend_from_slice(&(chunk_off as u32).to_be_bytes());
+    for mask in masks {
+        output.extend_from_slice(&mask.to_be_bytes());
+    }
+    output.extend_from_slice(&links);
+    output.extend_from_slice(&chunks);
+
+    return output;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compress_round_trip() {
+        // Literals, short and long matches, overlapping runs and matches further back than the window
+        let mut data: Vec<u8> = (0..0x3000u32).map(|i| (i.wrapping_mul(0x9E3779B1) >> 24) as u8).collect();
+        data.extend_from_slice(&[0xAB; 0x400]);
+        data.extend_from_within(0x100..0x180);
+        data.extend_from_within(0x10..0x15);
+        data.extend_from_slice(b"Yay0Yay0Yay0Yay0");
+
+        let compressed = compress(&data);
+        assert!(is_yay0(&compressed));
+        assert!(compressed.len() < data.len());
+        assert_eq!(decompressed_size(&compressed).unwrap(), data.len());
+        assert_eq!(decompress(&compressed).unwrap(), data);
+    }
+
+    #[test]
+    fn compress_round_trip_short() {
+        for data in [&b""[..], b"a", b"ab", b"abc", b"aaaa"] {
+            assert_eq!(decompress(&compress(data)).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn truncated_data() {
+        let compressed = compress(&[0x11; 0x100]);
+        assert!(decompress(&compressed[..compressed.len() - 1]).is_err());
+    }
+}